@@ -0,0 +1,34 @@
+use crate::common::logger::{log_directives, set_log_level};
+use anyhow::{anyhow, Result};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tracing::{info, warn};
+
+/// Reads admin commands from stdin, one per line:
+/// - `loglevel list`
+/// - `loglevel <target> <level>` (target `default` sets the global level)
+pub fn spawn_admin_console() {
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Err(e) = handle_command(line) {
+                warn!("Admin command {line:?} failed: {e}");
+            }
+        }
+    });
+}
+
+fn handle_command(line: &str) -> Result<()> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    match parts.as_slice() {
+        ["loglevel", "list"] => {
+            info!("Log filter directives: {}", log_directives().join(","));
+            Ok(())
+        }
+        ["loglevel", target, level] => set_log_level(target, level),
+        _ => Err(anyhow!("unknown command (try `loglevel list` or `loglevel <target> <level>`)")),
+    }
+}
@@ -0,0 +1,297 @@
+use crate::common::utils::{env_var_opt, parse_commitment, parse_pubkey_list};
+use anyhow::{anyhow, Result};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// Values from the config file, keyed by env var name. `env_var_opt` falls back to these,
+/// so every setting can live in the file and the environment still wins.
+static FILE_VALUES: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// Settings the bot cannot start without. Targets are checked separately since either
+/// `TARGET_PUBKEYS` or `TARGET_PUBKEY` will do.
+const REQUIRED: &[&str] = &["RPC_ENDPOINT", "RPC_WEBSOCKET_ENDPOINT", "PRIVATE_KEY"];
+
+/// The value `key` has in the config file, if it has one.
+pub fn file_value(key: &str) -> Option<String> {
+    FILE_VALUES.get()?.get(key).cloned()
+}
+
+/// Startup configuration: the environment merged over the optional TOML file at
+/// `CONFIG_FILE` (default `./config.toml`).
+///
+/// The file is flat and its keys are the env var names, in either case:
+///
+/// ```toml
+/// rpc_endpoint = "https://mainnet.helius-rpc.com/?api-key=..."
+/// target_pubkeys = ["<wallet>", "<wallet>"]
+/// max_buy_sol = 0.05
+/// slippage_bps = 300
+/// mirror_sells = true
+/// ```
+///
+/// Arrays become comma-separated lists. The settings below are validated when the
+/// config loads; the rest are still read through the env helpers, which see the file too.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The file that was loaded, if any.
+    pub source: Option<PathBuf>,
+    pub rpc_endpoint: String,
+    pub rpc_websocket_endpoint: String,
+    /// `TARGET_PUBKEYS` (or the single `TARGET_PUBKEY`): the wallets to mirror.
+    pub targets: Vec<Pubkey>,
+    pub slippage_bps: u16,
+    pub max_buy_sol: f64,
+    /// `MIN_BUY_SOL`: buys sized below this are skipped (0 = no floor).
+    pub min_buy_sol: f64,
+    /// `MAX_SOL_PER_MINT`: `None` = unlimited exposure per mint.
+    pub max_sol_per_mint: Option<f64>,
+    /// `MAX_DAILY_SOL` (or the older `DAILY_SPEND_LIMIT_SOL`): rolling 24h spend limit
+    /// (0 = unlimited).
+    pub max_daily_sol: f64,
+    /// `MIN_SOL_RESERVE_SOL`: SOL buys always leave in the wallet.
+    pub min_sol_reserve_sol: f64,
+    pub mirror_buys_only: bool,
+    pub mirror_sells: bool,
+    /// `CONFIRM_TIMEOUT_SECS`: how long a sent swap is waited on.
+    pub confirm_timeout_secs: u64,
+    /// `RPC_COMMITMENT` (default `processed`): commitment of RPC reads and sends.
+    pub rpc_commitment: CommitmentConfig,
+    /// `RPC_CONFIRM_COMMITMENT` (default `confirmed`): what a sent swap must reach before
+    /// it counts as confirmed. A processed transaction can still be dropped with its fork.
+    pub confirm_commitment: CommitmentConfig,
+}
+
+impl Config {
+    /// Loads the file (once per process), then checks that every required setting is
+    /// present in the file or the environment, reporting all missing ones in one error.
+    pub fn load() -> Result<Self> {
+        let explicit = env_var_opt("CONFIG_FILE").filter(|p| !p.trim().is_empty());
+        let path = PathBuf::from(explicit.as_deref().map(str::trim).unwrap_or("config.toml"));
+        let source = match fs::read_to_string(&path) {
+            Ok(raw) => {
+                let values = parse_file(&raw).map_err(|e| anyhow!("Invalid config file {}: {e}", path.display()))?;
+                let _ = FILE_VALUES.set(values);
+                Some(path.clone())
+            }
+            // Only a file that was asked for has to exist
+            Err(e) if e.kind() == io::ErrorKind::NotFound && explicit.is_none() => None,
+            Err(e) => return Err(anyhow!("Failed to read config file {}: {e}", path.display())),
+        };
+
+        Self::resolve(source, &path, env_var_opt)
+    }
+
+    /// Builds the config from `get` (env var name -> value), collecting every missing or
+    /// invalid setting so they are all reported in one error.
+    fn resolve(source: Option<PathBuf>, path: &Path, get: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let mut settings = Settings { get: &get, invalid: Vec::new() };
+
+        let mut missing: Vec<&str> = REQUIRED.iter().copied().filter(|key| settings.value(key).is_none()).collect();
+        let targets = match ["TARGET_PUBKEYS", "TARGET_PUBKEY"].iter().find_map(|key| Some((*key, settings.value(key)?))) {
+            Some((key, raw)) => match parse_pubkey_list(key, &raw) {
+                Ok(targets) if targets.is_empty() => settings.invalid(format!("{key} does not contain any pubkey")),
+                Ok(targets) => targets,
+                Err(e) => settings.invalid(e),
+            },
+            None => {
+                missing.push("TARGET_PUBKEYS (or TARGET_PUBKEY)");
+                Vec::new()
+            }
+        };
+
+        let commitment = |settings: &mut Settings, key: &str, default: &str| {
+            let value = settings.value(key).unwrap_or_else(|| default.to_string());
+            parse_commitment(key, &value).unwrap_or_else(|e| settings.invalid(e))
+        };
+        let config = Self {
+            source,
+            rpc_endpoint: settings.value("RPC_ENDPOINT").unwrap_or_default(),
+            rpc_websocket_endpoint: settings.value("RPC_WEBSOCKET_ENDPOINT").unwrap_or_default(),
+            targets,
+            slippage_bps: settings.parse("SLIPPAGE_BPS", 500),
+            max_buy_sol: settings.sol("MAX_BUY_SOL", 0.02),
+            min_buy_sol: settings.sol("MIN_BUY_SOL", 0.0),
+            max_sol_per_mint: settings.value("MAX_SOL_PER_MINT").map(|_| settings.sol("MAX_SOL_PER_MINT", 0.0)),
+            max_daily_sol: match settings.value("MAX_DAILY_SOL") {
+                Some(_) => settings.sol("MAX_DAILY_SOL", 0.0),
+                None => settings.sol("DAILY_SPEND_LIMIT_SOL", 0.0),
+            },
+            min_sol_reserve_sol: settings.sol("MIN_SOL_RESERVE_SOL", 0.01),
+            mirror_buys_only: settings.flag("MIRROR_BUYS_ONLY", true),
+            mirror_sells: settings.flag("MIRROR_SELLS", false),
+            confirm_timeout_secs: settings.parse("CONFIRM_TIMEOUT_SECS", 30),
+            rpc_commitment: commitment(&mut settings, "RPC_COMMITMENT", "processed"),
+            confirm_commitment: commitment(&mut settings, "RPC_CONFIRM_COMMITMENT", "confirmed"),
+        };
+
+        if config.slippage_bps > 10_000 {
+            settings.invalid::<()>(format!("SLIPPAGE_BPS={}: must be at most 10000", config.slippage_bps));
+        }
+
+        let mut problems = Vec::new();
+        if !missing.is_empty() {
+            problems.push(format!("missing {}", missing.join(", ")));
+        }
+        problems.extend(settings.invalid);
+        if !problems.is_empty() {
+            return Err(anyhow!(
+                "Invalid config: {} (set in the environment or in {})",
+                problems.join("; "),
+                path.display()
+            ));
+        }
+        Ok(config)
+    }
+}
+
+/// Reads settings for `Config::resolve`, recording what is wrong with them instead of
+/// stopping at the first problem.
+struct Settings<'a> {
+    get: &'a dyn Fn(&str) -> Option<String>,
+    invalid: Vec<String>,
+}
+
+impl Settings<'_> {
+    /// The trimmed value of `key`; blank counts as unset.
+    fn value(&self, key: &str) -> Option<String> {
+        (self.get)(key).map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+    }
+
+    /// Records a problem; returns a placeholder so resolving can carry on.
+    fn invalid<T: Default>(&mut self, problem: impl Display) -> T {
+        self.invalid.push(problem.to_string());
+        T::default()
+    }
+
+    /// `key` parsed as a `T`, or `default` when it is unset.
+    fn parse<T: FromStr>(&mut self, key: &str, default: T) -> T
+    where
+        T::Err: Display,
+    {
+        match self.value(key) {
+            Some(raw) => raw.parse().unwrap_or_else(|e| {
+                self.invalid.push(format!("{key}={raw}: {e}"));
+                default
+            }),
+            None => default,
+        }
+    }
+
+    /// A SOL amount: the range `sol_to_lamports` accepts.
+    fn sol(&mut self, key: &str, default: f64) -> f64 {
+        let sol = self.parse(key, default);
+        if !(0.0..=1000.0).contains(&sol) {
+            self.invalid.push(format!("{key}={sol}: must be between 0 and 1000 SOL"));
+            return default;
+        }
+        sol
+    }
+
+    /// A boolean in any of the spellings `env_bool` accepts.
+    fn flag(&mut self, key: &str, default: bool) -> bool {
+        match self.value(key).map(|v| v.to_lowercase()).as_deref() {
+            Some("true" | "1" | "yes" | "y") => true,
+            Some("false" | "0" | "no" | "n") => false,
+            Some(other) => {
+                self.invalid.push(format!("{key}={other}: expected true or false"));
+                default
+            }
+            None => default,
+        }
+    }
+}
+
+/// Flattens the file into env var name -> value.
+fn parse_file(raw: &str) -> Result<HashMap<String, String>> {
+    let table: toml::Table = raw.parse()?;
+    let mut values = HashMap::new();
+    for (key, value) in table {
+        let value = match value {
+            toml::Value::Array(items) => items
+                .iter()
+                .map(scalar)
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| anyhow!("{key}: arrays may only hold strings, numbers or booleans"))?
+                .join(","),
+            other => scalar(&other).ok_or_else(|| anyhow!("{key}: nested tables are not supported"))?,
+        };
+        values.insert(key.to_uppercase(), value);
+    }
+    Ok(values)
+}
+
+fn scalar(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(s) => Some(s.clone()),
+        toml::Value::Integer(i) => Some(i.to_string()),
+        toml::Value::Float(f) => Some(f.to_string()),
+        toml::Value::Boolean(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(vars: &[(&str, &str)]) -> Result<Config> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        Config::resolve(None, Path::new("config.toml"), |key| vars.get(key).cloned())
+    }
+
+    #[test]
+    fn settings_are_read_and_defaulted() {
+        let target = Pubkey::new_unique();
+        let config = resolve(&[
+            ("RPC_ENDPOINT", "https://rpc.example"),
+            ("RPC_WEBSOCKET_ENDPOINT", "wss://rpc.example"),
+            ("PRIVATE_KEY", "key"),
+            ("TARGET_PUBKEY", &target.to_string()),
+            ("MAX_BUY_SOL", "0.5"),
+            ("MIRROR_SELLS", "yes"),
+            ("DAILY_SPEND_LIMIT_SOL", "3"),
+        ])
+        .unwrap();
+        assert_eq!(config.targets, vec![target]);
+        assert_eq!(config.max_buy_sol, 0.5);
+        assert!(config.mirror_sells && config.mirror_buys_only);
+        assert_eq!(config.max_daily_sol, 3.0);
+        assert_eq!(config.max_sol_per_mint, None);
+        assert_eq!((config.slippage_bps, config.confirm_timeout_secs), (500, 30));
+    }
+
+    #[test]
+    fn every_missing_and_invalid_setting_is_reported_at_once() {
+        let err = resolve(&[
+            ("RPC_WEBSOCKET_ENDPOINT", "wss://rpc.example"),
+            ("TARGET_PUBKEYS", "not-a-pubkey"),
+            ("SLIPPAGE_BPS", "20000"),
+            ("MAX_BUY_SOL", "-1"),
+            ("MIRROR_SELLS", "maybe"),
+            ("CONFIRM_TIMEOUT_SECS", "soon"),
+            ("RPC_COMMITMENT", "eventually"),
+        ])
+        .unwrap_err()
+        .to_string();
+        for problem in [
+            "missing RPC_ENDPOINT, PRIVATE_KEY",
+            "Invalid pubkey in TARGET_PUBKEYS",
+            "SLIPPAGE_BPS=20000",
+            "MAX_BUY_SOL=-1",
+            "MIRROR_SELLS=maybe",
+            "CONFIRM_TIMEOUT_SECS=soon",
+            "RPC_COMMITMENT 'eventually'",
+        ] {
+            assert!(err.contains(problem), "{problem} not in: {err}");
+        }
+
+        let err = resolve(&[]).unwrap_err().to_string();
+        assert!(err.contains("TARGET_PUBKEYS (or TARGET_PUBKEY)"), "{err}");
+    }
+}
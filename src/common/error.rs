@@ -0,0 +1,35 @@
+use thiserror::Error;
+
+/// Failure kinds surfaced by the DEX, intent and WS layers, so the engine can pick a
+/// retry policy per kind. Engine code still works in `anyhow` and converts with `?`.
+#[derive(Debug, Error)]
+pub enum BotError {
+    #[error("Jupiter quote failed: {0}")]
+    QuoteFailed(String),
+    #[error("Jupiter swap build failed: {0}")]
+    SwapBuildFailed(String),
+    #[error("Swap send failed: {0}")]
+    SendFailed(String),
+    #[error("Swap simulation failed: {0}")]
+    SimulationFailed(String),
+    #[error("WS disconnected: {0}")]
+    WsDisconnected(String),
+    #[error("Intent parse error: {0}")]
+    IntentParse(String),
+    #[error("Environment variable {0} is not set")]
+    ConfigMissing(String),
+}
+
+impl BotError {
+    /// Whether trying the same operation again may succeed. Quote, swap-build and WS
+    /// failures are usually transient; a rejected send or simulation, an unparsable intent
+    /// or a missing setting will fail the same way again.
+    pub fn is_retriable(&self) -> bool {
+        matches!(
+            self,
+            BotError::QuoteFailed(_) | BotError::SwapBuildFailed(_) | BotError::WsDisconnected(_)
+        )
+    }
+}
+
+pub type BotResult<T> = std::result::Result<T, BotError>;
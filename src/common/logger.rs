@@ -1,15 +1,180 @@
-use anyhow::Result;
-use tracing_subscriber::{fmt, EnvFilter};
+use crate::common::utils::env_var_opt;
+use anyhow::{anyhow, Result};
+use std::fs;
+use std::sync::{Mutex, OnceLock};
+use tracing::info;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, reload, EnvFilter, Registry};
+
+/// Live filter state: the reload handle plus the directives it was built from.
+struct LogControl {
+    handle: reload::Handle<EnvFilter, Registry>,
+    directives: Mutex<Vec<String>>,
+    /// `LOG_FILTER_FILE`: runtime changes are written here and restored on start.
+    persist_path: Option<String>,
+}
+
+static LOG_CONTROL: OnceLock<LogControl> = OnceLock::new();
 
 pub fn init_tracing() -> Result<()> {
-    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let persist_path = env_var_opt("LOG_FILTER_FILE").filter(|p| !p.trim().is_empty());
+    let persisted = persist_path
+        .as_deref()
+        .and_then(|p| fs::read_to_string(p).ok())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty());
+    let initial = persisted
+        .clone()
+        .or_else(|| env_var_opt("RUST_LOG"))
+        .unwrap_or_else(|| "info".to_string());
+
+    let filter = EnvFilter::try_new(&initial).unwrap_or_else(|_| EnvFilter::new("info"));
+    let (filter, handle) = reload::Layer::new(filter);
 
-    fmt()
-        .with_env_filter(filter)
-        .with_target(true)
-        .with_level(true)
-        .compact()
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().with_target(true).with_level(true).compact())
         .init();
 
+    let _ = LOG_CONTROL.set(LogControl {
+        handle,
+        directives: Mutex::new(split_directives(&initial)),
+        persist_path: persist_path.clone(),
+    });
+    if persisted.is_some() {
+        info!(
+            "Log filter restored from {}: {initial}",
+            persist_path.unwrap_or_default()
+        );
+    }
+
     Ok(())
 }
+
+/// Current filter directives, e.g. `["info", "ammalgam_assistant::helius=debug"]`.
+pub fn log_directives() -> Vec<String> {
+    LOG_CONTROL
+        .get()
+        .map(|c| c.directives.lock().unwrap_or_else(|e| e.into_inner()).clone())
+        .unwrap_or_default()
+}
+
+/// Sets `target` to `level` (`default` sets the global level) and reloads the filter.
+pub fn set_log_level(target: &str, level: &str) -> Result<()> {
+    LOG_CONTROL
+        .get()
+        .ok_or_else(|| anyhow!("Logging is not initialized"))?
+        .set_level(target, level)
+}
+
+impl LogControl {
+    fn set_level(&self, target: &str, level: &str) -> Result<()> {
+        let level: LevelFilter = level
+            .parse()
+            .map_err(|_| anyhow!("Invalid log level {level:?} (use off, error, warn, info, debug or trace)"))?;
+        let level = level.to_string().to_lowercase();
+
+        let mut directives = self.directives.lock().unwrap_or_else(|e| e.into_inner());
+        let key = (target != "default").then_some(target);
+        let mut next: Vec<String> = directives
+            .iter()
+            .filter(|d| directive_target(d) != key)
+            .cloned()
+            .collect();
+        next.push(if target == "default" { level } else { format!("{target}={level}") });
+
+        let filter = EnvFilter::try_new(next.join(",")).map_err(|e| anyhow!("Invalid log filter: {e}"))?;
+        self.handle
+            .reload(filter)
+            .map_err(|e| anyhow!("Failed to reload log filter: {e}"))?;
+        *directives = next;
+
+        let joined = directives.join(",");
+        info!("Log filter changed to {joined}");
+        if let Some(path) = &self.persist_path {
+            fs::write(path, &joined).map_err(|e| anyhow!("Failed to persist log filter to {path}: {e}"))?;
+        }
+        Ok(())
+    }
+}
+
+fn split_directives(raw: &str) -> Vec<String> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Target part of a `target=level` directive; `None` for a bare global level.
+fn directive_target(directive: &str) -> Option<&str> {
+    directive.split_once('=').map(|(t, _)| t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::Arc;
+    use tracing::debug;
+
+    /// Collects everything the fmt layer writes.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Capture {
+        fn take(&self) -> String {
+            String::from_utf8(std::mem::take(&mut *self.0.lock().unwrap())).unwrap()
+        }
+    }
+
+    #[test]
+    fn changing_a_level_changes_what_is_logged() {
+        let capture = Capture::default();
+        let (filter, handle) = reload::Layer::new(EnvFilter::new("info"));
+        let writer = capture.clone();
+        let subscriber = tracing_subscriber::registry()
+            .with(filter)
+            .with(fmt::layer().with_writer(move || writer.clone()).with_ansi(false));
+        let control = LogControl {
+            handle,
+            directives: Mutex::new(split_directives("info")),
+            persist_path: None,
+        };
+
+        tracing::subscriber::with_default(subscriber, || {
+            debug!(target: "ammalgam_assistant::helius", "ws frame 1");
+            assert!(!capture.take().contains("ws frame"));
+
+            control.set_level("ammalgam_assistant::helius", "debug").unwrap();
+            debug!(target: "ammalgam_assistant::helius", "ws frame 2");
+            debug!(target: "ammalgam_assistant::engine", "engine detail");
+            let logged = capture.take();
+            assert!(logged.contains("ws frame 2"), "{logged}");
+            assert!(!logged.contains("engine detail"), "{logged}");
+
+            control.set_level("ammalgam_assistant::helius", "warn").unwrap();
+            control.set_level("default", "debug").unwrap();
+            debug!(target: "ammalgam_assistant::helius", "ws frame 3");
+            debug!(target: "ammalgam_assistant::engine", "engine detail");
+            let logged = capture.take();
+            assert!(!logged.contains("ws frame 3"), "{logged}");
+            assert!(logged.contains("engine detail"), "{logged}");
+        });
+
+        assert_eq!(*control.directives.lock().unwrap(), ["ammalgam_assistant::helius=warn", "debug"]);
+        assert!(control.set_level("default", "loud").is_err());
+    }
+}
@@ -0,0 +1,108 @@
+use crate::common::metrics::metrics;
+use crate::common::utils::env_u64;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// A map that holds at most `capacity` entries, evicting the least recently used one
+/// when full. Its size and hit rate are exported as `cache_entries`, `cache_hits` and
+/// `cache_misses` under its name.
+#[derive(Debug)]
+pub struct LruCache<K, V> {
+    name: &'static str,
+    capacity: usize,
+    /// Bumped on every use; an entry's last tick orders it in `order`.
+    tick: u64,
+    entries: HashMap<K, (V, u64)>,
+    order: BTreeMap<u64, K>,
+}
+
+impl<K: Eq + Hash + Clone, V> LruCache<K, V> {
+    /// Sized by `<NAME>_CACHE_SIZE`, `default` entries when unset. Every cache is built
+    /// here so each gets a setting and its metrics.
+    pub fn from_env(name: &'static str, default: usize) -> Self {
+        let key = format!("{}_CACHE_SIZE", name.to_uppercase());
+        Self::new(name, env_u64(&key, default as u64) as usize)
+    }
+
+    pub fn new(name: &'static str, capacity: usize) -> Self {
+        Self {
+            name,
+            capacity: capacity.max(1),
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The value for `key`, marking it used.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.get_mut(key).map(|v| &*v)
+    }
+
+    /// The value for `key`, marking it used.
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.tick += 1;
+        let Some((value, last)) = self.entries.get_mut(key) else {
+            metrics().cache_misses.with_label_values(&[self.name]).inc();
+            return None;
+        };
+        metrics().cache_hits.with_label_values(&[self.name]).inc();
+        self.order.remove(last);
+        self.order.insert(self.tick, key.clone());
+        *last = self.tick;
+        Some(value)
+    }
+
+    /// Inserts or replaces `key`, returning the key evicted to make room, if any.
+    pub fn insert(&mut self, key: K, value: V) -> Option<K> {
+        self.tick += 1;
+        if let Some((_, last)) = self.entries.insert(key.clone(), (value, self.tick)) {
+            self.order.remove(&last);
+        }
+        self.order.insert(self.tick, key);
+        let evicted = if self.entries.len() > self.capacity {
+            self.order.pop_first().map(|(_, oldest)| {
+                self.entries.remove(&oldest);
+                oldest
+            })
+        } else {
+            None
+        };
+        metrics().cache_entries.with_label_values(&[self.name]).set(self.entries.len() as i64);
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn over_capacity_evicts_the_least_recently_used_entry() {
+        let mut cache = LruCache::new("test", 2);
+        assert_eq!(cache.insert("a", 1), None);
+        assert_eq!(cache.insert("b", 2), None);
+        // Reading "a" leaves "b" as the least recently used
+        assert_eq!(cache.get(&"a"), Some(&1));
+        assert_eq!(cache.insert("c", 3), Some("b"));
+        assert_eq!(cache.get(&"b"), None);
+        // Replacing a key counts as a use and never evicts
+        assert_eq!(cache.insert("a", 10), None);
+        assert_eq!(cache.insert("d", 4), Some("c"));
+        assert_eq!(cache.get(&"a"), Some(&10));
+        assert_eq!(cache.get(&"d"), Some(&4));
+        assert_eq!(cache.len(), 2);
+
+        let entries = metrics().cache_entries.with_label_values(&["test"]).get();
+        assert_eq!(entries, 2);
+        assert!(metrics().cache_misses.with_label_values(&["test"]).get() >= 1);
+    }
+}
@@ -0,0 +1,206 @@
+use anyhow::{anyhow, Result};
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder};
+use std::sync::OnceLock;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Latency buckets in seconds, from a fast quote to a slow confirmation-bound send.
+const LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// Trade counters and latency histograms served on `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    /// Intents inferred from target transactions.
+    pub trades_detected: IntCounter,
+    /// Mirrored swaps submitted.
+    pub trades_sent: IntCounter,
+    pub trades_confirmed: IntCounter,
+    /// Mirrored swaps that failed to build, send or land.
+    pub trades_failed: IntCounter,
+    pub quote_latency: Histogram,
+    pub swap_build_latency: Histogram,
+    /// From the target notification arriving to our buy being sent.
+    pub detect_to_send_latency: Histogram,
+    /// Entries held by each bounded in-memory cache, by `cache` name.
+    pub cache_entries: IntGaugeVec,
+    pub cache_hits: IntCounterVec,
+    pub cache_misses: IntCounterVec,
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+/// The process-wide metrics, created on first use.
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(|| Metrics::new().expect("metric definitions are valid"))
+}
+
+impl Metrics {
+    fn new() -> Result<Self> {
+        let registry = Registry::new();
+        let counter = |name: &str, help: &str| -> Result<IntCounter> {
+            let c = IntCounter::new(name, help)?;
+            registry.register(Box::new(c.clone()))?;
+            Ok(c)
+        };
+        let histogram = |name: &str, help: &str| -> Result<Histogram> {
+            let h = Histogram::with_opts(HistogramOpts::new(name, help).buckets(LATENCY_BUCKETS.to_vec()))?;
+            registry.register(Box::new(h.clone()))?;
+            Ok(h)
+        };
+        let per_cache = |name: &str, help: &str| -> Result<IntCounterVec> {
+            let c = IntCounterVec::new(Opts::new(name, help), &["cache"])?;
+            registry.register(Box::new(c.clone()))?;
+            Ok(c)
+        };
+        let cache_entries = IntGaugeVec::new(Opts::new("cache_entries", "Entries held by a bounded in-memory cache"), &["cache"])?;
+        registry.register(Box::new(cache_entries.clone()))?;
+        Ok(Self {
+            trades_detected: counter("trades_detected", "Intents inferred from target transactions")?,
+            trades_sent: counter("trades_sent", "Mirrored swaps submitted")?,
+            trades_confirmed: counter("trades_confirmed", "Mirrored swaps confirmed on-chain")?,
+            trades_failed: counter("trades_failed", "Mirrored swaps that failed to build, send or land")?,
+            quote_latency: histogram("quote_latency", "Jupiter quote latency in seconds")?,
+            swap_build_latency: histogram("swap_build_latency", "Jupiter swap tx build latency in seconds")?,
+            detect_to_send_latency: histogram(
+                "detect_to_send_latency",
+                "Seconds from target notification to mirrored buy sent",
+            )?,
+            cache_entries,
+            cache_hits: per_cache("cache_hits", "Bounded in-memory cache lookups that found the key")?,
+            cache_misses: per_cache("cache_misses", "Bounded in-memory cache lookups that missed")?,
+            registry,
+        })
+    }
+
+    /// Prometheus text exposition of every metric.
+    pub fn render(&self) -> Result<String> {
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf)?;
+        Ok(String::from_utf8(buf)?)
+    }
+}
+
+/// Records `elapsed` in `histogram`.
+pub fn observe(histogram: &Histogram, elapsed: Duration) {
+    histogram.observe(elapsed.as_secs_f64());
+}
+
+/// Binds `0.0.0.0:port` and serves `/metrics` from a background task.
+pub async fn spawn_metrics_server(port: u16) -> Result<()> {
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .await
+        .map_err(|e| anyhow!("Failed to bind METRICS_PORT {port}: {e}"))?;
+    info!("Serving metrics on http://0.0.0.0:{port}/metrics");
+    tokio::spawn(async move {
+        loop {
+            let mut socket = match listener.accept().await {
+                Ok((socket, _)) => socket,
+                Err(e) => {
+                    warn!("Metrics accept failed: {e}");
+                    continue;
+                }
+            };
+            tokio::spawn(async move {
+                if let Err(e) = serve(&mut socket).await {
+                    debug!("Metrics request failed: {e}");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Pushes the metrics to the Prometheus Pushgateway at `url` every `interval`, for
+/// deployments that cannot be scraped. Failed pushes are logged and retried on the next tick.
+pub fn spawn_pushgateway(http: reqwest::Client, url: String, job: String, interval: Duration) {
+    info!("Pushing metrics to {url} as job {job} every {}s", interval.as_secs());
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = push(&http, &url, &job).await {
+                warn!("Metrics push to {url} failed: {e}");
+            }
+        }
+    });
+}
+
+/// Replaces `job`'s metrics on the Pushgateway with the current ones (a PUT of the same
+/// text `/metrics` serves).
+pub async fn push(http: &reqwest::Client, url: &str, job: &str) -> Result<()> {
+    let endpoint = format!("{}/metrics/job/{job}", url.trim_end_matches('/'));
+    let response = http
+        .put(&endpoint)
+        .header(reqwest::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(metrics().render()?)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("Pushgateway answered {}", response.status()));
+    }
+    Ok(())
+}
+
+/// Answers one HTTP request: the metrics for `GET /metrics`, 404 for anything else.
+async fn serve(socket: &mut TcpStream) -> Result<()> {
+    let mut buf = [0u8; 2048];
+    let n = socket.read(&mut buf).await?;
+    let head = String::from_utf8_lossy(&buf[..n]);
+    let path = head.split_whitespace().nth(1).unwrap_or_default();
+    let (status, body) = match path.split('?').next() {
+        Some("/metrics") => ("200 OK", metrics().render()?),
+        _ => ("404 Not Found", String::new()),
+    };
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    socket.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn push_sends_every_metric_family_to_the_job() {
+        let gateway = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/metrics/job/ammalgam"))
+            .and(header("content-type", "text/plain; version=0.0.4"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&gateway)
+            .await;
+        metrics().trades_detected.inc();
+
+        push(&reqwest::Client::new(), &format!("{}/", gateway.uri()), "ammalgam").await.unwrap();
+
+        let requests = gateway.received_requests().await.unwrap();
+        let body = String::from_utf8_lossy(&requests[0].body);
+        for family in [
+            "trades_detected counter",
+            "trades_sent counter",
+            "trades_confirmed counter",
+            "trades_failed counter",
+            "quote_latency histogram",
+            "swap_build_latency histogram",
+            "detect_to_send_latency histogram",
+        ] {
+            assert!(body.contains(&format!("# TYPE {family}")), "{family} missing from:\n{body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejected_push_is_an_error() {
+        let gateway = MockServer::start().await;
+        Mock::given(method("PUT")).respond_with(ResponseTemplate::new(500)).mount(&gateway).await;
+        let err = push(&reqwest::Client::new(), &gateway.uri(), "ammalgam").await.unwrap_err();
+        assert!(err.to_string().contains("500"), "{err}");
+    }
+}
@@ -1 +1,8 @@
+pub mod admin;
+pub mod config;
+pub mod error;
 pub mod events;
+pub mod lru;
+pub mod metrics;
+pub mod notify;
+pub mod proxy;
@@ -0,0 +1,100 @@
+use crate::common::utils::env_var_opt;
+use anyhow::{anyhow, Result};
+use reqwest::Client;
+use serde_json::json;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+/// Messages waiting for Telegram; beyond this new ones are dropped.
+const QUEUE_LEN: usize = 100;
+
+/// When trade notifications go out (`NOTIFY_ON`). Failures are reported either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifyOn {
+    /// When the swap is sent, and again once it confirms.
+    Send,
+    /// Only once it confirms, with the amounts read back from the landed transaction.
+    Confirm,
+}
+
+impl FromStr for NotifyOn {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "send" => Ok(NotifyOn::Send),
+            "confirm" => Ok(NotifyOn::Confirm),
+            other => Err(anyhow!("Unknown NOTIFY_ON '{other}' (expected send|confirm)")),
+        }
+    }
+}
+
+/// A channel trade events are reported to besides the log. `notify` must return at once:
+/// it is called from the trade loop.
+pub trait Notifier: Send + Sync {
+    fn notify(&self, text: &str);
+}
+
+/// Used when no channel is configured.
+pub struct NoopNotifier;
+
+impl Notifier for NoopNotifier {
+    fn notify(&self, _text: &str) {}
+}
+
+/// Posts to a chat through the Telegram Bot API. Messages are queued and sent in order by
+/// a background task; while Telegram is slow or down and the queue is full, new messages
+/// are dropped instead of waited for.
+pub struct TelegramNotifier {
+    queue: mpsc::Sender<String>,
+}
+
+impl TelegramNotifier {
+    pub fn spawn(http: Client, token: String, chat_id: String) -> Self {
+        let (queue, mut rx) = mpsc::channel::<String>(QUEUE_LEN);
+        tokio::spawn(async move {
+            let url = format!("https://api.telegram.org/bot{token}/sendMessage");
+            while let Some(text) = rx.recv().await {
+                let body = json!({ "chat_id": chat_id, "text": text, "disable_web_page_preview": true });
+                let sent = http
+                    .post(&url)
+                    .json(&body)
+                    .send()
+                    .await
+                    .and_then(|r| r.error_for_status());
+                // The URL carries the bot token, so it stays out of the log
+                if let Err(e) = sent {
+                    warn!("Telegram notification failed: {}", e.without_url());
+                }
+            }
+        });
+        Self { queue }
+    }
+}
+
+impl Notifier for TelegramNotifier {
+    fn notify(&self, text: &str) {
+        if self.queue.try_send(text.to_string()).is_err() {
+            debug!("Telegram queue full; notification dropped: {text}");
+        }
+    }
+}
+
+/// Telegram when `TELEGRAM_BOT_TOKEN` and `TELEGRAM_CHAT_ID` are both set, otherwise a no-op.
+pub fn notifier_from_env(http: &Client) -> Arc<dyn Notifier> {
+    let token = env_var_opt("TELEGRAM_BOT_TOKEN").filter(|v| !v.trim().is_empty());
+    let chat_id = env_var_opt("TELEGRAM_CHAT_ID").filter(|v| !v.trim().is_empty());
+    match (token, chat_id) {
+        (Some(token), Some(chat_id)) => {
+            info!("Telegram notifications on for chat {chat_id}");
+            Arc::new(TelegramNotifier::spawn(http.clone(), token.trim().to_string(), chat_id.trim().to_string()))
+        }
+        (None, None) => Arc::new(NoopNotifier),
+        _ => {
+            warn!("Telegram notifications need both TELEGRAM_BOT_TOKEN and TELEGRAM_CHAT_ID; disabled");
+            Arc::new(NoopNotifier)
+        }
+    }
+}
@@ -1,8 +1,17 @@
+use crate::common::config::{file_value, Config};
+use crate::common::error::{BotError, BotResult};
 use anyhow::{anyhow, Result};
+use solana_account_decoder::UiAccountData;
 use solana_client::rpc_client::RpcClient;
 use solana_client::nonblocking::rpc_client::RpcClient as AsyncRpcClient;
-use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Keypair};
-use std::{env, str::FromStr, sync::Arc};
+use solana_client::rpc_request::TokenAccountsFilter;
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Keypair, signer::Signer};
+use std::{collections::HashMap, env, str::FromStr, sync::Arc};
+
+/// SPL Token program
+pub const TOKEN_PROGRAM_ID: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+/// SPL Token-2022 program (extensions such as transfer fees)
+pub const TOKEN_2022_PROGRAM_ID: &str = "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb";
 
 #[derive(Clone)]
 pub struct AppState {
@@ -10,14 +19,20 @@ pub struct AppState {
     pub rpc_nonblocking_client: Arc<AsyncRpcClient>,
     pub wallet: Arc<Keypair>,
     pub wallet_pubkey: Pubkey,
+    /// `FEE_PAYER_KEY`: pays transaction fees instead of `wallet`, which still holds
+    /// and swaps the assets.
+    pub fee_payer: Option<Arc<Keypair>>,
+    /// `RPC_CONFIRM_COMMITMENT`: level a sent swap must reach to count as confirmed.
+    pub confirm_commitment: CommitmentConfig,
 }
 
-pub fn env_var(key: &str) -> Result<String> {
-    env::var(key).map_err(|_| anyhow!("Environment variable {key} is not set"))
+pub fn env_var(key: &str) -> BotResult<String> {
+    env_var_opt(key).ok_or_else(|| BotError::ConfigMissing(key.to_string()))
 }
 
+/// The environment first, then the config file (`CONFIG_FILE`).
 pub fn env_var_opt(key: &str) -> Option<String> {
-    env::var(key).ok()
+    env::var(key).ok().or_else(|| file_value(key))
 }
 
 pub fn env_bool(key: &str, default: bool) -> bool {
@@ -51,20 +66,90 @@ pub fn parse_pubkey(key: &str, value: &str) -> Result<Pubkey> {
     Pubkey::from_str(value).map_err(|e| anyhow!("Invalid pubkey in {key}: {e}"))
 }
 
-pub fn create_rpc_client() -> Result<Arc<RpcClient>> {
+/// Parses a comma-separated pubkey list from env var `key`, dropping blanks and duplicates.
+pub fn parse_pubkey_list(key: &str, raw: &str) -> Result<Vec<Pubkey>> {
+    let mut keys = Vec::new();
+    for part in raw.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let pubkey = parse_pubkey(key, part)?;
+        if !keys.contains(&pubkey) {
+            keys.push(pubkey);
+        }
+    }
+    Ok(keys)
+}
+
+/// Parses a commitment level (`processed`, `confirmed` or `finalized`) from env var `key`.
+pub fn parse_commitment(key: &str, value: &str) -> Result<CommitmentConfig> {
+    match value.trim().to_lowercase().as_str() {
+        "processed" => Ok(CommitmentConfig::processed()),
+        "confirmed" => Ok(CommitmentConfig::confirmed()),
+        "finalized" => Ok(CommitmentConfig::finalized()),
+        other => Err(anyhow!("Unknown {key} '{other}' (expected processed|confirmed|finalized)")),
+    }
+}
+
+pub fn create_rpc_client(commitment: CommitmentConfig) -> Result<Arc<RpcClient>> {
     let rpc_https = env_var("RPC_ENDPOINT")?;
-    Ok(Arc::new(RpcClient::new_with_commitment(
-        rpc_https,
-        CommitmentConfig::processed(),
-    )))
+    Ok(Arc::new(RpcClient::new_with_commitment(rpc_https, commitment)))
 }
 
-pub async fn create_nonblocking_rpc_client() -> Result<Arc<AsyncRpcClient>> {
+pub async fn create_nonblocking_rpc_client(commitment: CommitmentConfig) -> Result<Arc<AsyncRpcClient>> {
     let rpc_https = env_var("RPC_ENDPOINT")?;
-    Ok(Arc::new(AsyncRpcClient::new_with_commitment(
-        rpc_https,
-        CommitmentConfig::processed(),
-    )))
+    Ok(Arc::new(AsyncRpcClient::new_with_commitment(rpc_https, commitment)))
+}
+
+/// Total raw (base-unit) balance `owner` holds of `mint`, summed over all its token accounts.
+/// Returns 0 when the owner has no token account for that mint.
+pub async fn token_balance(rpc: &AsyncRpcClient, owner: &Pubkey, mint: &Pubkey) -> Result<u64> {
+    let accounts = rpc
+        .get_token_accounts_by_owner(owner, TokenAccountsFilter::Mint(*mint))
+        .await?;
+
+    let mut total: u64 = 0;
+    for keyed in accounts {
+        let UiAccountData::Json(parsed) = keyed.account.data else { continue; };
+        let amount = parsed
+            .parsed
+            .pointer("/info/tokenAmount/amount")
+            .and_then(|v| v.as_str())
+            .and_then(|v| v.parse::<u64>().ok())
+            .ok_or_else(|| anyhow!("Unexpected token account layout for {}", keyed.pubkey))?;
+        total = total.saturating_add(amount);
+    }
+    Ok(total)
+}
+
+/// Nonzero raw balances `owner` holds per mint across all its SPL Token and Token-2022
+/// accounts.
+pub async fn token_holdings(rpc: &AsyncRpcClient, owner: &Pubkey) -> Result<HashMap<Pubkey, u64>> {
+    let mut accounts = Vec::new();
+    for program in [TOKEN_PROGRAM_ID, TOKEN_2022_PROGRAM_ID] {
+        let program = Pubkey::from_str(program)?;
+        accounts.extend(
+            rpc.get_token_accounts_by_owner(owner, TokenAccountsFilter::ProgramId(program))
+                .await?,
+        );
+    }
+
+    let mut holdings: HashMap<Pubkey, u64> = HashMap::new();
+    for keyed in accounts {
+        let UiAccountData::Json(parsed) = keyed.account.data else { continue; };
+        let info = &parsed.parsed["info"];
+        let mint = info["mint"]
+            .as_str()
+            .and_then(|v| Pubkey::from_str(v).ok())
+            .ok_or_else(|| anyhow!("Unexpected token account layout for {}", keyed.pubkey))?;
+        let amount = info
+            .pointer("/tokenAmount/amount")
+            .and_then(|v| v.as_str())
+            .and_then(|v| v.parse::<u64>().ok())
+            .ok_or_else(|| anyhow!("Unexpected token account layout for {}", keyed.pubkey))?;
+        if amount > 0 {
+            let total = holdings.entry(mint).or_default();
+            *total = total.saturating_add(amount);
+        }
+    }
+    Ok(holdings)
 }
 
 /// PRIVATE_KEY can be either:
@@ -73,27 +158,47 @@ pub async fn create_nonblocking_rpc_client() -> Result<Arc<AsyncRpcClient>> {
 ///
 /// Supports both formats.
 pub fn import_wallet() -> Result<Arc<Keypair>> {
-    let raw = env_var("PRIVATE_KEY")?.trim().to_string();
+    parse_keypair(&env_var("PRIVATE_KEY")?)
+}
+
+/// Optional separate fee payer from `FEE_PAYER_KEY`, in the same formats as `PRIVATE_KEY`.
+pub fn import_fee_payer() -> Result<Option<Arc<Keypair>>> {
+    env_var_opt("FEE_PAYER_KEY")
+        .filter(|v| !v.trim().is_empty())
+        .map(|raw| parse_keypair(&raw).map_err(|e| anyhow!("Invalid FEE_PAYER_KEY: {e}")))
+        .transpose()
+}
+
+fn parse_keypair(raw: &str) -> Result<Arc<Keypair>> {
+    let raw = raw.trim();
 
     if raw.starts_with('[') {
-        let bytes: Vec<u8> = serde_json::from_str(&raw)?;
+        let bytes: Vec<u8> = serde_json::from_str(raw)?;
         let kp = Keypair::from_bytes(&bytes)?;
         return Ok(Arc::new(kp));
     }
 
-    Ok(Arc::new(Keypair::from_base58_string(&raw)))
+    Ok(Arc::new(Keypair::from_base58_string(raw)))
 }
 
-pub async fn build_state() -> Result<AppState> {
-    let rpc_client = create_rpc_client()?;
-    let rpc_nonblocking_client = create_nonblocking_rpc_client().await?;
+/// Clients use `config.rpc_commitment` for reads and sends; confirmation waits for
+/// `config.confirm_commitment`.
+pub async fn build_state(config: &Config) -> Result<AppState> {
+    let rpc_client = create_rpc_client(config.rpc_commitment)?;
+    let rpc_nonblocking_client = create_nonblocking_rpc_client(config.rpc_commitment).await?;
     let wallet = import_wallet()?;
     let wallet_pubkey = wallet.pubkey();
+    let fee_payer = import_fee_payer()?;
+    if fee_payer.as_ref().is_some_and(|fp| fp.pubkey() == wallet_pubkey) {
+        return Err(anyhow!("FEE_PAYER_KEY is the trading wallet itself; unset it instead"));
+    }
 
     Ok(AppState {
         rpc_client,
         rpc_nonblocking_client,
         wallet,
         wallet_pubkey,
+        fee_payer,
+        confirm_commitment: config.confirm_commitment,
     })
 }
@@ -0,0 +1,201 @@
+use crate::common::utils::{env_bool, env_u64, env_var_opt};
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
+use reqwest::Client;
+use serde_json::{json, Value};
+use solana_client::nonblocking::rpc_client::RpcClient as AsyncRpcClient;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    system_transaction,
+    transaction::VersionedTransaction,
+};
+use std::str::FromStr;
+use tokio::time::{sleep, Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// Jito mainnet tip accounts; one is picked per bundle.
+const TIP_ACCOUNTS: [&str; 8] = [
+    "96gYZGLnJYVFmbjzopPSU6QiEV5fGqZNyN9nmNhvrZU5",
+    "HFqU5x63VTqvQss8hp11i4wVV8bD44PvwucfZ2bU7gRe",
+    "Cw8CFyM9FkoMi7K7Crf6HNQqf4uEMzpKw6QNghXLvLkY",
+    "ADaUMid9yfUytqMBgopwjb2DTLSokTSzL1zt6iGPaS49",
+    "DfXygSm4jCyNCybVYYK6DwvWqjKee8pbDmJGcLWNDXjh",
+    "ADuUkR4vqLUMWXxW9gh6D6L8pMSawimctcNZ5pGwDcEt",
+    "DttWaMuVvTiduZRnguLF7jNxTgiMBZ1hyAumKUiL2KRL",
+    "3AVi9Tg9Uo68tJfuvoKvqKNWKkC5wPdSSdeBnizKZ6jT",
+];
+
+const DEFAULT_BLOCK_ENGINE_URL: &str = "https://mainnet.block-engine.jito.wtf";
+
+#[derive(Debug, Clone)]
+pub struct JitoConfig {
+    pub block_engine_url: String,
+    pub tip_lamports: u64,
+}
+
+impl JitoConfig {
+    /// `Some` only when `USE_JITO=true`.
+    pub fn from_env() -> Option<Self> {
+        if !env_bool("USE_JITO", false) {
+            return None;
+        }
+        Some(Self {
+            block_engine_url: env_var_opt("JITO_BLOCK_ENGINE_URL")
+                .unwrap_or_else(|| DEFAULT_BLOCK_ENGINE_URL.to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            tip_lamports: env_u64("JITO_TIP_LAMPORTS", 10_000),
+        })
+    }
+
+    fn bundles_url(&self) -> String {
+        format!("{}/api/v1/bundles", self.block_engine_url)
+    }
+}
+
+/// Final state of a submitted bundle as reported by the block engine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BundleOutcome {
+    Landed { slot: u64 },
+    /// Rejected or dropped by the block engine; safe to fall back to a normal send.
+    Rejected(String),
+    Timeout,
+}
+
+/// Submits `tx` (already signed) plus a tip transfer from `wallet` as a Jito bundle.
+/// The tip reuses `tx`'s blockhash so both expire together. Returns the bundle id.
+pub async fn send_via_jito(
+    http: &Client,
+    cfg: &JitoConfig,
+    wallet: &Keypair,
+    tx: &VersionedTransaction,
+) -> Result<String> {
+    let tip_account = pick_tip_account(tx.signatures.first())?;
+    let tip_tx = VersionedTransaction::from(system_transaction::transfer(
+        wallet,
+        &tip_account,
+        cfg.tip_lamports,
+        *tx.message.recent_blockhash(),
+    ));
+
+    let encoded = [tx, &tip_tx]
+        .iter()
+        .map(|t| bincode::serialize(t).map(|b| B64.encode(b)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let res = jito_rpc(
+        http,
+        cfg,
+        "sendBundle",
+        json!([encoded, { "encoding": "base64" }]),
+    )
+    .await?;
+    let bundle_id = res
+        .as_str()
+        .ok_or_else(|| anyhow!("Jito sendBundle returned no bundle id: {res}"))?
+        .to_string();
+
+    info!(
+        "Jito bundle {bundle_id} submitted (tip {} lamports -> {tip_account})",
+        cfg.tip_lamports
+    );
+    Ok(bundle_id)
+}
+
+/// Polls the block engine until the bundle lands, is rejected, or `timeout` elapses.
+pub async fn wait_for_bundle(
+    http: &Client,
+    cfg: &JitoConfig,
+    bundle_id: &str,
+    timeout: Duration,
+) -> Result<BundleOutcome> {
+    let start = Instant::now();
+    loop {
+        let res = jito_rpc(http, cfg, "getInflightBundleStatuses", json!([[bundle_id]])).await?;
+        let status = res.pointer("/value/0/status").and_then(|v| v.as_str());
+        debug!("Jito bundle {bundle_id} status: {status:?}");
+
+        match status {
+            Some("Landed") => {
+                let slot = res
+                    .pointer("/value/0/landed_slot")
+                    .and_then(|v| v.as_u64())
+                    .unwrap_or_default();
+                return Ok(BundleOutcome::Landed { slot });
+            }
+            Some(s @ ("Failed" | "Invalid")) => return Ok(BundleOutcome::Rejected(s.to_string())),
+            _ => {}
+        }
+
+        if start.elapsed() > timeout {
+            return Ok(BundleOutcome::Timeout);
+        }
+        sleep(Duration::from_millis(500)).await;
+    }
+}
+
+/// Sends `tx` as a Jito bundle and waits for it. If the bundle is rejected or the
+/// block engine errors, the same signed tx is sent through `rpc` instead; since the
+/// signature is unchanged it can still only land once.
+pub async fn send_with_jito_fallback(
+    http: &Client,
+    cfg: &JitoConfig,
+    rpc: &AsyncRpcClient,
+    wallet: &Keypair,
+    tx: &VersionedTransaction,
+) -> Result<Signature> {
+    let sig = *tx
+        .signatures
+        .first()
+        .ok_or_else(|| anyhow!("Transaction is not signed"))?;
+
+    let outcome = match send_via_jito(http, cfg, wallet, tx).await {
+        Ok(bundle_id) => wait_for_bundle(http, cfg, &bundle_id, Duration::from_secs(30)).await,
+        Err(e) => Err(e),
+    };
+
+    match outcome {
+        Ok(BundleOutcome::Landed { slot }) => {
+            info!("Jito bundle landed in slot {slot}: {sig}");
+            return Ok(sig);
+        }
+        Ok(BundleOutcome::Timeout) => warn!("Jito bundle for {sig} not confirmed in time; sending via RPC"),
+        Ok(BundleOutcome::Rejected(reason)) => warn!("Jito bundle for {sig} {reason}; sending via RPC"),
+        Err(e) => warn!("Jito submission for {sig} failed: {e}; sending via RPC"),
+    }
+
+    let sig = rpc.send_transaction(tx).await?;
+    info!("Sent swap tx: {sig}");
+    Ok(sig)
+}
+
+async fn jito_rpc(http: &Client, cfg: &JitoConfig, method: &str, params: Value) -> Result<Value> {
+    let body = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params,
+    });
+
+    let res = http.post(cfg.bundles_url()).json(&body).send().await?;
+    if !res.status().is_success() {
+        let t = res.text().await.unwrap_or_default();
+        return Err(anyhow!("Jito {method} failed: {}", t));
+    }
+
+    let mut v: Value = res.json().await?;
+    if let Some(err) = v.get("error") {
+        return Err(anyhow!("Jito {method} error: {err}"));
+    }
+    Ok(v["result"].take())
+}
+
+/// Spreads tips over the tip accounts using the tx signature as a cheap source of entropy.
+fn pick_tip_account(sig: Option<&Signature>) -> Result<Pubkey> {
+    let idx = sig
+        .map(|s| s.as_ref()[0] as usize)
+        .unwrap_or_default()
+        % TIP_ACCOUNTS.len();
+    Pubkey::from_str(TIP_ACCOUNTS[idx]).map_err(|e| anyhow!("Invalid Jito tip account: {e}"))
+}
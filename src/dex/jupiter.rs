@@ -1,16 +1,30 @@
+use crate::common::error::{BotError, BotResult};
+use crate::common::metrics::{metrics, observe};
+use crate::common::utils::{env_bool, env_u64, env_var_opt};
+use crate::dex::tx_guard::TxGuard;
 use anyhow::{anyhow, Result};
 use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use solana_client::nonblocking::rpc_client::RpcClient as AsyncRpcClient;
+use solana_client::rpc_config::RpcSimulateTransactionConfig;
 use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    compute_budget::ComputeBudgetInstruction,
     hash::Hash,
-    message::VersionedMessage,
+    instruction::{AccountMeta, CompiledInstruction, Instruction},
+    message::{Message, MessageHeader, VersionedMessage},
+    packet::PACKET_DATA_SIZE,
     pubkey::Pubkey,
     signature::{Keypair, Signature, Signer},
-    transaction::VersionedTransaction,
+    system_instruction,
+    transaction::{Transaction, VersionedTransaction},
 };
-use tracing::{debug, info};
+use std::future::Future;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::time::{sleep, Duration, Instant};
+use tracing::{debug, error, info, warn};
 
 #[derive(Debug, Clone, Serialize)]
 pub struct QuoteRequest {
@@ -20,22 +34,197 @@ pub struct QuoteRequest {
     pub slippage_bps: u16,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// Which side of a quote is fixed (Jupiter's `swapMode`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SwapMode {
+    /// `amount` is the input; the output varies and slippage lowers it.
+    #[default]
+    ExactIn,
+    /// `amount` is the output; the input varies and slippage raises it.
+    ExactOut,
+}
+
+impl SwapMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SwapMode::ExactIn => "ExactIn",
+            SwapMode::ExactOut => "ExactOut",
+        }
+    }
+}
+
+/// Optional `/quote` routing parameters; each is sent only when set, so Jupiter's own
+/// default applies otherwise.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QuoteOptions {
+    /// `onlyDirectRoutes`: a single pool, no intermediate hops.
+    pub only_direct_routes: Option<bool>,
+    /// `restrictIntermediateTokens`: hop only through liquid tokens, which keeps fresh
+    /// tokens out of illiquid multi-hop routes.
+    pub restrict_intermediate_tokens: Option<bool>,
+    /// `maxAccounts`: caps the accounts a route may touch so the swap fits in a transaction.
+    pub max_accounts: Option<u64>,
+}
+
+impl QuoteOptions {
+    /// `JUP_ONLY_DIRECT_ROUTES`, `JUP_RESTRICT_INTERMEDIATES` and `JUP_MAX_ACCOUNTS`, each
+    /// unset by default.
+    pub fn from_env() -> Self {
+        let flag = |key: &str| env_var_opt(key).map(|_| env_bool(key, false));
+        Self {
+            only_direct_routes: flag("JUP_ONLY_DIRECT_ROUTES"),
+            restrict_intermediate_tokens: flag("JUP_RESTRICT_INTERMEDIATES"),
+            max_accounts: env_var_opt("JUP_MAX_ACCOUNTS").and_then(|v| v.trim().parse().ok()),
+        }
+    }
+
+    fn params(&self) -> Vec<(&'static str, String)> {
+        let mut params = Vec::new();
+        if let Some(v) = self.only_direct_routes {
+            params.push(("onlyDirectRoutes", v.to_string()));
+        }
+        if let Some(v) = self.restrict_intermediate_tokens {
+            params.push(("restrictIntermediateTokens", v.to_string()));
+        }
+        if let Some(v) = self.max_accounts {
+            params.push(("maxAccounts", v.to_string()));
+        }
+        params
+    }
+}
+
+/// A Jupiter `/quote` response. Serializes back to the same JSON, which `/swap` expects.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QuoteResponse {
     #[serde(rename = "inAmount")]
     pub in_amount: String,
     #[serde(rename = "outAmount")]
     pub out_amount: String,
+    /// With `ExactIn`, the least output the swap accepts (`outAmount` less the slippage);
+    /// with `ExactOut`, the most input it may take (`inAmount` plus the slippage).
+    #[serde(rename = "otherAmountThreshold")]
+    pub other_amount_threshold: String,
+    #[serde(rename = "swapMode", default)]
+    pub swap_mode: SwapMode,
+    /// Fraction as a string (`"0.0123"` = 1.23%).
+    #[serde(rename = "priceImpactPct", default, skip_serializing_if = "Option::is_none")]
+    pub price_impact_pct: Option<String>,
+    #[serde(rename = "routePlan", default)]
+    pub route_plan: Vec<RoutePlanStep>,
 
     // we keep full route object for swap
     #[serde(flatten)]
     pub extra: serde_json::Value,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutePlanStep {
+    #[serde(rename = "swapInfo")]
+    pub swap_info: SwapInfo,
+    pub percent: u8,
+    #[serde(flatten)]
+    pub extra: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwapInfo {
+    #[serde(rename = "ammKey")]
+    pub amm_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(flatten)]
+    pub extra: serde_json::Value,
+}
+
+impl QuoteResponse {
+    /// `outAmount` in raw units of the output mint (0 if malformed).
+    pub fn out_amount(&self) -> u64 {
+        self.out_amount.parse().unwrap_or(0)
+    }
+
+    /// `inAmount` in raw units of the input mint (0 if malformed).
+    pub fn in_amount(&self) -> u64 {
+        self.in_amount.parse().unwrap_or(0)
+    }
+
+    /// `otherAmountThreshold` of an `ExactIn` quote, in raw units of the output mint
+    /// (0 if malformed).
+    pub fn min_out_amount(&self) -> u64 {
+        self.other_amount_threshold.parse().unwrap_or(0)
+    }
+
+    /// `otherAmountThreshold` of an `ExactOut` quote, in raw units of the input mint
+    /// (0 if malformed).
+    pub fn max_in_amount(&self) -> u64 {
+        self.other_amount_threshold.parse().unwrap_or(0)
+    }
+
+    /// The side Jupiter priced rather than the one requested: `outAmount` for `ExactIn`,
+    /// `inAmount` for `ExactOut`.
+    pub fn quoted_amount(&self) -> u64 {
+        match self.swap_mode {
+            SwapMode::ExactIn => self.out_amount(),
+            SwapMode::ExactOut => self.in_amount(),
+        }
+    }
+
+    /// `priceImpactPct` as a percentage. `None` if the field is missing or malformed.
+    pub fn price_impact_pct(&self) -> Option<f64> {
+        let fraction: f64 = self.price_impact_pct.as_deref()?.parse().ok()?;
+        Some(fraction * 100.0)
+    }
+
+    /// Pool accounts (`routePlan[].swapInfo.ammKey`) the quote routes through. The swap
+    /// write-locks them, so their recent priority fees are the relevant competition.
+    pub fn pool_accounts(&self) -> Vec<Pubkey> {
+        self.route_plan
+            .iter()
+            .filter_map(|step| Pubkey::from_str(&step.swap_info.amm_key).ok())
+            .collect()
+    }
+
+    /// Rejects a quote that does not match `swap_mode`, prices the other side at nothing,
+    /// or whose threshold is looser than `slippage_bps` allows, so the swap built from it
+    /// could fill worse than asked for.
+    pub fn validate(&self, swap_mode: SwapMode, slippage_bps: u16) -> BotResult<()> {
+        if self.swap_mode != swap_mode {
+            return Err(BotError::QuoteFailed(format!("asked for {} but got a {} quote", swap_mode.as_str(), self.swap_mode.as_str())));
+        }
+        let quoted = self.quoted_amount();
+        let threshold = self.other_amount_threshold.parse::<u64>().unwrap_or(0);
+        if quoted == 0 {
+            return Err(BotError::QuoteFailed(format!(
+                "quote prices {} at nothing (inAmount {:?}, outAmount {:?})",
+                swap_mode.as_str(),
+                self.in_amount,
+                self.out_amount
+            )));
+        }
+        // One unit of slack for rounding on Jupiter's side
+        let (low, high) = match swap_mode {
+            SwapMode::ExactIn => {
+                let floor = quoted as u128 * 10_000u128.saturating_sub(slippage_bps as u128) / 10_000;
+                ((floor as u64).saturating_sub(1), quoted)
+            }
+            SwapMode::ExactOut => {
+                let ceiling = (quoted as u128 * (10_000 + slippage_bps as u128)).div_ceil(10_000);
+                (quoted, (ceiling as u64).saturating_add(1))
+            }
+        };
+        if !(low..=high).contains(&threshold) {
+            return Err(BotError::QuoteFailed(format!(
+                "otherAmountThreshold {threshold} outside [{low}, {high}] for a {} quote of {quoted} at {slippage_bps} bps",
+                swap_mode.as_str()
+            )));
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct SwapRequest {
     #[serde(rename = "quoteResponse")]
-    pub quote_response: serde_json::Value,
+    pub quote_response: QuoteResponse,
     #[serde(rename = "userPublicKey")]
     pub user_public_key: String,
     #[serde(rename = "wrapAndUnwrapSol")]
@@ -53,98 +242,1184 @@ pub struct SwapResponse {
     pub swap_transaction: String,
 }
 
+/// What became of a sent transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxConfirmation {
+    Confirmed,
+    /// Not seen at the confirmation commitment before the timeout; it may still land or be dropped.
+    Timeout,
+    /// Landed but reverted, with the on-chain error.
+    Failed(String),
+}
+
 pub const SOL_MINT: &str = "So11111111111111111111111111111111111111112";
+pub const USDC_MINT: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+pub const USDT_MINT: &str = "Es9vMFrzaCERmJfrF4H2FYD4KCoNkY11McCe8BenwNYB";
+
+/// Jupiter v6 aggregator program
+pub const JUPITER_PROGRAM_ID: &str = "JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUZoi5QNyVTaV4";
+
+/// Every v6 route instruction ends with
+/// `in_amount: u64, quoted_out_amount: u64, slippage_bps: u16, platform_fee_bps: u8`
+/// (`out_amount, quoted_in_amount, ...` for the ExactOut routes).
+const ROUTE_ARGS_TAIL_LEN: usize = 8 + 8 + 2 + 1;
+
+/// SPL Memo program (v2).
+pub const MEMO_PROGRAM_ID: &str = "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr";
+
+/// Compute units a transaction is assumed to use when converting a priority fee in
+/// lamports to a unit price without an explicit limit.
+const DEFAULT_COMPUTE_UNITS: u32 = 200_000;
+
+/// Hosted Jupiter v6 API, used unless `JUPITER_BASE_URL` is set.
+const DEFAULT_BASE_URL: &str = "https://quote-api.jup.ag/v6";
 
-/// Jupiter v6 quote endpoint
-fn quote_url() -> &'static str {
-    "https://quote-api.jup.ag/v6/quote"
+/// How failed Jupiter calls are retried: network errors, timeouts and 429/500-504
+/// responses, up to `max_retries` times with exponential backoff from `base_delay` (or
+/// the server's `Retry-After`), and never once `window` has passed since the first
+/// attempt, so a quote is not acted on long after it was asked for.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries: u64,
+    pub base_delay: Duration,
+    pub window: Duration,
 }
 
-/// Jupiter v6 swap endpoint
-fn swap_url() -> &'static str {
-    "https://quote-api.jup.ag/v6/swap"
+impl RetryPolicy {
+    /// `JUPITER_MAX_RETRIES` (default 3; `QUOTE_MAX_RETRIES` is the old name),
+    /// `JUPITER_RETRY_BASE_MS` (default 250) and `JUPITER_RETRY_WINDOW_MS` (default 5000).
+    pub fn from_env() -> Self {
+        Self {
+            max_retries: env_u64("JUPITER_MAX_RETRIES", env_u64("QUOTE_MAX_RETRIES", 3)),
+            base_delay: Duration::from_millis(env_u64("JUPITER_RETRY_BASE_MS", 250).max(1)),
+            window: Duration::from_millis(env_u64("JUPITER_RETRY_WINDOW_MS", 5_000)),
+        }
+    }
+
+    /// Doubling per attempt (capped at 32x the base), plus up to 50% jitter so
+    /// concurrent callers do not retry in lockstep.
+    fn backoff_delay(&self, attempt: u64) -> Duration {
+        let base_ms = (self.base_delay.as_millis() as u64) << attempt.min(5);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos() as u64)
+            .unwrap_or_default();
+        Duration::from_millis(base_ms + nanos % (base_ms / 2 + 1))
+    }
+}
+
+/// Jupiter API access: `JUPITER_BASE_URL` (hosted v6 by default, or a self-hosted
+/// instance) with `/quote` and `/swap` appended, and `JUPITER_API_KEY`, when set, sent
+/// as `x-api-key` on both. Quotes carry its `QuoteOptions`. Cheap to clone; the HTTP
+/// client is also used for Jito.
+#[derive(Clone)]
+pub struct JupiterClient {
+    http: Client,
+    base_url: String,
+    api_key: Option<String>,
+    retry: RetryPolicy,
+    quote_options: QuoteOptions,
+}
+
+impl JupiterClient {
+    pub fn new(http: Client, base_url: &str, api_key: Option<String>, retry: RetryPolicy) -> Self {
+        Self {
+            http,
+            base_url: base_url.trim().trim_end_matches('/').to_string(),
+            api_key,
+            retry,
+            quote_options: QuoteOptions::default(),
+        }
+    }
+
+    pub fn with_quote_options(mut self, quote_options: QuoteOptions) -> Self {
+        self.quote_options = quote_options;
+        self
+    }
+
+    pub fn from_env(http: Client) -> Self {
+        let base_url = env_var_opt("JUPITER_BASE_URL").filter(|v| !v.trim().is_empty());
+        let api_key = env_var_opt("JUPITER_API_KEY")
+            .map(|k| k.trim().to_string())
+            .filter(|k| !k.is_empty());
+        Self::new(http, base_url.as_deref().unwrap_or(DEFAULT_BASE_URL), api_key, RetryPolicy::from_env())
+            .with_quote_options(QuoteOptions::from_env())
+    }
+
+    pub fn http(&self) -> &Client {
+        &self.http
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn has_api_key(&self) -> bool {
+        self.api_key.is_some()
+    }
+
+    pub fn quote_options(&self) -> &QuoteOptions {
+        &self.quote_options
+    }
+
+    fn with_key(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.api_key {
+            Some(key) => req.header("x-api-key", key),
+            None => req,
+        }
+    }
+
+    /// Quotes a swap of `input_mint` into `output_mint`. `amount` is the input with
+    /// `SwapMode::ExactIn` and the output with `SwapMode::ExactOut`.
+    pub async fn quote(
+        &self,
+        input_mint: &str,
+        output_mint: &str,
+        amount: u64,
+        slippage_bps: u16,
+        swap_mode: SwapMode,
+    ) -> BotResult<QuoteResponse> {
+        let url = quote_url(&self.base_url, input_mint, output_mint, amount, slippage_bps, swap_mode, &self.quote_options)?;
+
+        let started = Instant::now();
+        let res = send_with_retries(&self.retry, BotError::QuoteFailed, || self.with_key(self.http.get(url.clone())).send()).await?;
+        let quote = res
+            .json::<QuoteResponse>()
+            .await
+            .map_err(|e| BotError::QuoteFailed(format!("invalid response: {e}")));
+        observe(&metrics().quote_latency, started.elapsed());
+        let quote = quote?;
+        quote.validate(swap_mode, slippage_bps)?;
+        Ok(quote)
+    }
+
+    pub async fn swap_tx(
+        &self,
+        quote_response: QuoteResponse,
+        user_pubkey: Pubkey,
+        prioritization_fee_lamports: u64,
+    ) -> BotResult<SwapResponse> {
+        let req = SwapRequest {
+            quote_response,
+            user_public_key: user_pubkey.to_string(),
+            wrap_and_unwrap_sol: true,
+            dynamic_compute_unit_limit: true,
+            prioritization_fee_lamports,
+        };
+
+        let url = format!("{}/swap", self.base_url);
+        let started = Instant::now();
+        let res =
+            send_with_retries(&self.retry, BotError::SwapBuildFailed, || self.with_key(self.http.post(&url)).json(&req).send()).await?;
+        let swap = res
+            .json::<SwapResponse>()
+            .await
+            .map_err(|e| BotError::SwapBuildFailed(format!("invalid response: {e}")));
+        observe(&metrics().swap_build_latency, started.elapsed());
+        swap
+    }
 }
 
-pub async fn jupiter_quote(
-    http: &Client,
+fn quote_url(
+    base_url: &str,
     input_mint: &str,
     output_mint: &str,
     amount: u64,
     slippage_bps: u16,
-) -> Result<serde_json::Value> {
-    let url = reqwest::Url::parse_with_params(
-        quote_url(),
-        &[
-            ("inputMint", input_mint),
-            ("outputMint", output_mint),
-            ("amount", &amount.to_string()),
-            ("slippageBps", &slippage_bps.to_string()),
-        ],
-    )?;
-
-    let res = http.get(url).send().await?;
-    if !res.status().is_success() {
-        let t = res.text().await.unwrap_or_default();
-        return Err(anyhow!("Jupiter quote failed: {}", t));
-    }
-    Ok(res.json::<serde_json::Value>().await?)
-}
-
-pub async fn jupiter_swap_tx(
-    http: &Client,
-    quote_response: serde_json::Value,
-    user_pubkey: Pubkey,
-    prioritization_fee_lamports: u64,
-) -> Result<SwapResponse> {
-    let req = SwapRequest {
-        quote_response,
-        user_public_key: user_pubkey.to_string(),
-        wrap_and_unwrap_sol: true,
-        dynamic_compute_unit_limit: true,
-        prioritization_fee_lamports,
+    swap_mode: SwapMode,
+    options: &QuoteOptions,
+) -> BotResult<reqwest::Url> {
+    let mut params = vec![
+        ("inputMint", input_mint.to_string()),
+        ("outputMint", output_mint.to_string()),
+        ("amount", amount.to_string()),
+        ("slippageBps", slippage_bps.to_string()),
+        ("swapMode", swap_mode.as_str().to_string()),
+    ];
+    params.extend(options.params());
+    reqwest::Url::parse_with_params(&format!("{base_url}/quote"), &params)
+        .map_err(|e| BotError::QuoteFailed(format!("invalid quote URL: {e}")))
+}
+
+/// Sends a Jupiter request, retrying as `policy` allows. Other statuses fail
+/// immediately; once retries or the window run out the last error is returned, wrapped
+/// by `kind`.
+async fn send_with_retries<F, Fut>(policy: &RetryPolicy, kind: fn(String) -> BotError, mut send: F) -> BotResult<reqwest::Response>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = reqwest::Result<reqwest::Response>>,
+{
+    let started = Instant::now();
+    let mut attempt: u64 = 0;
+    loop {
+        let (err, retry_after) = match send().await {
+            Ok(res) if res.status().is_success() => return Ok(res),
+            Ok(res) => {
+                let status = res.status();
+                let retry_after = retry_after(&res);
+                let t = res.text().await.unwrap_or_default();
+                let err = kind(format!("{status}: {t}"));
+                if !is_retriable(status) {
+                    return Err(err);
+                }
+                (err, retry_after)
+            }
+            Err(e) => (kind(format!("request error: {e}")), None),
+        };
+
+        if attempt >= policy.max_retries {
+            return Err(err);
+        }
+        let delay = retry_after.unwrap_or_else(|| policy.backoff_delay(attempt));
+        if started.elapsed() + delay > policy.window {
+            warn!("{err}; not retrying: a retry in {}ms would pass the {}ms retry window", delay.as_millis(), policy.window.as_millis());
+            return Err(err);
+        }
+        attempt += 1;
+        warn!("{err}; retry {attempt}/{} in {}ms", policy.max_retries, delay.as_millis());
+        sleep(delay).await;
+    }
+}
+
+fn is_retriable(status: reqwest::StatusCode) -> bool {
+    status == reqwest::StatusCode::TOO_MANY_REQUESTS || (500..=504).contains(&status.as_u16())
+}
+
+/// `Retry-After` in seconds. The HTTP-date form is ignored in favour of the backoff.
+fn retry_after(res: &reqwest::Response) -> Option<Duration> {
+    let secs: u64 = res.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim().parse().ok()?;
+    Some(Duration::from_secs(secs))
+}
+
+/// Log lines that fail a simulation even when it reports no error.
+const SIMULATION_FAILURE_LOGS: &[&str] = &["insufficient funds", "slippage tolerance exceeded"];
+
+/// Simulates a signed swap, signatures checked, and returns the compute units it used.
+/// Fails with `SimulationFailed` if it errors or its logs show insufficient funds or
+/// exceeded slippage, so the tx is not sent.
+pub async fn simulate_signed_swap(rpc: &AsyncRpcClient, tx: &VersionedTransaction) -> BotResult<Option<u64>> {
+    let config = RpcSimulateTransactionConfig {
+        sig_verify: true,
+        commitment: Some(rpc.commitment()),
+        ..Default::default()
     };
+    let sim = rpc
+        .simulate_transaction_with_config(tx, config)
+        .await
+        .map_err(|e| BotError::SimulationFailed(format!("request error: {e}")))?
+        .value;
+    let logs = sim.logs.unwrap_or_default();
+    if let Some(err) = sim.err {
+        debug!("Simulation logs: {logs:?}");
+        return Err(BotError::SimulationFailed(err.to_string()));
+    }
+    if let Some(line) = logs.iter().find(|l| {
+        let l = l.to_lowercase();
+        SIMULATION_FAILURE_LOGS.iter().any(|f| l.contains(f))
+    }) {
+        return Err(BotError::SimulationFailed(line.clone()));
+    }
+    info!(
+        "Swap simulation ok: {} compute units",
+        sim.units_consumed.map_or_else(|| "unknown".to_string(), |u| u.to_string())
+    );
+    Ok(sim.units_consumed)
+}
+
+pub async fn send_signed_swap(rpc: &AsyncRpcClient, tx: &VersionedTransaction) -> BotResult<Signature> {
+    debug!("Sending signed swap tx...");
+    let sig = rpc
+        .send_transaction(tx)
+        .await
+        .map_err(|e| BotError::SendFailed(e.to_string()))?;
+    info!("Sent swap tx: {sig}");
+    Ok(sig)
+}
 
-    let res = http.post(swap_url()).json(&req).send().await?;
-    if !res.status().is_success() {
-        let t = res.text().await.unwrap_or_default();
-        return Err(anyhow!("Jupiter swap failed: {}", t));
+/// Re-checks a signed tx's blockhash right before sending and, if it has expired,
+/// re-fetches it and re-signs. Txs co-signed by a third party cannot be refreshed and
+/// error instead.
+pub async fn ensure_fresh_blockhash(
+    rpc: &AsyncRpcClient,
+    wallet: &Keypair,
+    fee_payer: Option<&Keypair>,
+    tx: VersionedTransaction,
+) -> Result<VersionedTransaction> {
+    let blockhash = *tx.message.recent_blockhash();
+    if rpc.is_blockhash_valid(&blockhash, rpc.commitment()).await? {
+        return Ok(tx);
+    }
+    let ours = fee_payer.is_some() as u8 + 1;
+    if tx.message.header().num_required_signatures > ours {
+        return Err(anyhow!("Blockhash {blockhash} expired on a co-signed swap tx; it cannot be re-signed"));
+    }
+    warn!("Blockhash {blockhash} expired before send; refreshing and re-signing");
+    sign_tx(rpc, &our_signers(wallet, fee_payer), tx).await
+}
+
+/// Makes `fee_payer` pay the transaction fee. Solana charges account 0, so the payer is
+/// prepended to the static keys as an extra writable signer and every account index
+/// in the instructions shifts by one; lookup-table addresses are indexed after the
+/// static keys, so they shift the same way. The wallet keeps its signer slot and still
+/// funds the swap itself, including any rent Jupiter's instructions charge it.
+/// Existing signatures are dropped because the message changes.
+pub fn with_fee_payer(mut tx: VersionedTransaction, fee_payer: &Pubkey) -> Result<VersionedTransaction> {
+    let keys = tx.message.static_account_keys();
+    if keys.first() == Some(fee_payer) {
+        return Ok(tx);
+    }
+    if keys.contains(fee_payer) {
+        return Err(anyhow!("Fee payer {fee_payer} is already an account of the swap tx"));
+    }
+    let signer_count = tx.message.header().num_required_signatures as usize;
+    if tx.signatures.iter().take(signer_count).any(|s| *s != Signature::default()) {
+        return Err(anyhow!("Swap tx is already signed; its fee payer cannot be changed"));
+    }
+
+    match &mut tx.message {
+        VersionedMessage::Legacy(m) => prepend_payer(&mut m.account_keys, &mut m.header, &mut m.instructions, fee_payer)?,
+        VersionedMessage::V0(m) => prepend_payer(&mut m.account_keys, &mut m.header, &mut m.instructions, fee_payer)?,
+    }
+    tx.signatures = vec![Signature::default(); signer_count + 1];
+
+    let size = bincode::serialized_size(&tx)? as usize;
+    if size > PACKET_DATA_SIZE {
+        return Err(anyhow!(
+            "Swap tx with a separate fee payer is {size} bytes, over the {PACKET_DATA_SIZE}-byte limit"
+        ));
     }
-    Ok(res.json::<SwapResponse>().await?)
+    Ok(tx)
 }
 
-pub async fn sign_and_send_swap(
+fn prepend_payer(
+    keys: &mut Vec<Pubkey>,
+    header: &mut MessageHeader,
+    instructions: &mut [CompiledInstruction],
+    payer: &Pubkey,
+) -> Result<()> {
+    let shift = |i: &mut u8| -> Result<()> {
+        *i = i.checked_add(1).ok_or_else(|| anyhow!("Swap tx has too many accounts to add a fee payer"))?;
+        Ok(())
+    };
+    for ix in instructions.iter_mut() {
+        shift(&mut ix.program_id_index)?;
+        for account in ix.accounts.iter_mut() {
+            shift(account)?;
+        }
+    }
+    keys.insert(0, *payer);
+    header.num_required_signatures += 1;
+    Ok(())
+}
+
+fn our_signers<'a>(wallet: &'a Keypair, fee_payer: Option<&'a Keypair>) -> Vec<&'a Keypair> {
+    std::iter::once(wallet).chain(fee_payer).collect()
+}
+
+/// Where an instruction goes in a transaction assembled by `TransactionBuilder`. Stages
+/// are emitted in this order, after the compute-budget instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IxStage {
+    /// Account setup: ATA creation, wrapping SOL.
+    Setup,
+    /// The swap itself.
+    Swap,
+    /// Closing temporary accounts, unwrapping wSOL.
+    Cleanup,
+    /// Memos, after everything they describe.
+    Memo,
+    /// Tips last, so they are only paid if everything before them succeeded.
+    Tip,
+}
+
+/// Assembles a transaction from instructions in a fixed order: compute unit limit and
+/// price, then each `IxStage` in turn, instructions within a stage in the order added.
+/// The result is unsigned with a default blockhash; `sign_swap` fills in both.
+#[derive(Debug, Clone)]
+pub struct TransactionBuilder {
+    payer: Pubkey,
+    compute_unit_limit: Option<u32>,
+    priority_fee_lamports: u64,
+    instructions: Vec<(IxStage, Instruction)>,
+}
+
+impl TransactionBuilder {
+    /// `payer` pays the fee and is the first signer.
+    pub fn new(payer: Pubkey) -> Self {
+        Self {
+            payer,
+            compute_unit_limit: None,
+            priority_fee_lamports: 0,
+            instructions: Vec::new(),
+        }
+    }
+
+    pub fn compute_unit_limit(mut self, units: u32) -> Self {
+        self.compute_unit_limit = Some(units);
+        self
+    }
+
+    /// Total priority fee, spread over the compute unit limit (`DEFAULT_COMPUTE_UNITS`
+    /// if none is set) as a unit price. 0 = no price instruction.
+    pub fn priority_fee_lamports(mut self, lamports: u64) -> Self {
+        self.priority_fee_lamports = lamports;
+        self
+    }
+
+    /// Appends `ix` to the end of `stage`.
+    pub fn push(mut self, stage: IxStage, ix: Instruction) -> Self {
+        self.instructions.push((stage, ix));
+        self
+    }
+
+    /// Inserts `ix` at the start of `stage`, before anything already in it.
+    pub fn prepend(mut self, stage: IxStage, ix: Instruction) -> Self {
+        let at = self.instructions.iter().position(|(s, _)| *s == stage).unwrap_or(self.instructions.len());
+        self.instructions.insert(at, (stage, ix));
+        self
+    }
+
+    /// A memo signed by `signer`, which must also sign the transaction.
+    pub fn memo(self, signer: &Pubkey, text: &str) -> Result<Self> {
+        let ix = Instruction::new_with_bytes(
+            Pubkey::from_str(MEMO_PROGRAM_ID)?,
+            text.as_bytes(),
+            vec![AccountMeta::new_readonly(*signer, true)],
+        );
+        Ok(self.push(IxStage::Memo, ix))
+    }
+
+    /// A SOL transfer of `lamports` from the payer to `to` (e.g. a Jito tip account).
+    pub fn tip(self, to: &Pubkey, lamports: u64) -> Self {
+        let ix = system_instruction::transfer(&self.payer, to, lamports);
+        self.push(IxStage::Tip, ix)
+    }
+
+    /// Every instruction, in the order they will execute.
+    pub fn instructions(&self) -> Vec<Instruction> {
+        let mut ixs = Vec::with_capacity(self.instructions.len() + 2);
+        let limit = self.compute_unit_limit;
+        if let Some(units) = limit {
+            ixs.push(ComputeBudgetInstruction::set_compute_unit_limit(units));
+        }
+        if self.priority_fee_lamports > 0 {
+            let units = limit.unwrap_or(DEFAULT_COMPUTE_UNITS).max(1) as u64;
+            let micro_lamports = self.priority_fee_lamports.saturating_mul(1_000_000) / units;
+            ixs.push(ComputeBudgetInstruction::set_compute_unit_price(micro_lamports));
+        }
+        // Stable sort: order within a stage is kept
+        let mut staged: Vec<&(IxStage, Instruction)> = self.instructions.iter().collect();
+        staged.sort_by_key(|(stage, _)| *stage);
+        ixs.extend(staged.into_iter().map(|(_, ix)| ix.clone()));
+        ixs
+    }
+
+    /// The unsigned transaction. Fails if it would not fit in a packet once signed.
+    pub fn build(&self) -> Result<VersionedTransaction> {
+        let message = Message::new(&self.instructions(), Some(&self.payer));
+        let tx = VersionedTransaction::from(Transaction::new_unsigned(message));
+        // Signature slots are already allocated, so this is the signed size
+        let size = bincode::serialized_size(&tx)? as usize;
+        if size > PACKET_DATA_SIZE {
+            return Err(anyhow!("Transaction is {size} bytes, over the {PACKET_DATA_SIZE}-byte limit"));
+        }
+        Ok(tx)
+    }
+
+    /// `build`, base64-encoded like a Jupiter `swapTransaction`.
+    pub fn build_base64(&self) -> Result<String> {
+        Ok(B64.encode(bincode::serialize(&self.build()?)?))
+    }
+}
+
+/// `quoted_out_amount` encoded in the Jupiter route instruction of a built swap tx,
+/// i.e. the out amount the transaction was actually built for. The `ExactOut` route
+/// instructions carry `quoted_in_amount` in the same place, which is returned instead.
+/// Returns `None` if the tx has no Jupiter instruction.
+pub fn swap_quoted_out_amount(swap_b64: &str) -> Result<Option<u64>> {
+    let bytes = B64.decode(swap_b64)?;
+    let tx: VersionedTransaction = bincode::deserialize(&bytes)?;
+    let jupiter = Pubkey::from_str(JUPITER_PROGRAM_ID)?;
+    let keys = tx.message.static_account_keys();
+
+    for ix in tx.message.instructions() {
+        if keys.get(ix.program_id_index as usize) != Some(&jupiter) {
+            continue;
+        }
+        // 8-byte discriminator, route plan, then the fixed tail
+        if ix.data.len() < 8 + ROUTE_ARGS_TAIL_LEN {
+            continue;
+        }
+        let tail = &ix.data[ix.data.len() - ROUTE_ARGS_TAIL_LEN..];
+        let mut out = [0u8; 8];
+        out.copy_from_slice(&tail[8..16]);
+        return Ok(Some(u64::from_le_bytes(out)));
+    }
+    Ok(None)
+}
+
+/// Polls `get_signature_statuses` until `sig` reaches `commitment`, reverts, or
+/// `timeout` elapses.
+pub async fn confirm_transaction(
+    rpc: &AsyncRpcClient,
+    sig: &Signature,
+    commitment: CommitmentConfig,
+    timeout: Duration,
+) -> Result<TxConfirmation> {
+    let start = Instant::now();
+    loop {
+        let statuses = rpc.get_signature_statuses(&[*sig]).await?;
+        if let Some(status) = statuses.value.into_iter().next().flatten() {
+            if let Some(err) = status.err {
+                return Ok(TxConfirmation::Failed(err.to_string()));
+            }
+            if status.satisfies_commitment(commitment) {
+                return Ok(TxConfirmation::Confirmed);
+            }
+        }
+
+        if start.elapsed() > timeout {
+            return Ok(TxConfirmation::Timeout);
+        }
+        sleep(Duration::from_millis(500)).await;
+    }
+}
+
+/// Jupiter's `SlippageToleranceExceeded` (6001) as it appears in a transaction error.
+const SLIPPAGE_EXCEEDED_ERROR: &str = "custom program error: 0x1771";
+
+/// Whether a send or on-chain failure is a swap's slippage check reverting, which a
+/// re-quote with more slippage may get past.
+pub fn is_slippage_error(err: &str) -> bool {
+    err.contains(SLIPPAGE_EXCEEDED_ERROR) || err.to_lowercase().contains("slippage")
+}
+
+/// Decodes Jupiter's base64 swap transaction and signs our slot with `wallet`, without
+/// sending it. With a `fee_payer` the tx is first rewritten so that key pays the fee,
+/// and it signs too. If we are the only required signers the blockhash is refreshed
+/// first; if other signers already signed, their signatures and the blockhash they
+/// signed over are preserved. Fails if a required signature is missing that we cannot add,
+/// or if `guard` rejects the tx.
+pub async fn sign_swap(
     rpc: &AsyncRpcClient,
     wallet: &Keypair,
+    fee_payer: Option<&Keypair>,
+    guard: Option<&TxGuard>,
     swap_b64: &str,
-) -> Result<Signature> {
+) -> Result<VersionedTransaction> {
     let bytes = B64.decode(swap_b64)?;
     let mut tx: VersionedTransaction = bincode::deserialize(&bytes)?;
+    if let Some(guard) = guard {
+        guard.check(&tx, &wallet.pubkey()).map_err(|e| {
+            error!("Refusing to sign swap tx: {e}");
+            anyhow!("swap tx rejected by TX_GUARD: {e}")
+        })?;
+    }
+    if let Some(payer) = fee_payer {
+        tx = with_fee_payer(tx, &payer.pubkey())?;
+    }
+    sign_tx(rpc, &our_signers(wallet, fee_payer), tx).await
+}
 
-    // Ensure blockhash is fresh
-    let latest: Hash = rpc.get_latest_blockhash().await?;
+/// Signs `tx` with `signers`, each of which must be one of its required signers. When they
+/// are all of them, the tx is rebuilt on a fresh blockhash. A tx that also needs someone
+/// else's signature is accepted only if that signature is already in place: its blockhash
+/// is kept and just our slots are filled. Otherwise it is an error naming the missing
+/// signers. Either way the result has every signature and verifies.
+async fn sign_tx(
+    rpc: &AsyncRpcClient,
+    signers: &[&Keypair],
+    mut tx: VersionedTransaction,
+) -> Result<VersionedTransaction> {
+    let signer_count = tx.message.header().num_required_signatures as usize;
+    let keys = tx.message.static_account_keys();
+    let mut our_slots = Vec::with_capacity(signers.len());
+    for signer in signers {
+        let index = keys
+            .iter()
+            .take(signer_count)
+            .position(|k| *k == signer.pubkey())
+            .ok_or_else(|| anyhow!("Swap tx does not list our key {} as a signer", signer.pubkey()))?;
+        our_slots.push((index, *signer));
+    }
+    let co_signers: Vec<usize> = (0..signer_count)
+        .filter(|i| !our_slots.iter().any(|(ours, _)| ours == i))
+        .collect();
+    let unsigned: Vec<String> = co_signers
+        .iter()
+        .filter(|i| tx.signatures.get(**i).is_none_or(|s| *s == Signature::default()))
+        .map(|i| keys[*i].to_string())
+        .collect();
+    if !unsigned.is_empty() {
+        return Err(anyhow!(
+            "Swap tx expects {signer_count} signers but only {} are ours; missing signatures from {}",
+            signers.len(),
+            unsigned.join(", ")
+        ));
+    }
 
-    // Replace recent blockhash inside message (both legacy and v0)
-    // We must rebuild the message with updated blockhash.
-    let msg = match &tx.message {
-        VersionedMessage::Legacy(m) => {
-            let mut m2 = m.clone();
-            m2.recent_blockhash = latest;
-            VersionedMessage::Legacy(m2)
+    let tx = if co_signers.is_empty() {
+        // We sign every slot, so the blockhash can be refreshed and the tx built anew
+        let latest: Hash = rpc.get_latest_blockhash().await?;
+        let mut message = tx.message;
+        match &mut message {
+            VersionedMessage::Legacy(m) => m.recent_blockhash = latest,
+            VersionedMessage::V0(m) => m.recent_blockhash = latest,
         }
-        VersionedMessage::V0(m) => {
-            let mut m2 = m.clone();
-            m2.recent_blockhash = latest;
-            VersionedMessage::V0(m2)
+        VersionedTransaction::try_new(message, signers).map_err(|e| anyhow!("Failed to sign swap tx: {e}"))?
+    } else {
+        // A new blockhash would invalidate the co-signers' signatures; fill in only our slots
+        debug!("Swap tx has {} co-signer(s); keeping its blockhash", co_signers.len());
+        tx.signatures.resize(signer_count, Signature::default());
+        let message = tx.message.serialize();
+        for (index, signer) in our_slots {
+            tx.signatures[index] = signer
+                .try_sign_message(&message)
+                .map_err(|e| anyhow!("Failed to sign swap tx: {e}"))?;
         }
+        tx
     };
 
-    // Re-sign
-    let signers: [&Keypair; 1] = [wallet];
-    tx.message = msg;
-    tx.sign(&signers, latest);
+    if tx.signatures.len() != signer_count {
+        return Err(anyhow!("Signed swap tx has {} signatures, expected {signer_count}", tx.signatures.len()));
+    }
+    tx.verify_and_hash_message()
+        .map_err(|e| anyhow!("Signed swap tx does not verify: {e}"))?;
+    Ok(tx)
+}
 
-    debug!("Sending signed swap tx...");
-    let sig = rpc.send_transaction(&tx).await?;
-    info!("Sent swap tx: {sig}");
-    Ok(sig)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use wiremock::matchers::{header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    /// Talks to the mock server directly, whatever proxy the environment sets.
+    fn http() -> Client {
+        Client::builder().no_proxy().build().unwrap()
+    }
+
+    fn no_retries() -> RetryPolicy {
+        RetryPolicy { max_retries: 0, base_delay: Duration::from_millis(1), window: Duration::from_secs(1) }
+    }
+
+    /// An `ExactIn` quote of 1_000_000 into 5_000 at 50 bps.
+    fn quote_json() -> serde_json::Value {
+        json!({
+            "inputMint": SOL_MINT,
+            "outputMint": USDC_MINT,
+            "inAmount": "1000000",
+            "outAmount": "5000",
+            "otherAmountThreshold": "4975",
+            "swapMode": "ExactIn",
+            "slippageBps": 50,
+            "priceImpactPct": "0.001",
+            "routePlan": [],
+        })
+    }
+
+    #[test]
+    fn quote_url_carries_the_swap_mode() {
+        let url = |mode| quote_url("https://jup.test/v6", SOL_MINT, USDC_MINT, 5, 50, mode, &QuoteOptions::default()).unwrap();
+        let exact_out = url(SwapMode::ExactOut);
+        assert_eq!(exact_out.path(), "/v6/quote");
+        let params: Vec<(String, String)> = exact_out.query_pairs().into_owned().collect();
+        assert!(params.contains(&("swapMode".to_string(), "ExactOut".to_string())), "{params:?}");
+        assert!(params.contains(&("amount".to_string(), "5".to_string())), "{params:?}");
+        assert!(url(SwapMode::ExactIn).query_pairs().any(|(k, v)| k == "swapMode" && v == "ExactIn"));
+    }
+
+    #[tokio::test]
+    async fn exact_out_quote_is_requested_and_validated_as_exact_out() {
+        let server = MockServer::start().await;
+        // Buying exactly 5000 out for at most 1_005_000 in at 50 bps
+        let exact_out = json!({
+            "inAmount": "1000000",
+            "outAmount": "5000",
+            "otherAmountThreshold": "1005000",
+            "swapMode": "ExactOut",
+            "routePlan": [],
+        });
+        Mock::given(path("/quote"))
+            .and(query_param("swapMode", "ExactOut"))
+            .and(query_param("amount", "5000"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(exact_out))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/quote"))
+            .and(query_param("swapMode", "ExactIn"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(quote_json()))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = JupiterClient::new(http(), &server.uri(), None, no_retries());
+        let quote = client.quote(SOL_MINT, USDC_MINT, 5000, 50, SwapMode::ExactOut).await.unwrap();
+        assert_eq!((quote.quoted_amount(), quote.max_in_amount()), (1_000_000, 1_005_000));
+        let quote = client.quote(SOL_MINT, USDC_MINT, 1_000_000, 50, SwapMode::ExactIn).await.unwrap();
+        assert_eq!((quote.quoted_amount(), quote.min_out_amount()), (5000, 4975));
+    }
+
+    #[tokio::test]
+    async fn quote_options_are_sent_only_when_set() {
+        let server = MockServer::start().await;
+        Mock::given(path("/quote"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(quote_json()))
+            .mount(&server)
+            .await;
+        let option_keys = ["onlyDirectRoutes", "restrictIntermediateTokens", "maxAccounts"];
+
+        let client = JupiterClient::new(http(), &server.uri(), None, no_retries());
+        client.quote(SOL_MINT, USDC_MINT, 1_000_000, 50, SwapMode::ExactIn).await.unwrap();
+        let client = client.with_quote_options(QuoteOptions {
+            only_direct_routes: Some(false),
+            restrict_intermediate_tokens: Some(true),
+            max_accounts: Some(40),
+        });
+        client.quote(SOL_MINT, USDC_MINT, 1_000_000, 50, SwapMode::ExactIn).await.unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let params = |i: usize| -> Vec<(String, String)> { requests[i].url.query_pairs().into_owned().collect() };
+        assert!(params(0).iter().all(|(k, _)| !option_keys.contains(&k.as_str())), "{:?}", params(0));
+        let set: Vec<(String, String)> =
+            params(1).into_iter().filter(|(k, _)| option_keys.contains(&k.as_str())).collect();
+        assert_eq!(
+            set,
+            [("onlyDirectRoutes", "false"), ("restrictIntermediateTokens", "true"), ("maxAccounts", "40")]
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+        );
+    }
+
+    fn retries(max_retries: u64, window_ms: u64) -> RetryPolicy {
+        RetryPolicy { max_retries, base_delay: Duration::from_millis(1), window: Duration::from_millis(window_ms) }
+    }
+
+    /// `send_with_retries` on a GET of `/quote` from `server`.
+    async fn get_with_retries(server: &MockServer, policy: &RetryPolicy) -> BotResult<reqwest::Response> {
+        let (client, url) = (http(), format!("{}/quote", server.uri()));
+        send_with_retries(policy, BotError::QuoteFailed, || client.get(&url).send()).await
+    }
+
+    async fn requests(server: &MockServer) -> usize {
+        server.received_requests().await.unwrap().len()
+    }
+
+    #[tokio::test]
+    async fn retries_until_success() {
+        let server = MockServer::start().await;
+        Mock::given(path("/quote")).respond_with(ResponseTemplate::new(503)).up_to_n_times(2).mount(&server).await;
+        Mock::given(path("/quote")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+
+        get_with_retries(&server, &retries(3, 5_000)).await.unwrap();
+        assert_eq!(requests(&server).await, 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let server = MockServer::start().await;
+        Mock::given(path("/quote")).respond_with(ResponseTemplate::new(500).set_body_string("down")).mount(&server).await;
+
+        let err = get_with_retries(&server, &retries(2, 5_000)).await.unwrap_err().to_string();
+        assert!(err.contains("500") && err.contains("down"), "{err}");
+        assert_eq!(requests(&server).await, 3);
+    }
+
+    #[tokio::test]
+    async fn honours_retry_after() {
+        let server = MockServer::start().await;
+        Mock::given(path("/quote"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(path("/quote")).respond_with(ResponseTemplate::new(200)).mount(&server).await;
+
+        let started = Instant::now();
+        get_with_retries(&server, &retries(3, 5_000)).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_secs(1), "{:?}", started.elapsed());
+        assert_eq!(requests(&server).await, 2);
+    }
+
+    #[tokio::test]
+    async fn stops_retrying_at_the_window() {
+        let server = MockServer::start().await;
+        Mock::given(path("/quote"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "2"))
+            .mount(&server)
+            .await;
+
+        // The server asks for 2s but the whole window is 1s
+        let started = Instant::now();
+        assert!(get_with_retries(&server, &retries(3, 1_000)).await.is_err());
+        assert!(started.elapsed() < Duration::from_secs(1), "{:?}", started.elapsed());
+        assert_eq!(requests(&server).await, 1);
+    }
+
+    #[tokio::test]
+    async fn client_errors_fail_immediately() {
+        let server = MockServer::start().await;
+        Mock::given(path("/quote")).respond_with(ResponseTemplate::new(400).set_body_string("bad mint")).mount(&server).await;
+
+        let err = get_with_retries(&server, &retries(3, 5_000)).await.unwrap_err();
+        assert!(matches!(&err, BotError::QuoteFailed(msg) if msg.contains("400") && msg.contains("bad mint")), "{err}");
+        assert_eq!(requests(&server).await, 1);
+    }
+
+    /// An RPC client whose `getLatestBlockhash` returns `blockhash`.
+    fn latest_blockhash(blockhash: Hash) -> AsyncRpcClient {
+        use solana_client::rpc_request::RpcRequest;
+        let response = json!({
+            "context": { "slot": 1 },
+            "value": { "blockhash": blockhash.to_string(), "lastValidBlockHeight": 100 },
+        });
+        AsyncRpcClient::new_mock_with_mocks("succeeds".to_string(), [(RpcRequest::GetLatestBlockhash, response)].into())
+    }
+
+    /// A tx as Jupiter returns it: unsigned, on an old blockhash.
+    fn unsigned(message: VersionedMessage) -> VersionedTransaction {
+        let signatures = vec![Signature::default(); message.header().num_required_signatures as usize];
+        VersionedTransaction { signatures, message }
+    }
+
+    #[tokio::test]
+    async fn single_signer_tx_gets_a_fresh_blockhash() {
+        use solana_sdk::message::v0;
+        let wallet = Keypair::new();
+        let ixs = [system_instruction::transfer(&wallet.pubkey(), &Pubkey::new_unique(), 1)];
+        let legacy = Message::new_with_blockhash(&ixs, Some(&wallet.pubkey()), &Hash::new_unique());
+        let v0 = v0::Message::try_compile(&wallet.pubkey(), &ixs, &[], Hash::new_unique()).unwrap();
+
+        for message in [VersionedMessage::Legacy(legacy), VersionedMessage::V0(v0)] {
+            // Mocked responses are used up by the first call
+            let fresh = Hash::new_unique();
+            let tx = sign_tx(&latest_blockhash(fresh), &[&wallet], unsigned(message)).await.unwrap();
+            assert_eq!(*tx.message.recent_blockhash(), fresh);
+            assert_eq!(tx.signatures.len(), 1);
+            tx.verify_and_hash_message().unwrap();
+            assert!(tx.verify_with_results().iter().all(|ok| *ok));
+        }
+    }
+
+    #[tokio::test]
+    async fn fee_payer_pays_and_both_sign() {
+        let (wallet, fee_payer) = (Keypair::new(), Keypair::new());
+        let ix = system_instruction::transfer(&wallet.pubkey(), &Pubkey::new_unique(), 1);
+        let message = Message::new_with_blockhash(&[ix], Some(&wallet.pubkey()), &Hash::new_unique());
+        let swap_b64 = B64.encode(bincode::serialize(&unsigned(VersionedMessage::Legacy(message))).unwrap());
+
+        let fresh = Hash::new_unique();
+        let tx = sign_swap(&latest_blockhash(fresh), &wallet, Some(&fee_payer), None, &swap_b64)
+            .await
+            .unwrap();
+        let keys = tx.message.static_account_keys();
+        assert_eq!(&keys[..2], &[fee_payer.pubkey(), wallet.pubkey()]);
+        assert_eq!(tx.message.header().num_required_signatures, 2);
+        assert_eq!(*tx.message.recent_blockhash(), fresh);
+        assert_eq!(tx.signatures.len(), 2);
+        assert_eq!(tx.verify_with_results(), [true, true]);
+    }
+
+    /// An RPC client that reports `valid` for any blockhash and returns `latest` as the newest.
+    fn blockhash_checks(valid: bool, latest: Hash) -> AsyncRpcClient {
+        use solana_client::rpc_request::RpcRequest;
+        let mocks = [
+            (RpcRequest::IsBlockhashValid, json!({ "context": { "slot": 1 }, "value": valid })),
+            (
+                RpcRequest::GetLatestBlockhash,
+                json!({ "context": { "slot": 1 }, "value": { "blockhash": latest.to_string(), "lastValidBlockHeight": 100 } }),
+            ),
+        ];
+        AsyncRpcClient::new_mock_with_mocks("succeeds".to_string(), mocks.into())
+    }
+
+    #[tokio::test]
+    async fn expired_blockhash_is_refreshed_and_re_signed() {
+        let wallet = Keypair::new();
+        let ix = system_instruction::transfer(&wallet.pubkey(), &Pubkey::new_unique(), 1);
+        let stale = Hash::new_unique();
+        let message = VersionedMessage::Legacy(Message::new_with_blockhash(&[ix], Some(&wallet.pubkey()), &stale));
+        let tx = VersionedTransaction::try_new(message, &[&wallet]).unwrap();
+
+        let still_valid = ensure_fresh_blockhash(&blockhash_checks(true, Hash::new_unique()), &wallet, None, tx.clone())
+            .await
+            .unwrap();
+        assert_eq!(still_valid.signatures, tx.signatures);
+
+        let fresh = Hash::new_unique();
+        let refreshed = ensure_fresh_blockhash(&blockhash_checks(false, fresh), &wallet, None, tx.clone()).await.unwrap();
+        assert_eq!(*refreshed.message.recent_blockhash(), fresh);
+        assert_ne!(refreshed.signatures, tx.signatures);
+        assert!(refreshed.verify_with_results().iter().all(|ok| *ok));
+    }
+
+    #[tokio::test]
+    async fn expired_blockhash_on_a_co_signed_tx_is_an_error() {
+        let (wallet, co_signer) = (Keypair::new(), Keypair::new());
+        let message = co_signed(&wallet, &co_signer, Hash::new_unique());
+        let tx = VersionedTransaction::try_new(message, &[&wallet, &co_signer]).unwrap();
+
+        let err = ensure_fresh_blockhash(&blockhash_checks(false, Hash::new_unique()), &wallet, None, tx)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("co-signed swap tx; it cannot be re-signed"), "{err}");
+    }
+
+    /// A memo paid for by `wallet` that `co_signer` must also sign.
+    fn co_signed(wallet: &Keypair, co_signer: &Keypair, blockhash: Hash) -> VersionedMessage {
+        let ix = Instruction::new_with_bytes(
+            Pubkey::from_str(MEMO_PROGRAM_ID).unwrap(),
+            b"co-signed",
+            vec![AccountMeta::new_readonly(co_signer.pubkey(), true)],
+        );
+        VersionedMessage::Legacy(Message::new_with_blockhash(&[ix], Some(&wallet.pubkey()), &blockhash))
+    }
+
+    #[tokio::test]
+    async fn co_signer_signature_is_kept() {
+        let (wallet, co_signer) = (Keypair::new(), Keypair::new());
+        let original = Hash::new_unique();
+        let mut tx = unsigned(co_signed(&wallet, &co_signer, original));
+        tx.signatures[1] = co_signer.sign_message(&tx.message.serialize());
+        let co_signature = tx.signatures[1];
+
+        let tx = sign_tx(&latest_blockhash(Hash::new_unique()), &[&wallet], tx).await.unwrap();
+        assert_eq!(*tx.message.recent_blockhash(), original);
+        assert_eq!(tx.signatures[1], co_signature);
+        assert!(tx.verify_with_results().iter().all(|ok| *ok));
+    }
+
+    #[tokio::test]
+    async fn missing_co_signer_is_an_error() {
+        let (wallet, co_signer) = (Keypair::new(), Keypair::new());
+        let tx = unsigned(co_signed(&wallet, &co_signer, Hash::new_unique()));
+
+        let err = sign_tx(&latest_blockhash(Hash::new_unique()), &[&wallet], tx).await.unwrap_err().to_string();
+        assert!(err.contains("expects 2 signers but only 1 are ours"), "{err}");
+        assert!(err.contains(&co_signer.pubkey().to_string()), "{err}");
+    }
+
+    #[tokio::test]
+    async fn tx_not_signed_by_us_is_an_error() {
+        let (wallet, stranger) = (Keypair::new(), Keypair::new());
+        let ix = system_instruction::transfer(&stranger.pubkey(), &Pubkey::new_unique(), 1);
+        let tx = unsigned(VersionedMessage::Legacy(Message::new(&[ix], Some(&stranger.pubkey()))));
+
+        let err = sign_tx(&latest_blockhash(Hash::new_unique()), &[&wallet], tx).await.unwrap_err().to_string();
+        assert!(err.contains(&format!("does not list our key {}", wallet.pubkey())), "{err}");
+    }
+
+    fn signed_transfer() -> VersionedTransaction {
+        let payer = Keypair::new();
+        let ix = system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1);
+        let message = Message::new_with_blockhash(&[ix], Some(&payer.pubkey()), &Hash::new_unique());
+        VersionedTransaction::try_new(VersionedMessage::Legacy(message), &[&payer]).unwrap()
+    }
+
+    /// An RPC client whose `simulateTransaction` returns `err` and `logs`.
+    fn simulating(err: serde_json::Value, logs: &[&str]) -> AsyncRpcClient {
+        use solana_client::rpc_request::RpcRequest;
+        let response = json!({
+            "context": { "slot": 1 },
+            "value": { "err": err, "logs": logs, "accounts": null, "unitsConsumed": 42_000, "returnData": null },
+        });
+        AsyncRpcClient::new_mock_with_mocks("succeeds".to_string(), [(RpcRequest::SimulateTransaction, response)].into())
+    }
+
+    #[tokio::test]
+    async fn simulation_success_returns_the_compute_units() {
+        let rpc = simulating(serde_json::Value::Null, &["Program log: Instruction: Route"]);
+        assert_eq!(simulate_signed_swap(&rpc, &signed_transfer()).await.unwrap(), Some(42_000));
+    }
+
+    #[tokio::test]
+    async fn simulation_error_fails() {
+        let rpc = simulating(json!("AccountNotFound"), &[]);
+        let err = simulate_signed_swap(&rpc, &signed_transfer()).await.unwrap_err();
+        assert!(matches!(&err, BotError::SimulationFailed(msg) if msg.contains("account")), "{err}");
+    }
+
+    #[tokio::test]
+    async fn simulation_failure_logs_fail_without_an_error() {
+        for line in [
+            "Program log: Error: insufficient funds",
+            "Program log: AnchorError occurred. Error Code: SlippageToleranceExceeded. Error Message: Slippage tolerance exceeded.",
+        ] {
+            let rpc = simulating(serde_json::Value::Null, &["Program log: Instruction: Route", line]);
+            let err = simulate_signed_swap(&rpc, &signed_transfer()).await.unwrap_err();
+            assert!(matches!(&err, BotError::SimulationFailed(msg) if msg == line), "{err}");
+        }
+    }
+
+    #[test]
+    fn builder_orders_instructions_by_stage() {
+        use crate::dex::raydium::create_ata_idempotent;
+        let payer = Pubkey::new_unique();
+        let (mint, ata, tip_account) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let token_program = Pubkey::from_str(crate::common::utils::TOKEN_PROGRAM_ID).unwrap();
+        let swap = Instruction::new_with_bytes(Pubkey::from_str(JUPITER_PROGRAM_ID).unwrap(), &[1], vec![]);
+        let swap_2 = Instruction::new_with_bytes(Pubkey::from_str(JUPITER_PROGRAM_ID).unwrap(), &[2], vec![]);
+        let setup = create_ata_idempotent(&payer, &ata, &mint, &token_program).unwrap();
+        let wrap = system_instruction::transfer(&payer, &ata, 10);
+
+        // Added out of order on purpose
+        let builder = TransactionBuilder::new(payer)
+            .tip(&tip_account, 1_000)
+            .memo(&payer, "mirror")
+            .unwrap()
+            .push(IxStage::Swap, swap)
+            .push(IxStage::Swap, swap_2)
+            .push(IxStage::Setup, wrap)
+            .prepend(IxStage::Setup, setup.clone())
+            .compute_unit_limit(100_000)
+            .priority_fee_lamports(5_000);
+        let ixs = builder.instructions();
+        let programs: Vec<String> = ixs.iter().map(|ix| ix.program_id.to_string()).collect();
+        let compute_budget = solana_sdk::compute_budget::id().to_string();
+        let system = solana_sdk::system_program::id().to_string();
+        let ata_program = setup.program_id.to_string();
+        assert_eq!(
+            programs,
+            [
+                &compute_budget,
+                &compute_budget,
+                &ata_program,
+                &system,
+                JUPITER_PROGRAM_ID,
+                JUPITER_PROGRAM_ID,
+                MEMO_PROGRAM_ID,
+                &system,
+            ]
+        );
+        assert_eq!(ixs[0], ComputeBudgetInstruction::set_compute_unit_limit(100_000));
+        // 5000 lamports over 100k units = 50_000 micro-lamports per unit
+        assert_eq!(ixs[1], ComputeBudgetInstruction::set_compute_unit_price(50_000));
+        assert_eq!((ixs[4].data.as_slice(), ixs[5].data.as_slice()), (&[1u8][..], &[2u8][..]));
+        assert_eq!(ixs[7], system_instruction::transfer(&payer, &tip_account, 1_000));
+
+        // No limit or fee: no compute-budget instructions at all
+        let bare = TransactionBuilder::new(payer).tip(&tip_account, 1).instructions();
+        assert_eq!(bare.len(), 1);
+    }
+
+    #[test]
+    fn builder_refuses_transactions_over_the_packet_size() {
+        let payer = Pubkey::new_unique();
+        let fits = TransactionBuilder::new(payer).memo(&payer, &"x".repeat(800)).unwrap();
+        let tx: VersionedTransaction = bincode::deserialize(&B64.decode(fits.build_base64().unwrap()).unwrap()).unwrap();
+        assert!(bincode::serialized_size(&tx).unwrap() as usize <= PACKET_DATA_SIZE);
+        assert_eq!(tx.message.static_account_keys()[0], payer);
+        assert_eq!(tx.signatures.len(), 1);
+
+        let too_big = TransactionBuilder::new(payer).memo(&payer, &"x".repeat(PACKET_DATA_SIZE)).unwrap();
+        let err = too_big.build().unwrap_err().to_string();
+        assert!(err.contains(&format!("over the {PACKET_DATA_SIZE}-byte limit")), "{err}");
+    }
+
+    /// A `/quote` response as the hosted v6 API returns it: 0.1 SOL into USDC at 50 bps.
+    const QUOTE_PAYLOAD: &str = r#"{
+        "inputMint": "So11111111111111111111111111111111111111112",
+        "inAmount": "100000000",
+        "outputMint": "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v",
+        "outAmount": "14893563",
+        "otherAmountThreshold": "14819095",
+        "swapMode": "ExactIn",
+        "slippageBps": 50,
+        "platformFee": null,
+        "priceImpactPct": "0.0001203",
+        "routePlan": [
+            {
+                "swapInfo": {
+                    "ammKey": "Czfq3xZZDmsdGdUyrNLtRhGc47cXcZtLG4crryfu44zE",
+                    "label": "Orca V2",
+                    "inputMint": "So11111111111111111111111111111111111111112",
+                    "outputMint": "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v",
+                    "inAmount": "100000000",
+                    "outAmount": "14893563",
+                    "feeAmount": "300000",
+                    "feeMint": "So11111111111111111111111111111111111111112"
+                },
+                "percent": 100
+            }
+        ],
+        "contextSlot": 299283763,
+        "timeTaken": 0.003281225
+    }"#;
+
+    #[test]
+    fn real_quote_payload_validates_and_round_trips() {
+        let quote: QuoteResponse = serde_json::from_str(QUOTE_PAYLOAD).unwrap();
+        quote.validate(SwapMode::ExactIn, 50).unwrap();
+        assert_eq!(quote.quoted_amount(), 14_893_563);
+        assert_eq!(quote.min_out_amount(), 14_819_095);
+        assert!((quote.price_impact_pct().unwrap() - 0.01203).abs() < 1e-9);
+        assert_eq!(quote.pool_accounts(), vec![Pubkey::from_str("Czfq3xZZDmsdGdUyrNLtRhGc47cXcZtLG4crryfu44zE").unwrap()]);
+
+        // /swap gets the quote back exactly as it came
+        let original: serde_json::Value = serde_json::from_str(QUOTE_PAYLOAD).unwrap();
+        assert_eq!(serde_json::to_value(&quote).unwrap(), original);
+    }
+
+    #[test]
+    fn validate_rejects_mismatched_or_loose_quotes() {
+        let quote = |patch: serde_json::Value| -> QuoteResponse {
+            let mut raw: serde_json::Value = serde_json::from_str(QUOTE_PAYLOAD).unwrap();
+            raw.as_object_mut().unwrap().extend(patch.as_object().unwrap().clone());
+            serde_json::from_value(raw).unwrap()
+        };
+        let err = |q: QuoteResponse, bps| q.validate(SwapMode::ExactIn, bps).unwrap_err().to_string();
+
+        assert!(err(quote(json!({ "swapMode": "ExactOut" })), 50).contains("asked for ExactIn"));
+        assert!(err(quote(json!({ "outAmount": "0" })), 50).contains("at nothing"));
+        // A threshold that allows 1% slippage when 0.5% was asked for
+        assert!(err(quote(json!({ "otherAmountThreshold": "14744627" })), 50).contains("outside"));
+        quote(json!({ "otherAmountThreshold": "14744627" })).validate(SwapMode::ExactIn, 100).unwrap();
+    }
+
+    #[tokio::test]
+    async fn client_sends_the_api_key_to_paths_under_the_base_url() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v6/quote"))
+            .and(header("x-api-key", "secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(quote_json()))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/v6/swap"))
+            .and(header("x-api-key", "secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "swapTransaction": "AQID" })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // A trailing slash on the base URL must not double up
+        let base_url = format!(" {}/v6/ ", server.uri());
+        let client = JupiterClient::new(http(), &base_url, Some("secret".to_string()), no_retries());
+        assert_eq!(client.base_url(), format!("{}/v6", server.uri()));
+
+        let quote = client.quote(SOL_MINT, USDC_MINT, 1_000_000, 50, SwapMode::ExactIn).await.unwrap();
+        assert_eq!(quote.out_amount(), 5000);
+        let swap = client.swap_tx(quote, Pubkey::new_unique(), 0).await.unwrap();
+        assert_eq!(swap.swap_transaction, "AQID");
+    }
+
+    #[tokio::test]
+    async fn client_without_a_key_sends_no_key_header() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/quote"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(quote_json()))
+            .mount(&server)
+            .await;
+
+        let client = JupiterClient::new(http(), &server.uri(), None, no_retries());
+        client.quote(SOL_MINT, USDC_MINT, 1_000_000, 50, SwapMode::ExactIn).await.unwrap();
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 1);
+        assert!(!requests[0].headers.contains_key("x-api-key"));
+    }
 }
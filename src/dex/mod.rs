@@ -1 +1,6 @@
+pub mod jito;
 pub mod jupiter;
+pub mod priority_fee;
+pub mod pumpfun;
+pub mod raydium;
+pub mod tx_guard;
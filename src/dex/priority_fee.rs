@@ -0,0 +1,116 @@
+use crate::common::utils::{env_u64, env_var_opt};
+use anyhow::{anyhow, Result};
+use solana_client::nonblocking::rpc_client::RpcClient as AsyncRpcClient;
+use solana_sdk::pubkey::Pubkey;
+use tracing::{debug, warn};
+
+/// A resolved priority fee.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeQuote {
+    /// What the swap will pay, after the cap.
+    pub lamports: u64,
+    /// The market estimate before capping (auto mode only); a spike signals congestion.
+    pub estimate_lamports: Option<u64>,
+}
+
+/// Priority fee passed to Jupiter as `prioritizationFeeLamports`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriorityFee {
+    /// `PRIORITY_FEE_LAMPORTS` on every swap (0 = none, the original behavior).
+    Fixed(u64),
+    /// `PRIORITY_FEE_MODE=auto`: a percentile of recent fees paid on the swap's pools.
+    Auto {
+        percentile: u8,
+        /// Compute units the fee is priced for (`PRIORITY_FEE_COMPUTE_UNITS`).
+        compute_units: u64,
+        /// Ceiling on the total fee (`PRIORITY_FEE_MAX_LAMPORTS`).
+        max_lamports: u64,
+        /// Used when the RPC query fails (`PRIORITY_FEE_LAMPORTS`).
+        fallback_lamports: u64,
+    },
+}
+
+impl PriorityFee {
+    pub fn from_env() -> Result<Self> {
+        let fixed = env_u64("PRIORITY_FEE_LAMPORTS", 0);
+        let mode = env_var_opt("PRIORITY_FEE_MODE").unwrap_or_else(|| "fixed".to_string());
+        match mode.trim().to_lowercase().as_str() {
+            "fixed" | "" => Ok(PriorityFee::Fixed(fixed)),
+            "auto" => {
+                let percentile = env_u64("PRIORITY_FEE_PERCENTILE", 75);
+                if !(1..=100).contains(&percentile) {
+                    return Err(anyhow!("PRIORITY_FEE_PERCENTILE must be between 1 and 100, got {percentile}"));
+                }
+                Ok(PriorityFee::Auto {
+                    percentile: percentile as u8,
+                    compute_units: env_u64("PRIORITY_FEE_COMPUTE_UNITS", 200_000),
+                    max_lamports: env_u64("PRIORITY_FEE_MAX_LAMPORTS", 1_000_000),
+                    fallback_lamports: fixed,
+                })
+            }
+            other => Err(anyhow!("Unknown PRIORITY_FEE_MODE '{other}' (expected fixed|auto)")),
+        }
+    }
+
+    /// Total priority fee in lamports for a swap that write-locks `accounts`.
+    ///
+    /// In auto mode this asks `getRecentPrioritizationFees` what txs touching those
+    /// accounts paid per compute unit over the last ~150 slots, takes the configured
+    /// percentile and prices `compute_units` at it. A higher percentile lands sooner when
+    /// the pools are contended but overpays when they are quiet; slots without any
+    /// prioritized tx count as 0, so a low percentile often resolves to no fee at all.
+    /// The result is capped at `max_lamports`; RPC errors fall back to the fixed fee.
+    pub async fn lamports_for(&self, rpc: &AsyncRpcClient, accounts: &[Pubkey]) -> u64 {
+        self.quote(rpc, accounts).await.lamports
+    }
+
+    /// Like `lamports_for`, also reporting the uncapped market estimate.
+    pub async fn quote(&self, rpc: &AsyncRpcClient, accounts: &[Pubkey]) -> FeeQuote {
+        let fixed = |lamports| FeeQuote {
+            lamports,
+            estimate_lamports: None,
+        };
+        let (percentile, compute_units, max_lamports, fallback_lamports) = match *self {
+            PriorityFee::Fixed(lamports) => return fixed(lamports),
+            PriorityFee::Auto {
+                percentile,
+                compute_units,
+                max_lamports,
+                fallback_lamports,
+            } => (percentile, compute_units, max_lamports, fallback_lamports),
+        };
+
+        let fees = match rpc.get_recent_prioritization_fees(accounts).await {
+            Ok(fees) => fees,
+            Err(e) => {
+                warn!("getRecentPrioritizationFees failed: {e}; using PRIORITY_FEE_LAMPORTS={fallback_lamports}");
+                return fixed(fallback_lamports);
+            }
+        };
+        let mut per_cu: Vec<u64> = fees.iter().map(|f| f.prioritization_fee).collect();
+        let Some(micro_lamports) = percentile_of(&mut per_cu, percentile) else {
+            return fixed(fallback_lamports);
+        };
+
+        let lamports = (micro_lamports as u128 * compute_units as u128 / 1_000_000) as u64;
+        let capped = lamports.min(max_lamports);
+        debug!(
+            "Priority fee: p{percentile} of {} recent slots = {micro_lamports} micro-lamports/CU -> {capped} lamports",
+            per_cu.len()
+        );
+        FeeQuote {
+            lamports: capped,
+            estimate_lamports: Some(lamports),
+        }
+    }
+}
+
+/// Nearest-rank percentile; `None` for an empty sample.
+fn percentile_of(values: &mut [u64], percentile: u8) -> Option<u64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_unstable();
+    let rank = (percentile as usize * values.len()).div_ceil(100).max(1);
+    values.get(rank - 1).copied()
+}
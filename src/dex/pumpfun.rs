@@ -1 +1,180 @@
-//PumpFun is a DEX on Solana that allows you to trade tokens with other users!
\ No newline at end of file
+//PumpFun is a DEX on Solana that allows you to trade tokens with other users!
+use crate::dex::jupiter::{IxStage, TransactionBuilder};
+use crate::dex::raydium::{associated_token_address, create_ata_idempotent};
+use anyhow::{anyhow, Result};
+use solana_client::nonblocking::rpc_client::RpcClient as AsyncRpcClient;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_program,
+};
+use std::str::FromStr;
+use tracing::debug;
+
+/// Pump.fun bonding-curve program.
+pub const PUMPFUN_PROGRAM_ID: &str = "6EF8rrecthR5Dkzon8Nwu78hRvfCKubJ14M5uBEwF6P";
+/// Pump.fun fee program, owner of the `fee_config` account buys pass along.
+const FEE_PROGRAM_ID: &str = "pfeeUxB6jkeY1Hxd7CsFCAjcbHA9rWtchMGdZ6VojVZ";
+
+/// Anchor discriminator of the bonding curve's `buy` instruction: `sha256("global:buy")[..8]`.
+pub const PUMPFUN_BUY_DISCRIMINATOR: [u8; 8] = [102, 6, 61, 18, 1, 218, 235, 234];
+/// `sha256("global:sell")[..8]`.
+pub const PUMPFUN_SELL_DISCRIMINATOR: [u8; 8] = [51, 230, 133, 164, 1, 127, 131, 173];
+
+/// Bonding curve account: 8-byte discriminator, `virtual_token_reserves`,
+/// `virtual_sol_reserves`, `real_token_reserves`, `real_sol_reserves`, `token_total_supply`
+/// (u64 each), `complete: bool`, then the creator.
+const CURVE_VIRTUAL_TOKEN_RESERVES: usize = 8;
+const CURVE_VIRTUAL_SOL_RESERVES: usize = 16;
+const CURVE_REAL_TOKEN_RESERVES: usize = 24;
+const CURVE_COMPLETE: usize = 48;
+const CURVE_CREATOR: usize = 49;
+/// Global account: 8-byte discriminator, `initialized: bool`, authority, then the fee recipient.
+const GLOBAL_FEE_RECIPIENT: usize = 41;
+
+/// Protocol plus creator fee the curve charges on top of a buy's SOL cost.
+const FEE_BPS: u64 = 125;
+/// Compute units requested for a buy with its token account setup.
+const BUY_COMPUTE_UNITS: u32 = 150_000;
+
+/// A Pump.fun bonding curve: the token trades here until it `complete`s and graduates to
+/// an AMM, which is when Jupiter can route it.
+#[derive(Debug, Clone)]
+pub struct BondingCurve {
+    pub address: Pubkey,
+    pub virtual_token_reserves: u64,
+    pub virtual_sol_reserves: u64,
+    pub real_token_reserves: u64,
+    pub complete: bool,
+    pub creator: Pubkey,
+}
+
+impl BondingCurve {
+    /// Raw tokens `lamports` buys after the fee, on the curve's constant product of
+    /// virtual reserves, capped at the tokens left on the curve.
+    pub fn tokens_out(&self, lamports: u64) -> u64 {
+        let net = lamports as u128 * 10_000 / (10_000 + FEE_BPS) as u128;
+        let out = self.virtual_token_reserves as u128 * net / (self.virtual_sol_reserves as u128 + net).max(1);
+        (out as u64).min(self.real_token_reserves)
+    }
+}
+
+/// A bonding-curve buy: an unsigned transaction in the same base64 form as Jupiter's
+/// `swapTransaction`, the tokens it asks for and the most SOL it may cost.
+#[derive(Debug, Clone)]
+pub struct PumpfunSwap {
+    pub bonding_curve: Pubkey,
+    pub swap_transaction: String,
+    pub out_amount: u64,
+    pub max_sol_cost: u64,
+}
+
+fn program() -> Result<Pubkey> {
+    Ok(Pubkey::from_str(PUMPFUN_PROGRAM_ID)?)
+}
+
+fn pda(seeds: &[&[u8]], program_id: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(seeds, program_id).0
+}
+
+/// The bonding curve of `mint`. Fails if `mint` was not launched on Pump.fun.
+pub async fn load_curve(rpc: &AsyncRpcClient, mint: &Pubkey) -> Result<BondingCurve> {
+    let address = pda(&[b"bonding-curve", mint.as_ref()], &program()?);
+    let data = rpc
+        .get_account_data(&address)
+        .await
+        .map_err(|e| anyhow!("No Pump.fun bonding curve for {mint}: {e}"))?;
+    let u64_at = |at: usize| -> Result<u64> {
+        let bytes = data.get(at..at + 8).ok_or_else(|| anyhow!("Bonding curve {address} data too short"))?;
+        Ok(u64::from_le_bytes(bytes.try_into()?))
+    };
+    let creator = data
+        .get(CURVE_CREATOR..CURVE_CREATOR + 32)
+        .ok_or_else(|| anyhow!("Bonding curve {address} has no creator"))?;
+    Ok(BondingCurve {
+        address,
+        virtual_token_reserves: u64_at(CURVE_VIRTUAL_TOKEN_RESERVES)?,
+        virtual_sol_reserves: u64_at(CURVE_VIRTUAL_SOL_RESERVES)?,
+        real_token_reserves: u64_at(CURVE_REAL_TOKEN_RESERVES)?,
+        complete: data.get(CURVE_COMPLETE).is_some_and(|b| *b != 0),
+        creator: Pubkey::try_from(creator)?,
+    })
+}
+
+/// Builds a buy of `mint` for `lamports` on its bonding curve, for a token that has not
+/// graduated yet. The token amount is the curve's quote for `lamports`; the buy fails
+/// on-chain rather than cost more than `lamports` plus `slippage_bps`.
+pub async fn pumpfun_buy(
+    rpc: &AsyncRpcClient,
+    owner: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    lamports: u64,
+    slippage_bps: u16,
+    priority_fee_lamports: u64,
+) -> Result<PumpfunSwap> {
+    let curve = load_curve(rpc, mint).await?;
+    if curve.complete {
+        return Err(anyhow!("Pump.fun token {mint} has graduated; its bonding curve is closed"));
+    }
+    let out_amount = curve.tokens_out(lamports);
+    if out_amount == 0 {
+        return Err(anyhow!("Bonding curve {} quotes 0 tokens for {lamports} lamports", curve.address));
+    }
+    let max_sol_cost = (lamports as u128 * (10_000 + slippage_bps as u128) / 10_000) as u64;
+
+    let program = program()?;
+    let global = pda(&[b"global"], &program);
+    let global_data = rpc.get_account_data(&global).await?;
+    let fee_recipient = global_data
+        .get(GLOBAL_FEE_RECIPIENT..GLOBAL_FEE_RECIPIENT + 32)
+        .ok_or_else(|| anyhow!("Pump.fun global account too short"))?;
+    let fee_recipient = Pubkey::try_from(fee_recipient)?;
+    debug!(
+        "Pump.fun curve {}: {lamports} lamports -> {out_amount} tokens (max cost {max_sol_cost}), fee recipient {fee_recipient}",
+        curve.address
+    );
+
+    let user_ata = associated_token_address(owner, mint, token_program)?;
+    let fee_program = Pubkey::from_str(FEE_PROGRAM_ID)?;
+    let mut data = PUMPFUN_BUY_DISCRIMINATOR.to_vec();
+    data.extend_from_slice(&out_amount.to_le_bytes());
+    data.extend_from_slice(&max_sol_cost.to_le_bytes());
+    let buy = Instruction::new_with_bytes(
+        program,
+        &data,
+        vec![
+            AccountMeta::new_readonly(global, false),
+            AccountMeta::new(fee_recipient, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new(curve.address, false),
+            // The curve's own token account holding the unsold supply
+            AccountMeta::new(associated_token_address(&curve.address, mint, token_program)?, false),
+            AccountMeta::new(user_ata, false),
+            AccountMeta::new(*owner, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(*token_program, false),
+            // Receives the creator's share of the fee
+            AccountMeta::new(pda(&[b"creator-vault", curve.creator.as_ref()], &program), false),
+            AccountMeta::new_readonly(pda(&[b"__event_authority"], &program), false),
+            AccountMeta::new_readonly(program, false),
+            AccountMeta::new(pda(&[b"global_volume_accumulator"], &program), false),
+            AccountMeta::new(pda(&[b"user_volume_accumulator", owner.as_ref()], &program), false),
+            AccountMeta::new_readonly(pda(&[b"fee_config", program.as_ref()], &fee_program), false),
+            AccountMeta::new_readonly(fee_program, false),
+        ],
+    );
+
+    let swap_transaction = TransactionBuilder::new(*owner)
+        .compute_unit_limit(BUY_COMPUTE_UNITS)
+        .priority_fee_lamports(priority_fee_lamports)
+        .push(IxStage::Setup, create_ata_idempotent(owner, &user_ata, mint, token_program)?)
+        .push(IxStage::Swap, buy)
+        .build_base64()?;
+    Ok(PumpfunSwap {
+        bonding_curve: curve.address,
+        swap_transaction,
+        out_amount,
+        max_sol_cost,
+    })
+}
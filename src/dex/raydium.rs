@@ -0,0 +1,324 @@
+use crate::common::utils::TOKEN_PROGRAM_ID;
+use crate::dex::jupiter::{IxStage, TransactionBuilder, SOL_MINT};
+use anyhow::{anyhow, Result};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::nonblocking::rpc_client::RpcClient as AsyncRpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_client::rpc_filter::{Memcmp, RpcFilterType};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    system_instruction, system_program,
+};
+use std::str::FromStr;
+use tracing::debug;
+
+/// Raydium AMM v4 (constant product, OpenBook market attached).
+pub const RAYDIUM_AMM_V4: &str = "675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8";
+/// PDA (`"amm authority"`) owning every v4 pool's vaults.
+const AMM_AUTHORITY: &str = "5Q544fKrFoe6tsEbD7S8EmxGTJYAKtTVhAW5Q5pge4j1";
+pub(crate) const ASSOCIATED_TOKEN_PROGRAM_ID: &str = "ATokenGPvbdGVxr1b2hvZbsiqW1Tx2bbnZdnzzVBT4bX";
+
+/// Size of an AMM v4 pool state account.
+const POOL_STATE_LEN: u64 = 752;
+/// Pool state offsets (`LIQUIDITY_STATE_LAYOUT_V4`).
+const SWAP_FEE_NUMERATOR: usize = 176;
+const SWAP_FEE_DENOMINATOR: usize = 184;
+const BASE_NEED_TAKE_PNL: usize = 192;
+const QUOTE_NEED_TAKE_PNL: usize = 200;
+const BASE_VAULT: usize = 336;
+const QUOTE_VAULT: usize = 368;
+const BASE_MINT: usize = 400;
+const QUOTE_MINT: usize = 432;
+const OPEN_ORDERS: usize = 496;
+const MARKET_ID: usize = 528;
+const MARKET_PROGRAM_ID: usize = 560;
+const TARGET_ORDERS: usize = 592;
+/// OpenBook market offsets (`MARKET_STATE_LAYOUT_V3`).
+const MARKET_VAULT_SIGNER_NONCE: usize = 45;
+const MARKET_BASE_VAULT: usize = 117;
+const MARKET_QUOTE_VAULT: usize = 165;
+const MARKET_EVENT_QUEUE: usize = 253;
+const MARKET_BIDS: usize = 285;
+const MARKET_ASKS: usize = 317;
+/// SPL token account: `amount` follows the 32-byte mint and owner.
+const TOKEN_ACCOUNT_AMOUNT: usize = 64;
+
+/// `SwapBaseIn` instruction tag.
+const SWAP_BASE_IN: u8 = 9;
+/// Compute units requested for a swap with its account setup.
+const SWAP_COMPUTE_UNITS: u32 = 200_000;
+
+/// A SOL-paired AMM v4 pool with everything a swap against it needs.
+#[derive(Debug, Clone)]
+pub struct RaydiumPool {
+    pub id: Pubkey,
+    pub base_mint: Pubkey,
+    pub quote_mint: Pubkey,
+    base_vault: Pubkey,
+    quote_vault: Pubkey,
+    open_orders: Pubkey,
+    target_orders: Pubkey,
+    market_program: Pubkey,
+    market: Pubkey,
+    bids: Pubkey,
+    asks: Pubkey,
+    event_queue: Pubkey,
+    market_base_vault: Pubkey,
+    market_quote_vault: Pubkey,
+    vault_signer: Pubkey,
+    /// Tradable reserves: vault balances less the protocol's untaken PnL.
+    pub base_reserve: u64,
+    pub quote_reserve: u64,
+    fee_numerator: u64,
+    fee_denominator: u64,
+}
+
+impl RaydiumPool {
+    /// Raw `input_mint` units out for `amount_in`, by the constant-product formula after
+    /// the pool's swap fee.
+    pub fn amount_out(&self, input_mint: &Pubkey, amount_in: u64) -> u64 {
+        let (reserve_in, reserve_out) = if *input_mint == self.base_mint {
+            (self.base_reserve, self.quote_reserve)
+        } else {
+            (self.quote_reserve, self.base_reserve)
+        };
+        let fee_den = self.fee_denominator.max(1) as u128;
+        let in_after_fee = amount_in as u128 * (fee_den - (self.fee_numerator as u128).min(fee_den)) / fee_den;
+        let denominator = reserve_in as u128 + in_after_fee;
+        if denominator == 0 {
+            return 0;
+        }
+        (reserve_out as u128 * in_after_fee / denominator) as u64
+    }
+
+    fn sol_reserve(&self) -> u64 {
+        if self.base_mint.to_string() == SOL_MINT { self.base_reserve } else { self.quote_reserve }
+    }
+}
+
+/// A swap built without Jupiter: an unsigned transaction (same base64 form as Jupiter's
+/// `swapTransaction`, so the usual sign and send path applies) and its expected output.
+#[derive(Debug, Clone)]
+pub struct RaydiumSwap {
+    pub pool: Pubkey,
+    pub swap_transaction: String,
+    pub out_amount: u64,
+    pub min_out_amount: u64,
+}
+
+/// Builds a swap of `amount_in` raw units of `input_mint` into `output_mint` (one of them
+/// SOL) against the deepest AMM v4 pool for the pair. SOL is wrapped into a temporary wSOL
+/// account that is closed again afterwards. `min_out_amount` is the constant-product
+/// quote less `slippage_bps`.
+pub async fn raydium_swap(
+    rpc: &AsyncRpcClient,
+    owner: &Pubkey,
+    input_mint: &Pubkey,
+    output_mint: &Pubkey,
+    amount_in: u64,
+    slippage_bps: u16,
+    priority_fee_lamports: u64,
+) -> Result<RaydiumSwap> {
+    let sol = Pubkey::from_str(SOL_MINT)?;
+    let token = match (*input_mint == sol, *output_mint == sol) {
+        (true, false) => output_mint,
+        (false, true) => input_mint,
+        _ => return Err(anyhow!("Raydium fallback only swaps between SOL and a token")),
+    };
+    let pool = find_pool(rpc, token).await?;
+    let out_amount = pool.amount_out(input_mint, amount_in);
+    if out_amount == 0 {
+        return Err(anyhow!("Raydium pool {} quotes 0 out", pool.id));
+    }
+    let min_out_amount = (out_amount as u128 * (10_000 - slippage_bps.min(10_000)) as u128 / 10_000) as u64;
+    debug!(
+        "Raydium pool {}: {amount_in} in -> {out_amount} out (min {min_out_amount}), reserves {}/{}",
+        pool.id, pool.base_reserve, pool.quote_reserve
+    );
+
+    let builder = swap_builder(&pool, owner, input_mint, output_mint, amount_in, min_out_amount)?
+        .priority_fee_lamports(priority_fee_lamports);
+    Ok(RaydiumSwap {
+        pool: pool.id,
+        swap_transaction: builder.build_base64()?,
+        out_amount,
+        min_out_amount,
+    })
+}
+
+/// The SOL pool for `token` with the largest SOL reserve.
+pub async fn find_pool(rpc: &AsyncRpcClient, token: &Pubkey) -> Result<RaydiumPool> {
+    let program = Pubkey::from_str(RAYDIUM_AMM_V4)?;
+    let sol = Pubkey::from_str(SOL_MINT)?;
+    let mut candidates = Vec::new();
+    for (base, quote) in [(token, &sol), (&sol, token)] {
+        let config = RpcProgramAccountsConfig {
+            filters: Some(vec![
+                RpcFilterType::DataSize(POOL_STATE_LEN),
+                RpcFilterType::Memcmp(Memcmp::new_base58_encoded(BASE_MINT, base.as_ref())),
+                RpcFilterType::Memcmp(Memcmp::new_base58_encoded(QUOTE_MINT, quote.as_ref())),
+            ]),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                ..Default::default()
+            },
+            with_context: None,
+        };
+        candidates.extend(rpc.get_program_accounts_with_config(&program, config).await?);
+    }
+
+    let mut best: Option<RaydiumPool> = None;
+    for (id, account) in candidates {
+        let pool = match load_pool(rpc, id, &account.data).await {
+            Ok(pool) => pool,
+            Err(e) => {
+                debug!("Raydium pool {id} skipped: {e}");
+                continue;
+            }
+        };
+        if best.as_ref().is_none_or(|b| pool.sol_reserve() > b.sol_reserve()) {
+            best = Some(pool);
+        }
+    }
+    best.ok_or_else(|| anyhow!("No Raydium AMM v4 pool pairs {token} with SOL"))
+}
+
+async fn load_pool(rpc: &AsyncRpcClient, id: Pubkey, data: &[u8]) -> Result<RaydiumPool> {
+    let key = |at: usize| -> Result<Pubkey> {
+        Pubkey::try_from(data.get(at..at + 32).ok_or_else(|| anyhow!("pool state too short"))?)
+            .map_err(|e| anyhow!("bad key at {at}: {e}"))
+    };
+    let base_vault = key(BASE_VAULT)?;
+    let quote_vault = key(QUOTE_VAULT)?;
+    let market = key(MARKET_ID)?;
+    let market_program = key(MARKET_PROGRAM_ID)?;
+
+    let accounts = rpc.get_multiple_accounts(&[base_vault, quote_vault, market]).await?;
+    let [Some(base), Some(quote), Some(market_account)] = accounts.as_slice() else {
+        return Err(anyhow!("vault or market account missing"));
+    };
+    let m = &market_account.data;
+    let market_key = |at: usize| -> Result<Pubkey> {
+        Pubkey::try_from(m.get(at..at + 32).ok_or_else(|| anyhow!("market state too short"))?)
+            .map_err(|e| anyhow!("bad market key at {at}: {e}"))
+    };
+    let nonce = u64_at(m, MARKET_VAULT_SIGNER_NONCE)?;
+    let vault_signer = Pubkey::create_program_address(&[market.as_ref(), &nonce.to_le_bytes()], &market_program)
+        .map_err(|e| anyhow!("vault signer: {e}"))?;
+
+    Ok(RaydiumPool {
+        id,
+        base_mint: key(BASE_MINT)?,
+        quote_mint: key(QUOTE_MINT)?,
+        base_vault,
+        quote_vault,
+        open_orders: key(OPEN_ORDERS)?,
+        target_orders: key(TARGET_ORDERS)?,
+        market_program,
+        market,
+        bids: market_key(MARKET_BIDS)?,
+        asks: market_key(MARKET_ASKS)?,
+        event_queue: market_key(MARKET_EVENT_QUEUE)?,
+        market_base_vault: market_key(MARKET_BASE_VAULT)?,
+        market_quote_vault: market_key(MARKET_QUOTE_VAULT)?,
+        vault_signer,
+        base_reserve: u64_at(&base.data, TOKEN_ACCOUNT_AMOUNT)?.saturating_sub(u64_at(data, BASE_NEED_TAKE_PNL)?),
+        quote_reserve: u64_at(&quote.data, TOKEN_ACCOUNT_AMOUNT)?.saturating_sub(u64_at(data, QUOTE_NEED_TAKE_PNL)?),
+        fee_numerator: u64_at(data, SWAP_FEE_NUMERATOR)?,
+        fee_denominator: u64_at(data, SWAP_FEE_DENOMINATOR)?,
+    })
+}
+
+fn u64_at(data: &[u8], at: usize) -> Result<u64> {
+    let bytes = data.get(at..at + 8).ok_or_else(|| anyhow!("account data too short for offset {at}"))?;
+    Ok(u64::from_le_bytes(bytes.try_into()?))
+}
+
+/// Account setup (wSOL wrap, output account), the `SwapBaseIn` itself and the wSOL
+/// account close that returns the SOL.
+fn swap_builder(
+    pool: &RaydiumPool,
+    owner: &Pubkey,
+    input_mint: &Pubkey,
+    output_mint: &Pubkey,
+    amount_in: u64,
+    min_out_amount: u64,
+) -> Result<TransactionBuilder> {
+    let token_program = Pubkey::from_str(TOKEN_PROGRAM_ID)?;
+    let sol = Pubkey::from_str(SOL_MINT)?;
+    let source = associated_token_address(owner, input_mint, &token_program)?;
+    let destination = associated_token_address(owner, output_mint, &token_program)?;
+    let wsol = if *input_mint == sol { source } else { destination };
+
+    let mut builder = TransactionBuilder::new(*owner).compute_unit_limit(SWAP_COMPUTE_UNITS);
+    if *input_mint == sol {
+        builder = builder
+            .push(IxStage::Setup, create_ata_idempotent(owner, &source, input_mint, &token_program)?)
+            .push(IxStage::Setup, system_instruction::transfer(owner, &source, amount_in))
+            // SyncNative: credit the transferred lamports as wSOL
+            .push(
+                IxStage::Setup,
+                Instruction::new_with_bytes(token_program, &[17], vec![AccountMeta::new(source, false)]),
+            );
+    }
+    builder = builder.push(IxStage::Setup, create_ata_idempotent(owner, &destination, output_mint, &token_program)?);
+
+    let mut data = vec![SWAP_BASE_IN];
+    data.extend_from_slice(&amount_in.to_le_bytes());
+    data.extend_from_slice(&min_out_amount.to_le_bytes());
+    let swap = Instruction::new_with_bytes(
+        Pubkey::from_str(RAYDIUM_AMM_V4)?,
+        &data,
+        vec![
+            AccountMeta::new_readonly(token_program, false),
+            AccountMeta::new(pool.id, false),
+            AccountMeta::new_readonly(Pubkey::from_str(AMM_AUTHORITY)?, false),
+            AccountMeta::new(pool.open_orders, false),
+            AccountMeta::new(pool.target_orders, false),
+            AccountMeta::new(pool.base_vault, false),
+            AccountMeta::new(pool.quote_vault, false),
+            AccountMeta::new_readonly(pool.market_program, false),
+            AccountMeta::new(pool.market, false),
+            AccountMeta::new(pool.bids, false),
+            AccountMeta::new(pool.asks, false),
+            AccountMeta::new(pool.event_queue, false),
+            AccountMeta::new(pool.market_base_vault, false),
+            AccountMeta::new(pool.market_quote_vault, false),
+            AccountMeta::new_readonly(pool.vault_signer, false),
+            AccountMeta::new(source, false),
+            AccountMeta::new(destination, false),
+            AccountMeta::new_readonly(*owner, true),
+        ],
+    );
+
+    // CloseAccount: unwraps whatever wSOL is left (all of it on a sell) back to SOL
+    let close = Instruction::new_with_bytes(
+        token_program,
+        &[9],
+        vec![AccountMeta::new(wsol, false), AccountMeta::new(*owner, false), AccountMeta::new_readonly(*owner, true)],
+    );
+    Ok(builder.push(IxStage::Swap, swap).push(IxStage::Cleanup, close))
+}
+
+/// `owner`'s associated token account for `mint` under `token_program` (SPL Token or Token-2022).
+pub(crate) fn associated_token_address(owner: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Result<Pubkey> {
+    let ata_program = Pubkey::from_str(ASSOCIATED_TOKEN_PROGRAM_ID)?;
+    Ok(Pubkey::find_program_address(&[owner.as_ref(), token_program.as_ref(), mint.as_ref()], &ata_program).0)
+}
+
+/// Associated token program `CreateIdempotent`: a no-op when the account exists.
+pub(crate) fn create_ata_idempotent(owner: &Pubkey, ata: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Result<Instruction> {
+    Ok(Instruction::new_with_bytes(
+        Pubkey::from_str(ASSOCIATED_TOKEN_PROGRAM_ID)?,
+        &[1],
+        vec![
+            AccountMeta::new(*owner, true),
+            AccountMeta::new(*ata, false),
+            AccountMeta::new_readonly(*owner, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(*token_program, false),
+        ],
+    ))
+}
@@ -0,0 +1,183 @@
+use crate::common::utils::{env_bool, env_u64, env_var_opt, parse_pubkey_list, TOKEN_2022_PROGRAM_ID, TOKEN_PROGRAM_ID};
+use crate::dex::jupiter::{JUPITER_PROGRAM_ID, MEMO_PROGRAM_ID, SOL_MINT};
+use crate::dex::pumpfun::PUMPFUN_PROGRAM_ID;
+use crate::dex::raydium::{associated_token_address, ASSOCIATED_TOKEN_PROGRAM_ID, RAYDIUM_AMM_V4};
+use anyhow::{anyhow, Result};
+use solana_sdk::{compute_budget, pubkey::Pubkey, system_program, transaction::VersionedTransaction};
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// Programs a swap tx may call at the top level. AMMs on a Jupiter route are reached by
+/// CPI from the Jupiter program, so only the ones we build swaps for directly are listed.
+const ALLOWED_PROGRAMS: &[&str] = &[
+    TOKEN_PROGRAM_ID,
+    TOKEN_2022_PROGRAM_ID,
+    ASSOCIATED_TOKEN_PROGRAM_ID,
+    JUPITER_PROGRAM_ID,
+    RAYDIUM_AMM_V4,
+    PUMPFUN_PROGRAM_ID,
+    MEMO_PROGRAM_ID,
+];
+
+/// System program instructions that move lamports: `Transfer` and `TransferWithSeed`.
+const SYSTEM_TRANSFER: u32 = 2;
+const SYSTEM_TRANSFER_WITH_SEED: u32 = 11;
+
+/// Checks a swap tx built elsewhere (Jupiter's `/swap`) before we sign it: we pay its
+/// fee, it only calls known programs, and it sends no more than `max_transfer_lamports`
+/// anywhere but our own wallet and wSOL account.
+#[derive(Debug, Clone)]
+pub struct TxGuard {
+    allowed_programs: HashSet<Pubkey>,
+    max_transfer_lamports: u64,
+}
+
+impl TxGuard {
+    pub fn new(extra_programs: &[Pubkey], max_transfer_lamports: u64) -> Result<Self> {
+        let mut allowed_programs: HashSet<Pubkey> = ALLOWED_PROGRAMS
+            .iter()
+            .map(|p| Pubkey::from_str(p))
+            .collect::<Result<_, _>>()?;
+        allowed_programs.insert(system_program::id());
+        allowed_programs.insert(compute_budget::id());
+        allowed_programs.extend(extra_programs);
+        Ok(Self { allowed_programs, max_transfer_lamports })
+    }
+
+    /// `TX_GUARD` (default true), `TX_GUARD_EXTRA_PROGRAMS` (comma-separated program ids
+    /// allowed on top of the built-in list) and `TX_GUARD_MAX_TRANSFER_LAMPORTS` (default
+    /// 1_000_000). `None` when the guard is off.
+    pub fn from_env() -> Result<Option<Self>> {
+        if !env_bool("TX_GUARD", true) {
+            return Ok(None);
+        }
+        let extra = match env_var_opt("TX_GUARD_EXTRA_PROGRAMS") {
+            Some(raw) => parse_pubkey_list("TX_GUARD_EXTRA_PROGRAMS", &raw)?,
+            None => Vec::new(),
+        };
+        Self::new(&extra, env_u64("TX_GUARD_MAX_TRANSFER_LAMPORTS", 1_000_000)).map(Some)
+    }
+
+    /// Fails, naming the offending program or destination, if `tx` should not be signed
+    /// by `wallet`.
+    pub fn check(&self, tx: &VersionedTransaction, wallet: &Pubkey) -> Result<()> {
+        let keys = tx.message.static_account_keys();
+        match keys.first() {
+            Some(payer) if payer == wallet => {}
+            Some(payer) => return Err(anyhow!("fee payer {payer} is not our wallet {wallet}")),
+            None => return Err(anyhow!("transaction has no account keys")),
+        }
+        let wsol = associated_token_address(wallet, &Pubkey::from_str(SOL_MINT)?, &Pubkey::from_str(TOKEN_PROGRAM_ID)?)?;
+
+        for (i, ix) in tx.message.instructions().iter().enumerate() {
+            // Program ids are always static keys; lookup tables cannot supply them
+            let program = keys
+                .get(ix.program_id_index as usize)
+                .ok_or_else(|| anyhow!("instruction {i} program index {} out of range", ix.program_id_index))?;
+            if !self.allowed_programs.contains(program) {
+                return Err(anyhow!("instruction {i} calls program {program}, which is not allowed"));
+            }
+            if *program != system_program::id() {
+                continue;
+            }
+            let Some((lamports, dest)) = system_transfer(&ix.data, &ix.accounts) else { continue };
+            // A destination loaded from a lookup table is not one of ours
+            let dest = keys.get(dest as usize);
+            if dest.is_some_and(|d| *d == *wallet || *d == wsol) || lamports <= self.max_transfer_lamports {
+                continue;
+            }
+            let dest = dest.map_or_else(|| "a lookup table account".to_string(), |d| d.to_string());
+            return Err(anyhow!(
+                "instruction {i} transfers {lamports} lamports to {dest}, above TX_GUARD_MAX_TRANSFER_LAMPORTS={}",
+                self.max_transfer_lamports
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Lamports and destination account index of a system transfer, `None` for any other
+/// system instruction.
+fn system_transfer(data: &[u8], accounts: &[u8]) -> Option<(u64, u8)> {
+    let kind = u32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+    let lamports = u64::from_le_bytes(data.get(4..12)?.try_into().ok()?);
+    let dest = match kind {
+        SYSTEM_TRANSFER => *accounts.get(1)?,
+        SYSTEM_TRANSFER_WITH_SEED => *accounts.get(2)?,
+        _ => return None,
+    };
+    Some((lamports, dest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::instruction::Instruction;
+    use solana_sdk::message::Message;
+    use solana_sdk::system_instruction;
+    use solana_sdk::transaction::Transaction;
+
+    fn tx(payer: &Pubkey, ixs: &[Instruction]) -> VersionedTransaction {
+        VersionedTransaction::from(Transaction::new_unsigned(Message::new(ixs, Some(payer))))
+    }
+
+    fn jupiter_swap() -> Instruction {
+        Instruction::new_with_bytes(Pubkey::from_str(JUPITER_PROGRAM_ID).unwrap(), &[1, 2, 3], vec![])
+    }
+
+    #[test]
+    fn allows_a_swap_paid_by_our_wallet() {
+        let wallet = Pubkey::new_unique();
+        let guard = TxGuard::new(&[], 1_000_000).unwrap();
+        let wsol = associated_token_address(
+            &wallet,
+            &Pubkey::from_str(SOL_MINT).unwrap(),
+            &Pubkey::from_str(TOKEN_PROGRAM_ID).unwrap(),
+        )
+        .unwrap();
+        let ixs = [
+            solana_sdk::compute_budget::ComputeBudgetInstruction::set_compute_unit_limit(200_000),
+            // Wrapping SOL moves any amount into our own wSOL account
+            system_instruction::transfer(&wallet, &wsol, 5_000_000_000),
+            jupiter_swap(),
+            // A tip within the cap
+            system_instruction::transfer(&wallet, &Pubkey::new_unique(), 1_000_000),
+        ];
+        guard.check(&tx(&wallet, &ixs), &wallet).unwrap();
+    }
+
+    #[test]
+    fn rejects_another_fee_payer() {
+        let (wallet, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let guard = TxGuard::new(&[], 1_000_000).unwrap();
+        let err = guard.check(&tx(&other, &[jupiter_swap()]), &wallet).unwrap_err().to_string();
+        assert!(err.contains(&format!("fee payer {other} is not our wallet")), "{err}");
+    }
+
+    #[test]
+    fn rejects_programs_not_on_the_allowlist() {
+        let wallet = Pubkey::new_unique();
+        let unknown = Pubkey::new_unique();
+        let tx = tx(&wallet, &[Instruction::new_with_bytes(unknown, &[0], vec![])]);
+        let err = TxGuard::new(&[], 1_000_000).unwrap().check(&tx, &wallet).unwrap_err().to_string();
+        assert!(err.contains(&format!("calls program {unknown}, which is not allowed")), "{err}");
+
+        // TX_GUARD_EXTRA_PROGRAMS adds to the list
+        TxGuard::new(&[unknown], 1_000_000).unwrap().check(&tx, &wallet).unwrap();
+    }
+
+    #[test]
+    fn rejects_transfers_over_the_cap() {
+        let wallet = Pubkey::new_unique();
+        let thief = Pubkey::new_unique();
+        let guard = TxGuard::new(&[], 1_000_000).unwrap();
+        let drain = system_instruction::transfer(&wallet, &thief, 1_000_001);
+        let err = guard.check(&tx(&wallet, &[jupiter_swap(), drain]), &wallet).unwrap_err().to_string();
+        assert!(err.contains(&format!("transfers 1000001 lamports to {thief}")), "{err}");
+
+        let seed_base = Pubkey::new_unique();
+        let seeded =
+            system_instruction::transfer_with_seed(&seed_base, &wallet, "s".to_string(), &system_program::id(), &thief, 2_000_000);
+        assert!(guard.check(&tx(&wallet, &[seeded]), &wallet).is_err());
+    }
+}
@@ -0,0 +1,137 @@
+use crate::engine::positions::now_secs;
+use crate::types::events::MirrorIntent;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::{fs, io};
+use tracing::info;
+
+/// What to do when the target sells a watched airdrop we never bought
+/// (`WATCH_AIRDROP_ACTION`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AirdropAction {
+    /// Log it and mirror nothing.
+    Ignore,
+    /// The sale shows the token now trades: buy it with `MAX_BUY_SOL`, then follow the
+    /// target's later sells and the exit rules as for any other position.
+    BuyThenSell,
+}
+
+impl FromStr for AirdropAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().replace('-', "_").as_str() {
+            "ignore" => Ok(AirdropAction::Ignore),
+            "buy_then_sell" => Ok(AirdropAction::BuyThenSell),
+            other => Err(anyhow!("Unknown WATCH_AIRDROP_ACTION '{other}' (expected ignore|buy_then_sell)")),
+        }
+    }
+}
+
+/// A token the target received without paying for it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AirdropWatch {
+    pub target: String,
+    /// Token program owning the mint, needed if it is bought later.
+    pub token_program: String,
+    /// Unix seconds of the first airdrop seen.
+    pub first_seen: u64,
+    pub signature: String,
+}
+
+/// Airdrops to the targets (`WATCH_AIRDROPS`), keyed by mint and persisted as JSON, so a
+/// sell weeks later is still recognised as the target cashing out a free token.
+#[derive(Debug)]
+pub struct AirdropWatchlist {
+    path: PathBuf,
+    action: AirdropAction,
+    watches: HashMap<String, AirdropWatch>,
+}
+
+impl AirdropWatchlist {
+    /// Loads watches from `path`, starting empty if the file does not exist yet.
+    pub fn load(path: impl Into<PathBuf>, action: AirdropAction) -> Result<Self> {
+        let path = path.into();
+        let watches = match fs::read_to_string(&path) {
+            Ok(raw) => serde_json::from_str(&raw)
+                .map_err(|e| anyhow!("Invalid airdrop watch file {}: {e}", path.display()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(anyhow!("Failed to read airdrop watch file {}: {e}", path.display())),
+        };
+        Ok(Self { path, action, watches })
+    }
+
+    pub fn len(&self) -> usize {
+        self.watches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    pub fn action(&self) -> AirdropAction {
+        self.action
+    }
+
+    /// Records an airdrop of `mint`. A mint already watched keeps its first entry.
+    pub fn watch(&mut self, mint: &Pubkey, token_program: &Pubkey, target: &Pubkey, signature: &str) -> Result<()> {
+        if self.watches.contains_key(&mint.to_string()) {
+            return Ok(());
+        }
+        self.watches.insert(
+            mint.to_string(),
+            AirdropWatch {
+                target: target.to_string(),
+                token_program: token_program.to_string(),
+                first_seen: now_secs(),
+                signature: signature.to_string(),
+            },
+        );
+        self.save()
+    }
+
+    /// What to mirror for `intent`. A `Watch` is recorded and yields nothing. A sell of a
+    /// watched mint we do not `held` ends the watch and becomes, per the action, nothing
+    /// or a buy of `buy_sol`. Anything else passes through.
+    pub fn resolve(&mut self, intent: MirrorIntent, target: &Pubkey, signature: &str, held: bool, buy_sol: f64) -> Result<Option<MirrorIntent>> {
+        match intent {
+            MirrorIntent::Watch { mint, token_program } => {
+                info!("Airdrop of {mint} to target {target}; watching it instead of buying");
+                self.watch(&mint, &token_program, target, signature)?;
+                Ok(None)
+            }
+            MirrorIntent::Sell { input_mint, .. } if !held => {
+                let Some(watch) = self.watches.remove(&input_mint.to_string()) else { return Ok(Some(intent)); };
+                self.save()?;
+                match self.action {
+                    AirdropAction::Ignore => {
+                        info!("Target {target} sold airdropped {input_mint}, which we never bought; ignored (WATCH_AIRDROP_ACTION=ignore)");
+                        Ok(None)
+                    }
+                    AirdropAction::BuyThenSell => {
+                        info!("Target {target} sold airdropped {input_mint}; buying it to follow the rest of the exit");
+                        Ok(Some(MirrorIntent::Buy {
+                            output_mint: input_mint,
+                            max_input_sol: buy_sol,
+                            observed_input_sol: None,
+                            token_program: Pubkey::from_str(&watch.token_program)?,
+                        }))
+                    }
+                }
+            }
+            other => Ok(Some(other)),
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let tmp = self.path.with_extension("json.tmp");
+        let raw = serde_json::to_string_pretty(&self.watches)?;
+        fs::write(&tmp, raw)
+            .and_then(|_| fs::rename(&tmp, &self.path))
+            .map_err(|e| anyhow!("Failed to write airdrop watch file {}: {e}", self.path.display()))
+    }
+}
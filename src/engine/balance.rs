@@ -0,0 +1,76 @@
+use crate::common::notify::Notifier;
+use anyhow::Result;
+use solana_client::nonblocking::rpc_client::RpcClient as AsyncRpcClient;
+use solana_sdk::pubkey::Pubkey;
+use std::sync::Arc;
+use tokio::time::{Duration, Instant};
+use tracing::info;
+
+/// How long a fetched wallet balance is trusted before it is read again.
+const BALANCE_TTL: Duration = Duration::from_secs(5);
+
+/// Pre-flight check that the wallet can pay for a buy and still keep
+/// `MIN_SOL_RESERVE_SOL` (default 0.01) for fees, so an empty wallet skips buys instead
+/// of failing every swap. The balance is cached for `BALANCE_TTL` and lowered by what
+/// each sent buy spends.
+pub struct BalanceGuard {
+    reserve_lamports: u64,
+    cached: Option<(u64, Instant)>,
+    /// Set while buys are being skipped, so the notification goes out once.
+    low: bool,
+    notifier: Arc<dyn Notifier>,
+}
+
+impl BalanceGuard {
+    pub fn new(notifier: Arc<dyn Notifier>, reserve_lamports: u64) -> Self {
+        Self {
+            reserve_lamports,
+            cached: None,
+            low: false,
+            notifier,
+        }
+    }
+
+    pub fn reserve_lamports(&self) -> u64 {
+        self.reserve_lamports
+    }
+
+    /// `None` when `wallet` can spend `lamports` and keep the reserve, otherwise why not.
+    /// An RPC error lets the buy through: the swap itself will fail if funds are short.
+    pub async fn check(&mut self, rpc: &AsyncRpcClient, wallet: &Pubkey, lamports: u64) -> Result<Option<String>> {
+        let balance = match self.cached {
+            Some((balance, at)) if at.elapsed() < BALANCE_TTL => balance,
+            _ => {
+                let balance = rpc.get_balance(wallet).await?;
+                self.cached = Some((balance, Instant::now()));
+                balance
+            }
+        };
+
+        let needed = lamports.saturating_add(self.reserve_lamports);
+        if balance >= needed {
+            if self.low {
+                self.low = false;
+                info!("Wallet balance back to {balance} lamports; buys resumed");
+            }
+            return Ok(None);
+        }
+        let reason = format!(
+            "wallet balance {:.6} SOL is below the buy plus MIN_SOL_RESERVE_SOL ({:.6} SOL)",
+            balance as f64 / 1_000_000_000.0,
+            needed as f64 / 1_000_000_000.0
+        );
+        if !self.low {
+            self.low = true;
+            self.notifier.notify(&format!("Buys skipped: {reason}"));
+        }
+        Ok(Some(reason))
+    }
+
+    /// Lowers the cached balance by a sent buy, until the next fetch.
+    pub fn record_spend(&mut self, lamports: u64) {
+        if let Some((balance, _)) = &mut self.cached {
+            *balance = balance.saturating_sub(lamports);
+        }
+    }
+}
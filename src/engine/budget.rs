@@ -0,0 +1,112 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io};
+use tracing::warn;
+
+/// Length of the rolling spend window.
+pub const WINDOW_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SpendEntry {
+    /// Unix seconds of the buy.
+    at: u64,
+    lamports: u64,
+}
+
+/// Rolling 24h budget for mirrored buys, persisted so a crash loop cannot reset it.
+#[derive(Debug)]
+pub struct SpendBudget {
+    path: PathBuf,
+    limit_lamports: u64,
+    entries: VecDeque<SpendEntry>,
+    paused: bool,
+}
+
+impl SpendBudget {
+    pub fn load(path: impl Into<PathBuf>, limit_lamports: u64) -> Result<Self> {
+        let path = path.into();
+        let entries = match fs::read_to_string(&path) {
+            Ok(raw) => serde_json::from_str(&raw)
+                .map_err(|e| anyhow!("Invalid budget file {}: {e}", path.display()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => return Err(anyhow!("Failed to read budget file {}: {e}", path.display())),
+        };
+        Ok(Self {
+            path,
+            limit_lamports,
+            entries,
+            paused: false,
+        })
+    }
+
+    pub fn limit_lamports(&self) -> u64 {
+        self.limit_lamports
+    }
+
+    /// Lamports spent within the current window.
+    pub fn spent_lamports(&mut self) -> u64 {
+        self.prune(now_secs());
+        self.entries.iter().map(|e| e.lamports).sum()
+    }
+
+    pub fn remaining_lamports(&mut self) -> u64 {
+        self.limit_lamports.saturating_sub(self.spent_lamports())
+    }
+
+    /// How many lamports a buy may use right now (0 = paused). Logs when the budget
+    /// pauses or resumes mirroring.
+    pub fn available_for_buy(&mut self) -> u64 {
+        let remaining = self.remaining_lamports();
+        let paused = remaining == 0;
+        if paused != self.paused {
+            self.paused = paused;
+            if paused {
+                warn!(
+                    "Daily spend limit of {} lamports reached; pausing mirrored buys until the 24h window rolls over",
+                    self.limit_lamports
+                );
+            } else {
+                warn!("Daily spend window rolled over; resuming mirrored buys ({remaining} lamports available)");
+            }
+        }
+        remaining
+    }
+
+    /// Records a sent buy and persists the window.
+    pub fn record(&mut self, lamports: u64) -> Result<()> {
+        self.entries.push_back(SpendEntry {
+            at: now_secs(),
+            lamports,
+        });
+        self.prune(now_secs());
+        self.save()
+    }
+
+    fn prune(&mut self, now: u64) {
+        while self
+            .entries
+            .front()
+            .is_some_and(|e| e.at + WINDOW_SECS <= now)
+        {
+            self.entries.pop_front();
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let tmp = self.path.with_extension("json.tmp");
+        let raw = serde_json::to_string_pretty(&self.entries)?;
+        fs::write(&tmp, raw)
+            .and_then(|_| fs::rename(&tmp, &self.path))
+            .map_err(|e| anyhow!("Failed to write budget file {}: {e}", self.path.display()))
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
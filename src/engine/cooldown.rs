@@ -0,0 +1,123 @@
+use crate::common::utils::env_u64;
+use crate::engine::positions::now_secs;
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use tokio::time::{Duration, Instant};
+
+/// Rate limits on mirrored trades so a target's burst of buys cannot drain the wallet.
+#[derive(Debug)]
+pub struct TradeCooldowns {
+    /// `MIRROR_COOLDOWN_SECS`: min time between two mirrored buys of the same mint.
+    per_mint: Duration,
+    /// `MIN_TRADE_INTERVAL_SECS`: min time between any two mirrored trades.
+    min_interval: Duration,
+    /// `LOSS_COOLDOWN_SECS`: after a sell of a mint realizes a loss, no buys of it for this
+    /// long (0 = off), so a target re-entering a token that just lost is not chased.
+    loss: Duration,
+    last_by_mint: HashMap<Pubkey, Instant>,
+    last_trade: Option<Instant>,
+}
+
+impl TradeCooldowns {
+    pub fn from_env() -> Self {
+        Self {
+            per_mint: Duration::from_secs(env_u64("MIRROR_COOLDOWN_SECS", 60)),
+            min_interval: Duration::from_secs(env_u64("MIN_TRADE_INTERVAL_SECS", 0)),
+            loss: Duration::from_secs(env_u64("LOSS_COOLDOWN_SECS", 0)),
+            last_by_mint: HashMap::new(),
+            last_trade: None,
+        }
+    }
+
+    pub fn per_mint(&self) -> Duration {
+        self.per_mint
+    }
+
+    pub fn min_interval(&self) -> Duration {
+        self.min_interval
+    }
+
+    /// Why a buy of `mint` must wait, if it must.
+    pub fn buy_blocked(&self, mint: &Pubkey) -> Option<String> {
+        if let Some(last) = self.last_by_mint.get(mint) {
+            let elapsed = last.elapsed();
+            if elapsed < self.per_mint {
+                return Some(format!(
+                    "MIRROR_COOLDOWN_SECS: last buy of this mint {}s ago",
+                    elapsed.as_secs()
+                ));
+            }
+        }
+        self.trade_blocked()
+    }
+
+    pub fn loss_cooldown(&self) -> Duration {
+        self.loss
+    }
+
+    /// Why a buy must wait given the mint's last sell (`PositionTracker::last_outcome`),
+    /// if it must: the sell lost money less than `LOSS_COOLDOWN_SECS` ago.
+    pub fn loss_blocked(&self, last_outcome: Option<(i64, u64)>) -> Option<String> {
+        let (pnl, at) = last_outcome?;
+        let ago = now_secs().saturating_sub(at);
+        (pnl < 0 && ago < self.loss.as_secs())
+            .then(|| format!("LOSS_COOLDOWN_SECS: last sell lost {} lamports {ago}s ago", -pnl))
+    }
+
+    /// Why any trade must wait, if it must.
+    pub fn trade_blocked(&self) -> Option<String> {
+        let elapsed = self.last_trade?.elapsed();
+        (elapsed < self.min_interval)
+            .then(|| format!("MIN_TRADE_INTERVAL_SECS: last trade {}ms ago", elapsed.as_millis()))
+    }
+
+    /// Records a sent buy of `mint`.
+    pub fn record_buy(&mut self, mint: &Pubkey) {
+        self.last_by_mint.insert(*mint, Instant::now());
+        self.record_trade();
+    }
+
+    /// Records any sent trade.
+    pub fn record_trade(&mut self) {
+        let now = Instant::now();
+        self.last_trade = Some(now);
+        self.last_by_mint.retain(|_, at| now.duration_since(*at) < self.per_mint);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::positions::PositionTracker;
+
+    fn cooldowns(loss_secs: u64) -> TradeCooldowns {
+        TradeCooldowns {
+            per_mint: Duration::ZERO,
+            min_interval: Duration::ZERO,
+            loss: Duration::from_secs(loss_secs),
+            last_by_mint: HashMap::new(),
+            last_trade: None,
+        }
+    }
+
+    #[test]
+    fn losing_mint_is_skipped_until_the_loss_cooldown_ends() {
+        let path = std::env::temp_dir().join(format!("ammalgam-positions-test-{}.json", std::process::id()));
+        let mut positions = PositionTracker::load(&path).unwrap();
+        let (loser, winner, untraded) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        positions.record_outcome(&loser, -5_000);
+        positions.record_outcome(&winner, 5_000);
+
+        let limits = cooldowns(600);
+        assert!(limits.loss_blocked(positions.last_outcome(&loser)).is_some());
+        assert!(limits.loss_blocked(positions.last_outcome(&winner)).is_none());
+        assert!(limits.loss_blocked(positions.last_outcome(&untraded)).is_none());
+
+        // The same loss once LOSS_COOLDOWN_SECS have passed
+        let (pnl, at) = positions.last_outcome(&loser).unwrap();
+        assert!(limits.loss_blocked(Some((pnl, at - 590))).is_some());
+        assert!(limits.loss_blocked(Some((pnl, at - 600))).is_none());
+        // 0 turns it off
+        assert!(cooldowns(0).loss_blocked(Some((pnl, at))).is_none());
+    }
+}
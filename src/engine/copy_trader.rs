@@ -1,120 +1,1185 @@
-use crate::common::utils::{build_state, env_bool, env_f64, env_u16, env_var, parse_pubkey};
-use crate::dex::jupiter::{jupiter_quote, jupiter_swap_tx, sign_and_send_swap, SOL_MINT};
-use crate::engine::intent::infer_intent_from_tx;
-use crate::helius::ws::connect_forever;
+use crate::common::admin::spawn_admin_console;
+use crate::common::config::Config;
+use crate::common::metrics::{metrics, observe};
+use crate::common::notify::{notifier_from_env, Notifier, NotifyOn};
+use crate::common::proxy::{build_http_client, ProxyConfig};
+use crate::common::utils::{
+    build_state, env_bool, env_f64, env_u16, env_u64, env_var_opt, parse_pubkey, parse_pubkey_list,
+    token_balance, token_holdings, AppState, TOKEN_2022_PROGRAM_ID,
+};
+use crate::dex::jito::{send_with_jito_fallback, JitoConfig};
+use crate::dex::jupiter::{
+    confirm_transaction, ensure_fresh_blockhash, is_slippage_error, send_signed_swap, sign_swap,
+    simulate_signed_swap, swap_quoted_out_amount, JupiterClient, QuoteOptions, QuoteResponse, SwapMode,
+    SwapResponse, TxConfirmation, SOL_MINT, USDC_MINT, USDT_MINT,
+};
+use crate::dex::priority_fee::{FeeQuote, PriorityFee};
+use crate::dex::pumpfun::pumpfun_buy;
+use crate::dex::raydium::raydium_swap;
+use crate::dex::tx_guard::TxGuard;
+use crate::engine::airdrops::{AirdropAction, AirdropWatchlist};
+use crate::engine::balance::BalanceGuard;
+use crate::engine::budget::SpendBudget;
+use crate::engine::cooldown::TradeCooldowns;
+use crate::engine::exits::{spawn_exit_monitor, ExitConfig, ExitPolicies, ExitPolicy, PoolWatch};
+use crate::engine::health::TargetHealth;
+use crate::engine::hooks::{decide, TradeHook};
+use crate::engine::intent::{
+    account_keys, infer_intent_from_tx, mentioned_targets, DustConfig, IntentConfig, IntentIds, IntentMode, SizeMode,
+};
+use crate::engine::positions::{now_secs, PositionTracker};
+use crate::engine::ramp::SizeRamp;
+use crate::engine::risk::RiskGuard;
+use crate::engine::rollback::{wait_confirmed, RollbackMonitor};
+use crate::engine::seen::SeenSignatures;
+use crate::engine::stats::StatsBook;
+use crate::helius::ws::{connect_forever, spawn_account_watch, WsEndpoints};
+use crate::types::events::{MirrorIntent, TokenBalance, TransactionNotification};
 use anyhow::{anyhow, Result};
-use reqwest::Client;
-use solana_sdk::pubkey::Pubkey;
-use tracing::{debug, error, info};
+use serde_json::json;
+use solana_client::nonblocking::rpc_client::RpcClient as AsyncRpcClient;
+use solana_client::rpc_request::RpcRequest;
+use solana_sdk::{pubkey::Pubkey, signature::Signature, signer::Signer};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, Duration, Instant};
+use tracing::{debug, error, info, warn};
 
-pub async fn run_copy_trader() -> Result<()> {
-    let state = build_state().await?;
+/// Target sold at least this fraction => treat as a full exit and sell everything we hold.
+const FULL_EXIT_FRACTION: f64 = 0.999_999;
 
-    let ws = env_var("RPC_WEBSOCKET_ENDPOINT")?;
-    let target_str = env_var("TARGET_PUBKEY")?;
-    let target: Pubkey = parse_pubkey("TARGET_PUBKEY", &target_str)?;
+/// Everything the copy trader runs with, resolved up front so nothing is read from the
+/// environment once it starts. `from_env` builds it from env vars and the config file;
+/// an embedding application can fill it from its own config source instead.
+pub struct CopyTraderConfig {
+    /// RPC clients, wallet and fee payer.
+    pub state: AppState,
+    /// `ADMIN_CONSOLE`: accept `loglevel ...` commands on stdin.
+    pub admin_console: bool,
+    pub ws_endpoints: WsEndpoints,
+    /// Proxy for the WS connection (Jupiter and Jito already go through `jupiter`'s client).
+    pub proxy: Option<ProxyConfig>,
+    pub targets: Vec<Pubkey>,
+    /// Linked wallet -> its group's first wallet (`TARGET_GROUPS`).
+    pub target_groups: HashMap<Pubkey, Pubkey>,
+    pub mirror_buys_only: bool,
+    pub mirror_sells: bool,
+    pub mirror_sells_without_position: bool,
+    pub never_sell: HashSet<Pubkey>,
+    pub token_whitelist: HashSet<Pubkey>,
+    pub token_blacklist: HashSet<Pubkey>,
+    /// Target buys smaller than this (test buys) are not mirrored, whatever the size mode is.
+    pub min_target_spend_sol: f64,
+    /// Skip buys into pools too thin for our size (0 = no check).
+    pub max_price_impact_pct: f64,
+    /// `None` = unlimited exposure per mint.
+    pub max_lamports_per_mint: Option<u64>,
+    /// Buy amounts are rounded down to a multiple of this (0/1 = no rounding).
+    pub amount_round_lamports: u64,
+    pub swap: SwapSettings,
+    pub intent: IntentConfig,
+    /// Namespaces intent ids so several instances following the same targets stay distinguishable.
+    pub instance_namespace: String,
+    /// Uncapped auto fee estimate (lamports) above which buys are skipped as congestion (0 = off).
+    pub congestion_fee_threshold: u64,
+    pub positions_file: String,
+    /// Tags positions so PnL can be attributed when several strategy profiles share targets.
+    pub strategy: Option<String>,
+    /// Used when `intent.watch_airdrops` is on.
+    pub airdrop_action: AirdropAction,
+    pub airdrops_file: String,
+    pub stats_file: String,
+    pub max_drawdown_lamports: u64,
+    /// Rolling 24h spend limit on mirrored buys (0 = unlimited).
+    pub daily_limit_lamports: u64,
+    pub budget_file: String,
+    pub jito: Option<JitoConfig>,
+    pub jupiter: JupiterClient,
+    pub notifier: Arc<dyn Notifier>,
+    pub notify_on: NotifyOn,
+    /// Failed trades in a row that trip the breaker (0 = off).
+    pub max_consecutive_failures: u64,
+    pub breaker_cooldown: Duration,
+    /// SOL (lamports) buys always leave in the wallet.
+    pub min_sol_reserve_lamports: u64,
+    /// Pause mirroring while the positions file cannot be written.
+    pub halt_on_persist_fail: bool,
+    pub import_existing_holdings: bool,
+    /// `None` = no periodic PnL report.
+    pub pnl_report_interval: Option<Duration>,
+    pub exit_policies: ExitPolicies,
+    /// `None` = no exit monitor.
+    pub exit: Option<ExitConfig>,
+    /// Safety interlock: pause trading while fewer than this many target feeds look alive.
+    pub min_active_targets: usize,
+    pub target_active_window: Duration,
+    pub ramp: Option<SizeRamp>,
+    pub cooldowns: TradeCooldowns,
+    /// Signatures already acted on, persisted so a replay after restart is not mirrored again.
+    pub state_file: String,
+    pub state_max_sigs: usize,
+    pub rollback: Option<RollbackMonitor>,
+}
+
+impl CopyTraderConfig {
+    /// Reads every setting from the environment (and the config file behind it) and
+    /// connects the RPC clients and wallet.
+    pub async fn from_env(config: Config) -> Result<Self> {
+        let state = build_state(&config).await?;
+        let targets = config.targets.clone();
+
+        let mut size_mode: SizeMode = env_var_opt("SIZE_MODE")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or(SizeMode::Fixed);
+        let mut size_ratio: f64 = env_f64("SIZE_RATIO", 1.0);
+        // COPY_RATIO is shorthand for SIZE_MODE=proportional with SIZE_RATIO=COPY_RATIO
+        if let Some(ratio) = env_var_opt("COPY_RATIO").filter(|v| !v.trim().is_empty()) {
+            size_ratio = ratio
+                .trim()
+                .parse()
+                .map_err(|e| anyhow!("Invalid COPY_RATIO: {e}"))?;
+            size_mode = SizeMode::Proportional;
+        }
+        // DETECT_MODE is accepted as another name for INTENT_MODE
+        let intent_mode: IntentMode = env_var_opt("INTENT_MODE")
+            .or_else(|| env_var_opt("DETECT_MODE"))
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or(IntentMode::Hybrid);
+        let max_lamports_per_mint = config.max_sol_per_mint.map(sol_to_lamports).transpose()?;
+        let swap = SwapSettings {
+            slippage_bps: config.slippage_bps,
+            confirm_timeout: Duration::from_secs(config.confirm_timeout_secs),
+            max_divergence_pct: env_f64("MAX_QUOTE_SWAP_DIVERGENCE_PCT", 0.0),
+            validate_blockhash: env_bool("VALIDATE_BLOCKHASH_PRESEND", false),
+            priority_fee: PriorityFee::from_env()?,
+            raydium_fallback: env_bool("ENABLE_RAYDIUM_FALLBACK", false),
+            pumpfun_routing: env_bool("ENABLE_PUMPFUN_ROUTING", false),
+            exact_out_sells: env_bool("EXACT_OUT_SELLS", false),
+            requote_bump_bps: env_u16("REQUOTE_SLIPPAGE_BUMP_BPS", 200),
+            requote_max_slippage_bps: env_u16("REQUOTE_MAX_SLIPPAGE_BPS", 1_000),
+            slippage_retries: env_u64("SLIPPAGE_RETRIES", 0) as u32,
+            max_slippage_bps: env_u16("MAX_SLIPPAGE_BPS", 1_500),
+            tx_guard: TxGuard::from_env()?,
+            simulate_before_send: env_bool("SIMULATE_BEFORE_SEND", false),
+        };
+        let intent = IntentConfig {
+            max_buy_sol: config.max_buy_sol,
+            size_mode,
+            size_ratio,
+            min_buy_sol: config.min_buy_sol,
+            mode: intent_mode,
+            target_pdas: parse_target_pdas(&targets)?,
+            base_mints: parse_base_mints()?,
+            dust: DustConfig {
+                min_delta_raw: env_u64("MIN_DELTA_RAW", 1).max(1),
+                min_buy_delta_ui: env_f64("MIN_BUY_DELTA_UI", 0.0).max(0.0),
+                overrides: load_dust_overrides()?,
+            },
+            ignore_mints: parse_mint_set("IGNORE_MINTS")?.iter().map(|m| m.to_string()).collect(),
+            allow_non_signer: env_bool("ALLOW_NON_SIGNER", false),
+            skip_token_2022: env_bool("SKIP_TOKEN_2022", false),
+            watch_airdrops: env_bool("WATCH_AIRDROPS", false),
+            ignore_lp_events: env_bool("IGNORE_LP_EVENTS", true),
+            pumpfun_detection: env_bool("ENABLE_PUMPFUN_DETECTION", false),
+        };
+
+        let proxy = ProxyConfig::from_env()?;
+        let http = build_http_client(proxy.as_ref())?;
+        let jupiter = JupiterClient::from_env(http.clone());
+        let exit_policies = ExitPolicies::from_env()?;
+        let exit = ExitConfig::from_env(swap.clone(), exit_policies.clone())?;
+        let pnl_report_secs = env_u64("PNL_REPORT_SECS", 900);
+
+        Ok(Self {
+            state,
+            admin_console: env_bool("ADMIN_CONSOLE", true),
+            ws_endpoints: WsEndpoints::from_env(config.rpc_websocket_endpoint),
+            proxy,
+            target_groups: parse_target_groups(&env_var_opt("TARGET_GROUPS").unwrap_or_default(), &targets)?,
+            targets,
+            mirror_buys_only: config.mirror_buys_only,
+            mirror_sells: config.mirror_sells,
+            mirror_sells_without_position: env_bool("MIRROR_SELLS_WITHOUT_POSITION", false),
+            never_sell: parse_never_sell()?,
+            token_whitelist: parse_mint_set("TOKEN_WHITELIST")?,
+            token_blacklist: parse_mint_set("TOKEN_BLACKLIST")?,
+            min_target_spend_sol: env_f64("MIN_TARGET_SPEND_SOL", 0.0),
+            max_price_impact_pct: env_f64("MAX_PRICE_IMPACT_PCT", 5.0),
+            max_lamports_per_mint,
+            amount_round_lamports: env_u64("AMOUNT_ROUND_LAMPORTS", 0),
+            swap,
+            intent,
+            instance_namespace: env_var_opt("INSTANCE_NAMESPACE").unwrap_or_default(),
+            congestion_fee_threshold: env_u64("CONGESTION_FEE_THRESHOLD", 0),
+            positions_file: env_var_opt("POSITIONS_FILE").unwrap_or_else(|| "positions.json".to_string()),
+            strategy: env_var_opt("STRATEGY").map(|s| s.trim().to_string()).filter(|s| !s.is_empty()),
+            airdrop_action: env_var_opt("WATCH_AIRDROP_ACTION")
+                .map(|v| v.parse())
+                .transpose()?
+                .unwrap_or(AirdropAction::Ignore),
+            airdrops_file: env_var_opt("AIRDROPS_FILE").unwrap_or_else(|| "airdrops.json".to_string()),
+            stats_file: env_var_opt("STATS_FILE").unwrap_or_else(|| "stats.json".to_string()),
+            max_drawdown_lamports: sol_to_lamports(env_f64("MAX_TARGET_DRAWDOWN_SOL", 0.0))?,
+            daily_limit_lamports: sol_to_lamports(config.max_daily_sol)?,
+            budget_file: env_var_opt("BUDGET_FILE").unwrap_or_else(|| "budget.json".to_string()),
+            jito: JitoConfig::from_env(),
+            jupiter,
+            notifier: notifier_from_env(&http),
+            notify_on: env_var_opt("NOTIFY_ON")
+                .map(|v| v.parse())
+                .transpose()?
+                .unwrap_or(NotifyOn::Send),
+            max_consecutive_failures: env_u64("MAX_CONSECUTIVE_FAILURES", 0),
+            breaker_cooldown: Duration::from_secs(env_u64("BREAKER_COOLDOWN_SECS", 300)),
+            min_sol_reserve_lamports: sol_to_lamports(config.min_sol_reserve_sol)?,
+            halt_on_persist_fail: env_bool("HALT_ON_PERSIST_FAIL", false),
+            import_existing_holdings: env_bool("IMPORT_EXISTING_HOLDINGS", false),
+            pnl_report_interval: (pnl_report_secs > 0).then(|| Duration::from_secs(pnl_report_secs)),
+            exit_policies,
+            exit,
+            min_active_targets: env_u64("MIN_ACTIVE_TARGETS", 0) as usize,
+            target_active_window: Duration::from_secs(env_u64("TARGET_ACTIVE_WINDOW_SECS", 3600).max(1)),
+            ramp: SizeRamp::from_env(),
+            cooldowns: TradeCooldowns::from_env(),
+            state_file: env_var_opt("STATE_FILE").unwrap_or_else(|| "state.json".to_string()),
+            state_max_sigs: env_u64("STATE_MAX_SIGS", 1000) as usize,
+            rollback: RollbackMonitor::from_env(),
+        })
+    }
+}
+
+/// The copy trader as a library: `CopyTrader::new(config).run().await` follows the
+/// targets in `config` until the WS stream ends, without reading the environment.
+pub struct CopyTrader {
+    config: CopyTraderConfig,
+    hooks: Vec<Arc<dyn TradeHook>>,
+}
+
+impl CopyTrader {
+    pub fn new(config: CopyTraderConfig) -> Self {
+        Self { config, hooks: Vec::new() }
+    }
+
+    /// Adds a hook; hooks run in the order they were added.
+    pub fn with_hook(mut self, hook: Arc<dyn TradeHook>) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Sends trade and risk notifications to `notifier` instead of the configured one.
+    pub fn with_notifier(mut self, notifier: Arc<dyn Notifier>) -> Self {
+        self.config.notifier = notifier;
+        self
+    }
+
+    /// Trades through `state`'s RPC clients and wallet instead of the configured ones.
+    pub fn with_state(mut self, state: AppState) -> Self {
+        self.config.state = state;
+        self
+    }
+
+    pub fn config(&self) -> &CopyTraderConfig {
+        &self.config
+    }
+
+    pub async fn run(self) -> Result<()> {
+        trade(self.config, self.hooks).await
+    }
+}
+
+/// Builds env config and runs the copy trader; see `CopyTrader` to embed it instead.
+pub async fn run_copy_trader(config: Config) -> Result<()> {
+    CopyTrader::new(CopyTraderConfig::from_env(config).await?).run().await
+}
 
-    let slippage_bps: u16 = env_u16("SLIPPAGE_BPS", 500);
-    let max_buy_sol: f64 = env_f64("MAX_BUY_SOL", 0.02);
-    let mirror_buys_only: bool = env_bool("MIRROR_BUYS_ONLY", true);
+async fn trade(config: CopyTraderConfig, hooks: Vec<Arc<dyn TradeHook>>) -> Result<()> {
+    let CopyTraderConfig {
+        state,
+        admin_console,
+        ws_endpoints,
+        proxy,
+        targets,
+        target_groups,
+        mirror_buys_only,
+        mirror_sells,
+        mirror_sells_without_position,
+        never_sell,
+        token_whitelist,
+        token_blacklist,
+        min_target_spend_sol,
+        max_price_impact_pct,
+        max_lamports_per_mint,
+        amount_round_lamports,
+        swap: swap_settings,
+        intent: intent_cfg,
+        instance_namespace,
+        congestion_fee_threshold,
+        positions_file,
+        strategy,
+        airdrop_action,
+        airdrops_file,
+        stats_file,
+        max_drawdown_lamports,
+        daily_limit_lamports,
+        budget_file,
+        jito,
+        jupiter,
+        notifier,
+        notify_on,
+        max_consecutive_failures,
+        breaker_cooldown,
+        min_sol_reserve_lamports,
+        halt_on_persist_fail,
+        import_existing_holdings: import_holdings,
+        pnl_report_interval,
+        exit_policies,
+        exit,
+        min_active_targets,
+        target_active_window,
+        ramp,
+        mut cooldowns,
+        state_file,
+        state_max_sigs,
+        rollback,
+    } = config;
+
+    if admin_console {
+        spawn_admin_console();
+    }
+
+    let slippage_bps = swap_settings.slippage_bps;
+    let max_buy_sol = intent_cfg.max_buy_sol;
+    let confirm_timeout = swap_settings.confirm_timeout;
 
     info!("Ammalgram Assistant started");
     info!("Wallet: {}", state.wallet_pubkey);
-    info!("Target: {}", target);
-    info!("SLIPPAGE_BPS={slippage_bps}, MAX_BUY_SOL={max_buy_sol}, MIRROR_BUYS_ONLY={mirror_buys_only}");
+    info!(
+        "RPC commitment {:?}, swaps confirmed at {:?}",
+        state.rpc_nonblocking_client.commitment().commitment,
+        state.confirm_commitment.commitment
+    );
+    if let Some(fee_payer) = &state.fee_payer {
+        info!("Fee payer: {} (FEE_PAYER_KEY)", fee_payer.pubkey());
+    }
+    for target in &targets {
+        match intent_cfg.target_pdas.get(target) {
+            Some(pda) => info!("Target: {target} (trading through PDA {pda})"),
+            None => info!("Target: {target}"),
+        }
+    }
+    // Wallets of one trader: stats, positions and dedup use the group's first wallet
+    for (member, leader) in target_groups.iter().filter(|(m, l)| m != l) {
+        info!("Target {member} is linked to {leader} (TARGET_GROUPS)");
+    }
+    info!("SLIPPAGE_BPS={slippage_bps}, MAX_BUY_SOL={max_buy_sol}, MIRROR_BUYS_ONLY={mirror_buys_only}, MIRROR_SELLS={mirror_sells}");
+    info!(
+        "SIZE_MODE={:?}, SIZE_RATIO={}, MIN_BUY_SOL={}, INTENT_MODE={:?}",
+        intent_cfg.size_mode, intent_cfg.size_ratio, intent_cfg.min_buy_sol, intent_cfg.mode
+    );
+    if min_target_spend_sol > 0.0 {
+        info!("MIN_TARGET_SPEND_SOL={min_target_spend_sol}");
+    }
+    info!("MAX_PRICE_IMPACT_PCT={max_price_impact_pct}");
+    info!("MIRROR_SELLS_WITHOUT_POSITION={mirror_sells_without_position}, never selling {} mint(s)", never_sell.len());
+    if !token_whitelist.is_empty() {
+        info!("TOKEN_WHITELIST set: only mirroring {} mint(s)", token_whitelist.len());
+    } else if !token_blacklist.is_empty() {
+        info!("TOKEN_BLACKLIST set: never mirroring {} mint(s)", token_blacklist.len());
+    }
+    info!("Priority fee: {:?}", swap_settings.priority_fee);
+    if congestion_fee_threshold > 0 {
+        if !matches!(swap_settings.priority_fee, PriorityFee::Auto { .. }) {
+            warn!("CONGESTION_FEE_THRESHOLD only applies with PRIORITY_FEE_MODE=auto");
+        }
+        info!("CONGESTION_FEE_THRESHOLD={congestion_fee_threshold} lamports");
+    }
+    if swap_settings.max_divergence_pct > 0.0 {
+        info!("MAX_QUOTE_SWAP_DIVERGENCE_PCT={}", swap_settings.max_divergence_pct);
+    }
+
+    let mut tracker = PositionTracker::load(&positions_file)?;
+    tracker.set_strategy(strategy.clone());
+    tracker.set_persist_failure_response(Arc::clone(&notifier), halt_on_persist_fail);
+    let positions = Arc::new(Mutex::new(tracker));
+    if let Some(name) = &strategy {
+        info!("STRATEGY={name}: new positions are tagged with it");
+    }
+    info!(
+        "Tracking {} open position(s) in {positions_file}",
+        positions.lock().unwrap_or_else(|e| e.into_inner()).all_positions().len()
+    );
+
+    // Airdropped tokens the targets may sell later, for WATCH_AIRDROP_ACTION
+    let mut airdrops = if intent_cfg.watch_airdrops {
+        let list = AirdropWatchlist::load(&airdrops_file, airdrop_action)?;
+        info!(
+            "WATCH_AIRDROPS on: {} airdrop(s) watched in {airdrops_file}, WATCH_AIRDROP_ACTION={airdrop_action:?}",
+            list.len()
+        );
+        Some(list)
+    } else {
+        None
+    };
+
+    let stats = Arc::new(Mutex::new(StatsBook::load(&stats_file, max_drawdown_lamports)?));
+    for target in &targets {
+        if stats.lock().unwrap_or_else(|e| e.into_inner()).is_disabled(target) {
+            error!("Target {target} is disabled by MAX_TARGET_DRAWDOWN_SOL; clear it in {stats_file} to resume mirroring");
+        }
+    }
 
-    let http = Client::new();
+    let budget = if daily_limit_lamports > 0 {
+        let budget = Arc::new(Mutex::new(SpendBudget::load(&budget_file, daily_limit_lamports)?));
+        spawn_budget_reporter(Arc::clone(&budget));
+        Some(budget)
+    } else {
+        None
+    };
+
+    if let Some(cfg) = &jito {
+        info!("USE_JITO=true, block engine {}, tip {} lamports", cfg.block_engine_url, cfg.tip_lamports);
+    }
+    if let Some(p) = &proxy {
+        info!("Routing Jupiter, Jito and WS traffic through {:?} proxy {}", p.kind, p.url.host_str().unwrap_or_default());
+    }
+    info!(
+        "Jupiter API at {}{}",
+        jupiter.base_url(),
+        if jupiter.has_api_key() { " (with JUPITER_API_KEY)" } else { "" }
+    );
+    if *jupiter.quote_options() != QuoteOptions::default() {
+        info!("Jupiter quote options: {:?}", jupiter.quote_options());
+    }
+    let mut risk = RiskGuard::new(budget.clone(), Arc::clone(&notifier), max_consecutive_failures, breaker_cooldown);
+    let mut balance_guard = BalanceGuard::new(Arc::clone(&notifier), min_sol_reserve_lamports);
+    info!("MIN_SOL_RESERVE_SOL={} SOL", balance_guard.reserve_lamports() as f64 / 1_000_000_000.0);
+    if risk.max_failures() > 0 {
+        info!(
+            "MAX_CONSECUTIVE_FAILURES={}, BREAKER_COOLDOWN_SECS={}",
+            risk.max_failures(),
+            risk.cooldown().as_secs()
+        );
+    }
+
+    if import_holdings {
+        import_existing_holdings(&jupiter, &state, &positions, &never_sell, slippage_bps).await?;
+    }
+
+    if let Some(every) = pnl_report_interval {
+        spawn_pnl_reporter(Arc::clone(&positions), Arc::clone(&stats), every);
+    }
+
+    if let Some(exit_cfg) = exit {
+        info!(
+            "Exit monitor on: TAKE_PROFIT_PCT={:?}, STOP_LOSS_PCT={:?}, TRAILING_STOP_PCT={:?}, MAX_HOLD_SECONDS={:?}, EXIT_LADDER rungs={}, EXIT_FLOOR_PCT={:?}, every {}s",
+            exit_cfg.take_profit_pct,
+            exit_cfg.stop_loss_pct,
+            exit_cfg.trailing_stop_pct,
+            exit_cfg.max_hold_secs,
+            exit_cfg.ladder.rungs.len(),
+            exit_cfg.policies.floor_pct,
+            exit_cfg.poll_interval.as_secs()
+        );
+        let pools = exit_cfg.realtime.then(|| {
+            info!("REALTIME_POSITION_MONITOR on: re-checking positions when their pools change");
+            let (subscribe, pools_rx) = mpsc::unbounded_channel();
+            let (changed_tx, changed) = mpsc::unbounded_channel();
+            spawn_account_watch(ws_endpoints.primary().to_string(), proxy.clone(), pools_rx, changed_tx);
+            PoolWatch { subscribe, changed }
+        });
+        spawn_exit_monitor(
+            exit_cfg,
+            state.clone(),
+            jupiter.clone(),
+            jito.clone(),
+            Arc::clone(&positions),
+            Arc::clone(&stats),
+            pools,
+        );
+    }
 
     // WS stream (auto reconnect)
-    let mut stream = connect_forever(ws, target_str).await?;
+    let target_strs: Vec<String> = targets.iter().map(|t| t.to_string()).collect();
+    let mut stream = connect_forever(ws_endpoints, target_strs, proxy).await?;
+
+    let mut health = TargetHealth::new(&targets, target_active_window);
+    let mut interlock_paused = false;
+    let token_2022: Pubkey = TOKEN_2022_PROGRAM_ID.parse()?;
 
-    // To avoid rapid duplicate triggers, keep last signature seen
-    let mut last_sig: Option<String> = None;
+    if let Some(r) = &ramp {
+        info!(
+            "Size ramp on: buys start at {}% of MAX_BUY_SOL and reach 100% after {}s",
+            r.start_pct(),
+            r.duration().as_secs()
+        );
+    }
+
+    info!(
+        "MIRROR_COOLDOWN_SECS={}, MIN_TRADE_INTERVAL_SECS={}, LOSS_COOLDOWN_SECS={}",
+        cooldowns.per_mint().as_secs(),
+        cooldowns.min_interval().as_secs(),
+        cooldowns.loss_cooldown().as_secs()
+    );
+    if min_active_targets > 0 {
+        info!("MIN_ACTIVE_TARGETS={min_active_targets} of {} target(s)", targets.len());
+    }
+
+    let mut seen = SeenSignatures::load(&state_file, state_max_sigs)?;
+    info!("Loaded {} seen signature(s) from {state_file}", seen.len());
+    let mut intent_ids = IntentIds::new(state_max_sigs);
+
+    // Samples processed notifications to decide whether they can be trusted
+    let rollback = rollback.map(|m| Arc::new(Mutex::new(m)));
+    if let Some(monitor) = &rollback {
+        let m = monitor.lock().unwrap_or_else(|e| e.into_inner());
+        info!(
+            "Rollback sampling on: MAX_ROLLBACK_RATE={}, confirmed-before-mirror={}",
+            m.max_rate(),
+            m.confirm_first()
+        );
+        drop(m);
+        spawn_rollback_reporter(Arc::clone(monitor));
+    }
 
     while let Some(msg) = stream.next().await {
-        // Extract signature if exists
-        let sig = msg
-            .pointer("/params/result/signature")
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string());
+        let received_at = Instant::now();
+        let sig = msg.signature.clone();
 
-        if let Some(s) = &sig {
-            if last_sig.as_deref() == Some(s.as_str()) {
-                continue;
-            }
-            last_sig = Some(s.clone());
+        debug!("WS msg: {msg:?}");
+
+        if sig.as_deref().is_some_and(|s| seen.contains(s)) {
+            debug!("Signature {sig:?} already processed; skipping");
+            continue;
         }
 
-        debug!("WS msg: {}", msg);
+        if let (Some(monitor), Some(target_sig)) = (&rollback, sig.as_deref().and_then(|s| s.parse::<Signature>().ok())) {
+            let (sample, confirm_first, timeout) = {
+                let mut m = monitor.lock().unwrap_or_else(|e| e.into_inner());
+                (m.should_sample(), m.confirm_first(), m.check_timeout)
+            };
+            if sample {
+                spawn_rollback_check(&state, Arc::clone(monitor), target_sig);
+            }
+            if confirm_first {
+                match wait_confirmed(&state.rpc_nonblocking_client, &target_sig, timeout).await {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        info!("Target tx {target_sig} never reached confirmed; not mirroring it");
+                        continue;
+                    }
+                    Err(e) => {
+                        error!("Confirmation check for target tx {target_sig} failed: {e}; not mirroring it");
+                        continue;
+                    }
+                }
+            }
+        }
 
-        let intent = match infer_intent_from_tx(&msg, max_buy_sol) {
-            Ok(v) => v,
-            Err(e) => {
-                error!("Intent infer error: {e}");
+        let mentioned = mentioned_targets(&msg, &targets);
+        for target in &mentioned {
+            health.mark(target);
+        }
+        if min_active_targets > 0 {
+            let paused = health.below_minimum(min_active_targets);
+            if paused != interlock_paused {
+                interlock_paused = paused;
+                let active = health.active_count();
+                if paused {
+                    warn!(
+                        "Only {active} of {} target feed(s) active (MIN_ACTIVE_TARGETS={min_active_targets}); pausing trading. Stale: {:?}",
+                        targets.len(),
+                        health.stale()
+                    );
+                } else {
+                    warn!("{active} target feed(s) active again; resuming trading");
+                }
+            }
+            if paused {
+                debug!("Notification {sig:?} not mirrored: MIN_ACTIVE_TARGETS interlock");
                 continue;
             }
-        };
-
-        let Some(intent) = intent else { continue; };
+        }
 
-        match intent {
-            crate::types::events::MirrorIntent::Buy { output_mint, max_input_sol } => {
-                // Safety: mirror only BUYs by default
-                if !mirror_buys_only {
-                    info!("BUY intent detected but MIRROR_BUYS_ONLY=false; continuing anyway");
+        // Linked wallets in the same tx produce the same intent once per wallet; mirror it once
+        let mut mirrored: HashSet<(Pubkey, Pubkey, &'static str)> = HashSet::new();
+        for wallet in mentioned {
+            let target = logical_target(&target_groups, wallet);
+            let intents = match infer_intent_from_tx(&msg, &wallet, &intent_cfg) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("Intent infer error for target {wallet}: {e}");
+                    continue;
                 }
+            };
 
-                // Convert SOL to lamports
-                let lamports = sol_to_lamports(max_input_sol)?;
-                info!("Mirroring BUY: spend up to {max_input_sol} SOL ({lamports} lamports) -> mint {output_mint}");
-
-                let quote = jupiter_quote(
-                    &http,
-                    SOL_MINT,
-                    &output_mint.to_string(),
-                    lamports,
-                    slippage_bps,
-                )
-                .await;
-
-                let quote = match quote {
-                    Ok(q) => q,
-                    Err(e) => {
-                        error!("Quote failed: {e}");
-                        continue;
+            for intent in intents {
+                let intent = match airdrops.as_mut() {
+                    Some(list) => {
+                        let held = positions
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .get_position(intent.mint())
+                            .is_some();
+                        match list.resolve(intent, &target, sig.as_deref().unwrap_or_default(), held, max_buy_sol) {
+                            Ok(Some(intent)) => intent,
+                            Ok(None) => continue,
+                            Err(e) => {
+                                error!("Airdrop watch list update failed: {e}");
+                                continue;
+                            }
+                        }
                     }
+                    None => intent,
                 };
-
-                let swap = jupiter_swap_tx(
-                    &http,
-                    quote.clone(),
-                    state.wallet_pubkey,
-                    0, // you can set tip/priority fee if you want
-                )
-                .await;
-
-                let swap = match swap {
-                    Ok(s) => s,
+                if !mirrored.insert((target, *intent.mint(), intent.direction())) {
+                    debug!("Intent {intent:?} from {wallet} already mirrored for linked target {target}");
+                    continue;
+                }
+                if stats.lock().unwrap_or_else(|e| e.into_inner()).is_disabled(&target) {
+                    info!("Intent from disabled target {target} ignored: {intent:?}");
+                    continue;
+                }
+                let id = match intent_ids.claim(
+                    &instance_namespace,
+                    sig.as_deref().unwrap_or_default(),
+                    intent.mint(),
+                    intent.direction(),
+                ) {
+                    Ok(id) => id,
                     Err(e) => {
-                        error!("Swap tx build failed: {e}");
+                        error!("Intent {intent:?} from {target} not mirrored: {e}");
                         continue;
                     }
                 };
+                info!("Target {target} triggered intent {id}: {intent:?}");
+                metrics().trades_detected.inc();
+                notifier.notify(&format!("Target {target}: {} {} (intent {id})", intent.direction(), intent.mint()));
+
+                if let Some(reason) = token_filter_reason(intent.mint(), &token_whitelist, &token_blacklist) {
+                    info!("Intent {id} on mint {} skipped: {reason}", intent.mint());
+                    continue;
+                }
+                let Some(intent) = decide(&hooks, &target, intent) else { continue };
+                // A trade whose position cannot be persisted would be lost to exits and caps on restart
+                if let Some(reason) = positions.lock().unwrap_or_else(|e| e.into_inner()).persist_halt() {
+                    warn!("Intent {id} on mint {} not mirrored: {reason}", intent.mint());
+                    continue;
+                }
+
+                match intent.clone() {
+                    crate::types::events::MirrorIntent::Buy {
+                        output_mint,
+                        max_input_sol,
+                        observed_input_sol,
+                        token_program,
+                    } => {
+                        if let Some(reason) = risk.check() {
+                            info!("BUY of mint {output_mint} not mirrored: {reason}");
+                            continue;
+                        }
+                        // Safety: mirror only BUYs by default
+                        if !mirror_buys_only {
+                            info!("BUY intent detected but MIRROR_BUYS_ONLY=false; continuing anyway");
+                        }
+                        if token_program == token_2022 {
+                            info!("Mint {output_mint} is a Token-2022 token; a transfer fee may cut the amount received");
+                        }
+
+                        if let Some(spent) = observed_input_sol.filter(|s| *s < min_target_spend_sol) {
+                            debug!(
+                                skip_reason = "min_target_spend",
+                                mint = %output_mint,
+                                target_spent_sol = spent,
+                                "BUY skipped: target spent less than MIN_TARGET_SPEND_SOL={min_target_spend_sol}"
+                            );
+                            continue;
+                        }
+                        if let Some(reason) = cooldowns.buy_blocked(&output_mint) {
+                            info!("BUY of mint {output_mint} suppressed by cooldown ({reason})");
+                            continue;
+                        }
+                        let last_outcome = positions.lock().unwrap_or_else(|e| e.into_inner()).last_outcome(&output_mint);
+                        if let Some(reason) = cooldowns.loss_blocked(last_outcome) {
+                            info!("BUY of mint {output_mint} suppressed by loss cooldown ({reason})");
+                            continue;
+                        }
+                        // Adding to a position the exit monitor is selling would leave it half-sold
+                        if let Some(trigger) = positions
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .exit_in_flight(&output_mint)
+                        {
+                            info!("BUY of mint {output_mint} skipped: {trigger} sell in flight");
+                            continue;
+                        }
 
-                let sent = sign_and_send_swap(
-                    &state.rpc_nonblocking_client,
-                    &state.wallet,
-                    &swap.swap_transaction,
-                )
-                .await;
+                        // Convert SOL to lamports
+                        let mut wanted = sol_to_lamports(max_input_sol)?;
+                        if let Some(ramp) = &ramp {
+                            let cap = ramp.cap_lamports(sol_to_lamports(max_buy_sol)?);
+                            if wanted > cap {
+                                debug!("BUY of mint {output_mint} ramped down to {cap} lamports ({:.0}% of MAX_BUY_SOL)", ramp.factor() * 100.0);
+                                wanted = cap;
+                            }
+                            if wanted == 0 {
+                                info!("BUY of mint {output_mint} skipped: size ramp still at 0");
+                                continue;
+                            }
+                        }
 
-                match sent {
-                    Ok(sig) => info!("Mirrored BUY sent: {sig}"),
-                    Err(e) => error!("Send failed: {e}"),
+                        // Per-mint exposure cap, computed on lamports so repeated buys never drift past it
+                        let already_spent = positions
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .get_position(&output_mint)
+                            .map(|p| p.lamports_spent)
+                            .unwrap_or(0);
+                        let mut lamports = clamp_to_mint_cap(wanted, already_spent, max_lamports_per_mint);
+                        if lamports == 0 {
+                            info!("BUY of mint {output_mint} skipped: MAX_SOL_PER_MINT reached ({already_spent} lamports already spent)");
+                            continue;
+                        }
+                        if lamports < wanted {
+                            info!("BUY of mint {output_mint} clamped from {wanted} to {lamports} lamports by MAX_SOL_PER_MINT ({already_spent} already spent)");
+                        }
+                        if let Some(budget) = &budget {
+                            let available = budget.lock().unwrap_or_else(|e| e.into_inner()).available_for_buy();
+                            if available == 0 {
+                                info!("BUY of mint {output_mint} not mirrored: MAX_DAILY_SOL reached");
+                                continue;
+                            }
+                            if lamports > available {
+                                info!("BUY of mint {output_mint} clamped from {lamports} to {available} lamports by MAX_DAILY_SOL");
+                                lamports = available;
+                            }
+                        }
+                        let lamports = round_down_lamports(lamports, amount_round_lamports);
+                        if lamports == 0 {
+                            info!("BUY of mint {output_mint} skipped: amount rounds to 0 with AMOUNT_ROUND_LAMPORTS={amount_round_lamports}");
+                            continue;
+                        }
+                        match balance_guard.check(&state.rpc_nonblocking_client, &state.wallet_pubkey, lamports).await {
+                            Ok(None) => {}
+                            Ok(Some(reason)) => {
+                                warn!("BUY of mint {output_mint} skipped: {reason}");
+                                continue;
+                            }
+                            Err(e) => warn!("Wallet balance pre-flight failed: {e}; sending anyway"),
+                        }
+                        let target_spend = observed_input_sol.map_or_else(|| "unknown".to_string(), |s| format!("{s} SOL"));
+                        info!(
+                            "Mirroring BUY: spend up to {} SOL ({lamports} lamports) -> mint {output_mint} (target spent {target_spend})",
+                            lamports as f64 / 1_000_000_000.0
+                        );
+
+                        let quote = jupiter
+                            .quote(
+                                SOL_MINT,
+                                &output_mint.to_string(),
+                                lamports,
+                                slippage_bps,
+                                SwapMode::ExactIn,
+                            )
+                            .await;
+
+                        let (swap_transaction, est_out) = match quote {
+                            Ok(quote) => {
+                                if let Some(impact) = excess_price_impact(&quote, max_price_impact_pct) {
+                                    info!("BUY of mint {output_mint} skipped: price impact {impact:.2}% exceeds MAX_PRICE_IMPACT_PCT={max_price_impact_pct}");
+                                    continue;
+                                }
+                                match quote.price_impact_pct() {
+                                    Some(impact) => debug!("Quote price impact for {output_mint}: {impact:.4}%"),
+                                    None if max_price_impact_pct > 0.0 => {
+                                        warn!("Quote for {output_mint} has no priceImpactPct; liquidity not checked")
+                                    }
+                                    None => {}
+                                }
+
+                                let est_out = quote.out_amount();
+
+                                let fee = swap_settings
+                                    .priority_fee
+                                    .quote(&state.rpc_nonblocking_client, &quote.pool_accounts())
+                                    .await;
+                                if let Some(estimate) = congestion_pause(&intent, &fee, congestion_fee_threshold) {
+                                    warn!(
+                                        "BUY of mint {output_mint} paused: priority fee estimate {estimate} lamports exceeds CONGESTION_FEE_THRESHOLD={congestion_fee_threshold}"
+                                    );
+                                    continue;
+                                }
+                                let swap = match jupiter.swap_tx(quote, state.wallet_pubkey, fee.lamports).await {
+                                    Ok(s) => Ok((s, est_out)),
+                                    // The route often disappears between quote and swap; quote it again
+                                    Err(e) => match swap_settings.requote_slippage_bps(slippage_bps) {
+                                        Some(requote_bps) if e.is_retriable() => {
+                                            warn!("Swap tx build failed: {e}; re-quoting BUY of {output_mint} at {requote_bps} bps");
+                                            requote_exact_in(
+                                                &jupiter,
+                                                &state.wallet_pubkey,
+                                                SOL_MINT,
+                                                &output_mint.to_string(),
+                                                lamports,
+                                                requote_bps,
+                                                max_price_impact_pct,
+                                                fee.lamports,
+                                            )
+                                            .await
+                                                .map_err(|re| anyhow!("{e}; re-quote at {requote_bps} bps: {re}"))
+                                        }
+                                        _ => Err(e.into()),
+                                    },
+                                };
+                                let (swap, est_out) = match swap {
+                                    Ok(s) => s,
+                                    Err(e) => {
+                                        error!(
+                                            mint = %output_mint,
+                                            lamports,
+                                            slippage_bps,
+                                            "Swap tx build failed: {e}"
+                                        );
+                                        metrics().trades_failed.inc();
+                                        notifier.notify(&format!("BUY of {output_mint} failed: swap tx build failed: {e}"));
+                                        continue;
+                                    }
+                                };
+                                if let Err(e) = check_swap_divergence(SwapMode::ExactIn, est_out, &swap.swap_transaction, swap_settings.max_divergence_pct) {
+                                    error!("BUY of mint {output_mint} aborted: {e}");
+                                    continue;
+                                }
+                                (swap.swap_transaction, est_out)
+                            }
+                            Err(e) => {
+                                // A token still on its Pump.fun bonding curve cannot be routed by Jupiter
+                                let pumpfun = if swap_settings.pumpfun_routing {
+                                    let fee = swap_settings.priority_fee.lamports_for(&state.rpc_nonblocking_client, &[]).await;
+                                    let swap = pumpfun_buy(
+                                        &state.rpc_nonblocking_client,
+                                        &state.wallet_pubkey,
+                                        &output_mint,
+                                        &token_program,
+                                        lamports,
+                                        slippage_bps,
+                                        fee,
+                                    )
+                                    .await;
+                                    match swap {
+                                        Ok(swap) => Some(swap),
+                                        Err(pe) => {
+                                            debug!("BUY of {output_mint} not routable through Pump.fun: {pe}");
+                                            None
+                                        }
+                                    }
+                                } else {
+                                    None
+                                };
+                                match pumpfun {
+                                    Some(swap) => {
+                                        info!(
+                                            "Quote failed: {e}; BUY of {output_mint} routed through Pump.fun curve {}: ~{} out (max cost {} lamports)",
+                                            swap.bonding_curve, swap.out_amount, swap.max_sol_cost
+                                        );
+                                        (swap.swap_transaction, swap.out_amount)
+                                    }
+                                    None if swap_settings.raydium_fallback => {
+                                        warn!("Quote failed: {e}; building the BUY of {output_mint} against Raydium directly");
+                                        let fee = swap_settings.priority_fee.lamports_for(&state.rpc_nonblocking_client, &[]).await;
+                                        let swap = raydium_swap(
+                                            &state.rpc_nonblocking_client,
+                                            &state.wallet_pubkey,
+                                            &parse_pubkey("SOL_MINT", SOL_MINT)?,
+                                            &output_mint,
+                                            lamports,
+                                            slippage_bps,
+                                            fee,
+                                        )
+                                        .await;
+                                        match swap {
+                                            Ok(swap) => {
+                                                info!(
+                                                    "BUY of {output_mint} routed through Raydium pool {}: ~{} out (min {})",
+                                                    swap.pool, swap.out_amount, swap.min_out_amount
+                                                );
+                                                (swap.swap_transaction, swap.out_amount)
+                                            }
+                                            Err(e) => {
+                                                error!("Raydium fallback failed: {e}");
+                                                metrics().trades_failed.inc();
+                                                notifier.notify(&format!("BUY of {output_mint} failed: Jupiter and Raydium fallback both unavailable: {e}"));
+                                                continue;
+                                            }
+                                        }
+                                    }
+                                    None => {
+                                        error!("Quote failed: {e}");
+                                        continue;
+                                    }
+                                }
+                            }
+                        };
+
+                        // Hold time counts from the send, not the confirmation
+                        let sent_at = now_secs();
+                        observe(&metrics().detect_to_send_latency, received_at.elapsed());
+                        let mut sent = send_swap(&jupiter, &state, jito.as_ref(), &swap_transaction, &swap_settings).await;
+                        let (mut est_out, mut attempt_bps) = (est_out, slippage_bps);
+                        for step in 1..=swap_settings.slippage_retries {
+                            let failure = match &sent {
+                                Ok((_, TxConfirmation::Failed(err))) => err.clone(),
+                                Err(e) => e.to_string(),
+                                Ok(_) => break,
+                            };
+                            let Some(next_bps) = swap_settings.escalated_slippage_bps(attempt_bps).filter(|_| is_slippage_error(&failure)) else {
+                                break;
+                            };
+                            info!(
+                                "BUY of {output_mint} hit slippage at {attempt_bps} bps ({failure}); escalation {step}/{}: re-quoting at {next_bps} bps",
+                                swap_settings.slippage_retries
+                            );
+                            let fee = swap_settings.priority_fee.lamports_for(&state.rpc_nonblocking_client, &[]).await;
+                            let requoted = requote_exact_in(
+                                &jupiter,
+                                &state.wallet_pubkey,
+                                SOL_MINT,
+                                &output_mint.to_string(),
+                                lamports,
+                                next_bps,
+                                max_price_impact_pct,
+                                fee,
+                            )
+                            .await
+                            .and_then(|(swap, out)| {
+                                check_swap_divergence(SwapMode::ExactIn, out, &swap.swap_transaction, swap_settings.max_divergence_pct)?;
+                                Ok((swap, out))
+                            });
+                            let (swap, out) = match requoted {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    warn!("Slippage escalation for BUY of {output_mint} stopped: {e}");
+                                    break;
+                                }
+                            };
+                            (est_out, attempt_bps) = (out, next_bps);
+                            sent = send_swap(&jupiter, &state, jito.as_ref(), &swap.swap_transaction, &swap_settings).await;
+                        }
+                        if sent.is_ok() {
+                            cooldowns.record_buy(&output_mint);
+                        }
+
+                        let (sig, confirmation) = match sent {
+                            Ok(v) => v,
+                            Err(e) => {
+                                error!("Send failed: {e}");
+                                metrics().trades_failed.inc();
+                                risk.record_failure();
+                                notifier.notify(&format!("BUY of {output_mint} failed to send: {e}"));
+                                continue;
+                            }
+                        };
+                        balance_guard.record_spend(lamports);
+                        for hook in &hooks {
+                            hook.on_sent(&intent, &sig);
+                        }
+                        if notify_on == NotifyOn::Send {
+                            notifier.notify(&format!("BUY sent: {lamports} lamports -> {output_mint}\n{sig}"));
+                        }
+                        metrics().trades_sent.inc();
+                        if matches!(confirmation, TxConfirmation::Failed(_)) {
+                            risk.record_failure();
+                        } else {
+                            risk.record_success();
+                        }
+                        match &confirmation {
+                            TxConfirmation::Confirmed => {
+                                info!("Mirrored BUY confirmed: {sig}");
+                                metrics().trades_confirmed.inc();
+                                match notify_on {
+                                    NotifyOn::Send => notifier.notify(&format!("BUY confirmed: {output_mint}\n{sig}")),
+                                    NotifyOn::Confirm => {
+                                        spawn_fill_notification(&state, Arc::clone(&notifier), "BUY", output_mint, sig)
+                                    }
+                                }
+                            }
+                            TxConfirmation::Timeout => warn!(
+                                "Mirrored BUY {sig} not confirmed within {}s; it may still land",
+                                confirm_timeout.as_secs()
+                            ),
+                            TxConfirmation::Failed(err) => {
+                                error!("Mirrored BUY {sig} failed on-chain: {err}");
+                                metrics().trades_failed.inc();
+                                notifier.notify(&format!("BUY of {output_mint} failed on-chain: {err}\n{sig}"));
+                                continue;
+                            }
+                        }
+
+                        // An unconfirmed buy may still land, so it counts against the budget
+                        if let Some(budget) = &budget {
+                            if let Err(e) = budget.lock().unwrap_or_else(|e| e.into_inner()).record(lamports) {
+                                error!("Failed to record spend: {e}");
+                            }
+                        }
+                        // Likewise it counts against MAX_SOL_PER_MINT and is tracked for exits
+                        // until it is seen to fail
+                        let opened = positions
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .open_position(&output_mint, &target, lamports, est_out, &sig.to_string(), sent_at);
+                        match opened {
+                            Ok(true) => {}
+                            Ok(false) => debug!("Signature {sig} already recorded; position unchanged"),
+                            Err(e) => error!("Failed to record position: {e}"),
+                        }
+                        if confirmation == TxConfirmation::Timeout {
+                            spawn_pending_buy_check(&state, Arc::clone(&positions), output_mint, sig, lamports, est_out);
+                        }
+                    }
+                    crate::types::events::MirrorIntent::Sell { input_mint, fraction } => {
+                        if never_sell.contains(&input_mint) {
+                            info!("SELL intent for mint {input_mint} ignored: mint is in NEVER_SELL_MINTS");
+                            continue;
+                        }
+                        // Under target_follow_with_floor a full exit by the target always closes us out
+                        let follow_exit = fraction >= FULL_EXIT_FRACTION
+                            && exit_policies.resolve(&input_mint, Some(&target)) == ExitPolicy::TargetFollowWithFloor;
+                        if !mirror_sells && !follow_exit {
+                            info!("SELL intent detected for mint {input_mint} but MIRROR_SELLS=false. Skipping.");
+                            continue;
+                        }
+                        let tracked = positions
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .get_position(&input_mint)
+                            .is_some();
+                        if !tracked && !mirror_sells_without_position {
+                            info!("SELL intent for mint {input_mint} but we have no recorded position and MIRROR_SELLS_WITHOUT_POSITION=false. Skipping.");
+                            continue;
+                        }
+                        // A target's full exit is never held back
+                        if let Some(reason) = cooldowns.trade_blocked().filter(|_| !follow_exit) {
+                            info!("SELL of mint {input_mint} suppressed by cooldown ({reason})");
+                            continue;
+                        }
+                        let trigger = if follow_exit { "target-exit" } else { "mirror-sell" };
+                        if let Err(holder) = positions
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .claim_exit(&input_mint, trigger)
+                        {
+                            info!("SELL of mint {input_mint} skipped: a {holder} sell is already in flight");
+                            continue;
+                        }
+
+                        let sold = mirror_sell(
+                            &jupiter,
+                            &state,
+                            jito.as_ref(),
+                            input_mint,
+                            fraction,
+                            &swap_settings,
+                        )
+                        .await;
+                        if matches!(sold, Ok(Some(_))) {
+                            cooldowns.record_trade();
+                        }
+                        match sold {
+                            Ok(Some(fill)) => {
+                                for hook in &hooks {
+                                    hook.on_sent(&intent, &fill.signature);
+                                }
+                                metrics().trades_sent.inc();
+                                if notify_on == NotifyOn::Send {
+                                    notifier.notify(&format!(
+                                        "SELL sent: {input_mint} -> {} lamports (quoted)\n{}",
+                                        fill.out_lamports, fill.signature
+                                    ));
+                                }
+                                let confirmed = match &fill.confirmation {
+                                    TxConfirmation::Confirmed => {
+                                        info!("Mirrored SELL confirmed: {}", fill.signature);
+                                        metrics().trades_confirmed.inc();
+                                        match notify_on {
+                                            NotifyOn::Send => {
+                                                notifier.notify(&format!("SELL confirmed: {input_mint}\n{}", fill.signature))
+                                            }
+                                            NotifyOn::Confirm => spawn_fill_notification(
+                                                &state,
+                                                Arc::clone(&notifier),
+                                                "SELL",
+                                                input_mint,
+                                                fill.signature,
+                                            ),
+                                        }
+                                        true
+                                    }
+                                    TxConfirmation::Timeout => {
+                                        warn!(
+                                            "Mirrored SELL {} not confirmed within {}s; position left unchanged",
+                                            fill.signature,
+                                            confirm_timeout.as_secs()
+                                        );
+                                        false
+                                    }
+                                    TxConfirmation::Failed(err) => {
+                                        error!("Mirrored SELL {} failed on-chain: {err}", fill.signature);
+                                        metrics().trades_failed.inc();
+                                        notifier.notify(&format!("SELL of {input_mint} failed on-chain: {err}\n{}", fill.signature));
+                                        false
+                                    }
+                                };
+                                if confirmed {
+                                    let (strategy, reduced) = {
+                                        let mut positions = positions.lock().unwrap_or_else(|e| e.into_inner());
+                                        let strategy = positions.strategy_of(&input_mint);
+                                        (strategy, positions.reduce_position(&input_mint, fill.fraction_of_holding))
+                                    };
+                                    match reduced {
+                                        Ok(Some(cost_lamports)) => {
+                                            let pnl = fill.out_lamports as i64 - cost_lamports as i64;
+                                            positions.lock().unwrap_or_else(|e| e.into_inner()).record_outcome(&input_mint, pnl);
+                                            info!(
+                                                "Realized PnL on {input_mint}: {pnl} lamports (quoted, strategy {})",
+                                                strategy.as_deref().unwrap_or("untagged")
+                                            );
+                                            let mut stats = stats.lock().unwrap_or_else(|e| e.into_inner());
+                                            let mut booked = stats.record_realized(&target, strategy.as_deref(), pnl);
+                                            if booked.is_ok() && fill.fraction_of_holding >= 1.0 {
+                                                info!("Position {input_mint} closed by {trigger}");
+                                                booked = stats.record_exit(&target, trigger);
+                                            }
+                                            if let Err(e) = booked {
+                                                error!("Failed to record stats: {e}");
+                                            }
+                                        }
+                                        Ok(None) => {}
+                                        Err(e) => error!("Failed to update position: {e}"),
+                                    }
+                                }
+                            }
+                            Ok(None) => {}
+                            Err(e) => {
+                                error!("SELL mirror failed: {e}");
+                                metrics().trades_failed.inc();
+                                notifier.notify(&format!("SELL of {input_mint} failed: {e}"));
+                            }
+                        }
+                        positions.lock().unwrap_or_else(|e| e.into_inner()).release_exit(&input_mint);
+                    }
+                    // Only produced with WATCH_AIRDROPS, and consumed by the watch list above
+                    crate::types::events::MirrorIntent::Watch { mint, .. } => {
+                        debug!("Airdrop of {mint} not watched");
+                    }
                 }
             }
-            crate::types::events::MirrorIntent::Sell { .. } => {
-                info!("SELL intent detected (not implemented in minimal safe build). Skipping.");
+        }
+
+        if let Some(s) = &sig {
+            if let Err(e) = seen.insert(s) {
+                error!("Failed to persist seen signature: {e}");
             }
         }
     }
@@ -122,6 +1187,304 @@ pub async fn run_copy_trader() -> Result<()> {
     Ok(())
 }
 
+/// Tracks every token the wallet already holds as an imported position, with its cost
+/// basis estimated from a current Jupiter quote (0 = unknown basis if it cannot be quoted).
+/// Imported positions belong to no target, so their exits are not booked in the per-target
+/// stats, and one with an unknown basis can only be closed by `MAX_HOLD_SECONDS`.
+async fn import_existing_holdings(
+    jupiter: &JupiterClient,
+    state: &AppState,
+    positions: &Mutex<PositionTracker>,
+    never_sell: &HashSet<Pubkey>,
+    slippage_bps: u16,
+) -> Result<()> {
+    let holdings = token_holdings(&state.rpc_nonblocking_client, &state.wallet_pubkey)
+        .await
+        .map_err(|e| anyhow!("Failed to list wallet holdings: {e}"))?;
+
+    let mut imported = 0;
+    for (mint, amount) in holdings {
+        if never_sell.contains(&mint) {
+            continue;
+        }
+        let est_cost = match jupiter.quote(&mint.to_string(), SOL_MINT, amount, slippage_bps, SwapMode::ExactIn).await {
+            Ok(quote) => quote.out_amount(),
+            Err(e) => {
+                warn!("Could not price existing holding {mint}: {e}; importing with unknown cost basis");
+                0
+            }
+        };
+        if positions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .import_position(&mint, est_cost, amount)?
+        {
+            info!("Imported existing holding {mint}: {amount} raw units, est. cost basis {est_cost} lamports");
+            imported += 1;
+        }
+    }
+    info!("IMPORT_EXISTING_HOLDINGS: imported {imported} position(s)");
+    Ok(())
+}
+
+/// Logs the remaining daily budget once an hour.
+fn spawn_budget_reporter(budget: Arc<Mutex<SpendBudget>>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(3600));
+        loop {
+            ticker.tick().await;
+            let mut budget = budget.lock().unwrap_or_else(|e| e.into_inner());
+            info!(
+                "Daily spend budget: {} of {} lamports remaining",
+                budget.remaining_lamports(),
+                budget.limit_lamports()
+            );
+        }
+    });
+}
+
+/// Logs the open positions (cost basis, average entry) and cumulative realized PnL
+/// every `PNL_REPORT_SECS`.
+fn spawn_pnl_reporter(positions: Arc<Mutex<PositionTracker>>, stats: Arc<Mutex<StatsBook>>, every: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(every);
+        loop {
+            ticker.tick().await;
+            let (realized, closed) = stats.lock().unwrap_or_else(|e| e.into_inner()).total_realized();
+            let tracker = positions.lock().unwrap_or_else(|e| e.into_inner());
+            let mut open = tracker.all_positions();
+            open.sort_by_key(|p| p.opened_at);
+            let cost: u64 = open.iter().map(|p| p.lamports_spent).sum();
+            info!(
+                "PnL: {} open position(s), {:.4} SOL at cost; realized {:+.4} SOL over {closed} closed trade(s)",
+                open.len(),
+                cost as f64 / 1_000_000_000.0,
+                realized as f64 / 1_000_000_000.0
+            );
+            for p in open {
+                // Average entry in lamports per raw token unit; 0 when nothing was quoted
+                let avg_entry = if p.est_out_amount > 0 {
+                    p.lamports_spent as f64 / p.est_out_amount as f64
+                } else {
+                    0.0
+                };
+                info!(
+                    "  {} amount={} spent={:.4} SOL avg_entry={avg_entry:.6} lamports/unit buys={}{}",
+                    p.mint,
+                    p.est_out_amount,
+                    p.lamports_spent as f64 / 1_000_000_000.0,
+                    p.signatures.len(),
+                    if p.imported { " (imported)" } else { "" }
+                );
+            }
+        }
+    });
+}
+
+/// Checks in the background whether a sampled processed notification reached `confirmed`.
+fn spawn_rollback_check(state: &AppState, monitor: Arc<Mutex<RollbackMonitor>>, sig: Signature) {
+    let rpc = Arc::clone(&state.rpc_nonblocking_client);
+    tokio::spawn(async move {
+        let timeout = monitor.lock().unwrap_or_else(|e| e.into_inner()).check_timeout;
+        match wait_confirmed(&rpc, &sig, timeout).await {
+            Ok(confirmed) => {
+                if !confirmed {
+                    warn!("Sampled processed tx {sig} was not confirmed within {}s (rolled back)", timeout.as_secs());
+                }
+                monitor.lock().unwrap_or_else(|e| e.into_inner()).record(!confirmed);
+            }
+            Err(e) => debug!("Rollback sample for {sig} dropped: {e}"),
+        }
+    });
+}
+
+/// `NOTIFY_ON=confirm`: reports what the confirmed `sig` actually filled for our wallet,
+/// off the trade loop. Falls back to a plain confirmation if the fill cannot be read.
+fn spawn_fill_notification(state: &AppState, notifier: Arc<dyn Notifier>, side: &'static str, mint: Pubkey, sig: Signature) {
+    let rpc = Arc::clone(&state.rpc_nonblocking_client);
+    let owner = state.wallet_pubkey;
+    tokio::spawn(async move {
+        let fill = confirmed_fill(&rpc, &sig, &owner, &mint)
+            .await
+            .inspect_err(|e| debug!("Could not read the fill of {sig}: {e}"))
+            .ok();
+        notifier.notify(&fill_message(side, &mint, &sig, fill));
+    });
+}
+
+/// The `NOTIFY_ON=confirm` text for a confirmed swap: the tokens and SOL it moved, or just
+/// the confirmation when the fill is unknown.
+fn fill_message(side: &str, mint: &Pubkey, sig: &Signature, fill: Option<(f64, i64)>) -> String {
+    match fill {
+        Some((tokens, lamports)) => {
+            format!("{side} filled: {tokens:+} of {mint} for {:+.6} SOL\n{sig}", lamports as f64 / 1_000_000_000.0)
+        }
+        None => format!("{side} confirmed: {mint}\n{sig}"),
+    }
+}
+
+/// What the confirmed transaction `sig` moved for `owner`: the change in its `mint`
+/// balance in whole tokens and in its lamports (fee included when it paid it). Read back
+/// with `getTransaction`, which can lag the confirmation slightly, so it is polled.
+async fn confirmed_fill(rpc: &AsyncRpcClient, sig: &Signature, owner: &Pubkey, mint: &Pubkey) -> Result<(f64, i64)> {
+    let params = json!([
+        sig.to_string(),
+        { "encoding": "json", "commitment": "confirmed", "maxSupportedTransactionVersion": 0 }
+    ]);
+    let mut result = serde_json::Value::Null;
+    for attempt in 1..=5u64 {
+        result = rpc.send(RpcRequest::GetTransaction, params.clone()).await?;
+        if !result.is_null() {
+            break;
+        }
+        sleep(Duration::from_millis(400 * attempt)).await;
+    }
+    if result.is_null() {
+        return Err(anyhow!("transaction not returned by getTransaction"));
+    }
+    let tx: TransactionNotification = serde_json::from_value(result)?;
+    let meta = tx.meta.as_ref().ok_or_else(|| anyhow!("transaction has no meta"))?;
+
+    let (owner_str, mint_str) = (owner.to_string(), mint.to_string());
+    let held = |entries: &[TokenBalance]| -> (u64, u8) {
+        entries
+            .iter()
+            .filter(|b| b.mint == mint_str && b.owner.as_deref() == Some(owner_str.as_str()))
+            .fold((0, 0), |(raw, _), b| {
+                (raw + b.ui_token_amount.amount.parse::<u64>().unwrap_or(0), b.ui_token_amount.decimals)
+            })
+    };
+    let (pre, pre_decimals) = held(&meta.pre_token_balances);
+    let (post, post_decimals) = held(&meta.post_token_balances);
+    let tokens = (post as i128 - pre as i128) as f64 / 10f64.powi(pre_decimals.max(post_decimals) as i32);
+
+    let lamports = account_keys(&tx)
+        .and_then(|keys| keys.iter().position(|k| k == owner))
+        .and_then(|i| Some(*meta.post_balances.get(i)? as i64 - *meta.pre_balances.get(i)? as i64))
+        .unwrap_or(0);
+    Ok((tokens, lamports))
+}
+
+fn spawn_rollback_reporter(monitor: Arc<Mutex<RollbackMonitor>>) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(600));
+        loop {
+            ticker.tick().await;
+            let m = monitor.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(rate) = m.rate() {
+                info!(
+                    "Processed rollback rate: {:.2}% over {} samples (confirmed-before-mirror={})",
+                    rate * 100.0,
+                    m.samples(),
+                    m.confirm_first()
+                );
+            }
+        }
+    });
+}
+
+/// `TARGET_PDA`: the smart-wallet PDA that owns a target's token accounts. Either a bare
+/// pubkey (single target only) or `target:pda` pairs, comma-separated.
+fn parse_target_pdas(targets: &[Pubkey]) -> Result<HashMap<Pubkey, Pubkey>> {
+    let raw = env_var_opt("TARGET_PDA").unwrap_or_default();
+    let mut pdas = HashMap::new();
+    for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (target, pda) = match entry.split_once(':') {
+            Some((target, pda)) => (parse_pubkey("TARGET_PDA", target.trim())?, parse_pubkey("TARGET_PDA", pda.trim())?),
+            None if targets.len() == 1 => (targets[0], parse_pubkey("TARGET_PDA", entry)?),
+            None => return Err(anyhow!("TARGET_PDA must use target:pda pairs when several targets are set")),
+        };
+        if !targets.contains(&target) {
+            return Err(anyhow!("TARGET_PDA entry for {target}, which is not a configured target"));
+        }
+        pdas.insert(target, pda);
+    }
+    Ok(pdas)
+}
+
+/// `TARGET_GROUPS`: wallets run by one trader, as `+`-joined groups separated by commas
+/// (`A+B,C+D+E`). Maps every member to its group's first wallet, which stands for the
+/// group in stats and positions, so a buy from A and a sell from B count as one trader's.
+fn parse_target_groups(raw: &str, targets: &[Pubkey]) -> Result<HashMap<Pubkey, Pubkey>> {
+    let mut groups = HashMap::new();
+    for group in raw.split(',').map(str::trim).filter(|g| !g.is_empty()) {
+        let members = group
+            .split('+')
+            .map(|m| parse_pubkey("TARGET_GROUPS", m.trim()))
+            .collect::<Result<Vec<_>>>()?;
+        let leader = members[0];
+        for member in members {
+            if !targets.contains(&member) {
+                return Err(anyhow!("TARGET_GROUPS lists {member}, which is not a configured target"));
+            }
+            if groups.insert(member, leader).is_some() {
+                return Err(anyhow!("TARGET_GROUPS lists {member} in more than one group"));
+            }
+        }
+    }
+    Ok(groups)
+}
+
+/// The target `wallet`'s trades count for: its group's first wallet, or itself.
+fn logical_target(groups: &HashMap<Pubkey, Pubkey>, wallet: Pubkey) -> Pubkey {
+    groups.get(&wallet).copied().unwrap_or(wallet)
+}
+
+/// Mints we never sell: `NEVER_SELL_MINTS` (default USDC), always including wrapped SOL.
+fn parse_never_sell() -> Result<HashSet<Pubkey>> {
+    let raw = env_var_opt("NEVER_SELL_MINTS").unwrap_or_else(|| USDC_MINT.to_string());
+    let mut mints: HashSet<Pubkey> = parse_pubkey_list("NEVER_SELL_MINTS", &raw)?.into_iter().collect();
+    mints.insert(parse_pubkey("SOL_MINT", SOL_MINT)?);
+    Ok(mints)
+}
+
+/// Quote-side mints for intent inference: `BASE_MINTS` (default USDC, USDT), always
+/// including wrapped SOL.
+fn parse_base_mints() -> Result<HashSet<String>> {
+    let raw = env_var_opt("BASE_MINTS").unwrap_or_else(|| format!("{USDC_MINT},{USDT_MINT}"));
+    let mut mints: HashSet<String> = parse_pubkey_list("BASE_MINTS", &raw)?
+        .iter()
+        .map(|m| m.to_string())
+        .collect();
+    mints.insert(SOL_MINT.to_string());
+    Ok(mints)
+}
+
+/// `DUST_OVERRIDES_FILE`: a JSON object of mint -> smallest change in whole tokens that
+/// counts as a trade, e.g. `{ "<mint>": 1000.0 }`. Empty when unset.
+fn load_dust_overrides() -> Result<HashMap<String, f64>> {
+    let Some(path) = env_var_opt("DUST_OVERRIDES_FILE").filter(|p| !p.trim().is_empty()) else {
+        return Ok(HashMap::new());
+    };
+    let raw = fs::read_to_string(path.trim()).map_err(|e| anyhow!("Failed to read DUST_OVERRIDES_FILE {path}: {e}"))?;
+    let overrides: HashMap<String, f64> =
+        serde_json::from_str(&raw).map_err(|e| anyhow!("Invalid DUST_OVERRIDES_FILE {path}: {e}"))?;
+    for mint in overrides.keys() {
+        parse_pubkey("DUST_OVERRIDES_FILE", mint)?;
+    }
+    info!("Loaded {} dust override(s) from {path}", overrides.len());
+    Ok(overrides)
+}
+
+/// Mint set from a comma-separated env var (empty when unset).
+fn parse_mint_set(key: &str) -> Result<HashSet<Pubkey>> {
+    let raw = env_var_opt(key).unwrap_or_default();
+    Ok(parse_pubkey_list(key, &raw)?.into_iter().collect())
+}
+
+/// Why `mint` must not be mirrored, if it must not. A non-empty whitelist takes
+/// precedence over the blacklist.
+fn token_filter_reason(
+    mint: &Pubkey,
+    whitelist: &HashSet<Pubkey>,
+    blacklist: &HashSet<Pubkey>,
+) -> Option<&'static str> {
+    if !whitelist.is_empty() {
+        return (!whitelist.contains(mint)).then_some("not in TOKEN_WHITELIST");
+    }
+    blacklist.contains(mint).then_some("in TOKEN_BLACKLIST")
+}
+
 fn sol_to_lamports(sol: f64) -> Result<u64> {
     if !(0.0..=1000.0).contains(&sol) {
         return Err(anyhow!("SOL amount out of safe range"));
@@ -131,5 +1494,768 @@ fn sol_to_lamports(sol: f64) -> Result<u64> {
     Ok(lamports as u64)
 }
 
+/// Lamports we may spend on a buy so total spend on the mint stays within `cap`.
+fn clamp_to_mint_cap(wanted: u64, already_spent: u64, cap: Option<u64>) -> u64 {
+    match cap {
+        Some(cap) => wanted.min(cap.saturating_sub(already_spent)),
+        None => wanted,
+    }
+}
+
+/// Signs Jupiter's swap tx, sends it (as a Jito bundle when `USE_JITO` is on) and
+/// waits up to `settings.confirm_timeout` for it to confirm.
+async fn send_swap(
+    jupiter: &JupiterClient,
+    state: &AppState,
+    jito: Option<&JitoConfig>,
+    swap_b64: &str,
+    settings: &SwapSettings,
+) -> Result<(Signature, TxConfirmation)> {
+    let rpc = &state.rpc_nonblocking_client;
+    let fee_payer = state.fee_payer.as_deref();
+    let mut tx = sign_swap(rpc, &state.wallet, fee_payer, settings.tx_guard.as_ref(), swap_b64).await?;
+    if settings.validate_blockhash {
+        tx = ensure_fresh_blockhash(rpc, &state.wallet, fee_payer, tx).await?;
+    }
+    if settings.simulate_before_send {
+        simulate_signed_swap(rpc, &tx).await?;
+    }
+    let sig = match jito {
+        Some(cfg) => send_with_jito_fallback(jupiter.http(), cfg, rpc, &state.wallet, &tx).await?,
+        None => send_signed_swap(rpc, &tx).await?,
+    };
+    let confirmation = confirm_transaction(rpc, &sig, state.confirm_commitment, settings.confirm_timeout).await?;
+    Ok((sig, confirmation))
+}
+
+/// How long a timed-out buy is watched before it is given up on. A blockhash expires after
+/// 150 slots (about a minute), so a tx that has not landed by then never will.
+const PENDING_BUY_WINDOW: Duration = Duration::from_secs(90);
+
+/// Keeps watching a buy that timed out, off the trade loop, and takes it back out of the
+/// position if it fails or never lands.
+fn spawn_pending_buy_check(
+    state: &AppState,
+    positions: Arc<Mutex<PositionTracker>>,
+    mint: Pubkey,
+    sig: Signature,
+    lamports: u64,
+    est_out: u64,
+) {
+    let rpc = Arc::clone(&state.rpc_nonblocking_client);
+    let commitment = state.confirm_commitment;
+    tokio::spawn(async move {
+        let outcome = match confirm_transaction(&rpc, &sig, commitment, PENDING_BUY_WINDOW).await {
+            Ok(TxConfirmation::Confirmed) => {
+                info!("Timed-out BUY {sig} of {mint} confirmed late");
+                return;
+            }
+            Ok(TxConfirmation::Failed(err)) => format!("failed on-chain: {err}"),
+            Ok(TxConfirmation::Timeout) => format!("not landed within {}s", PENDING_BUY_WINDOW.as_secs()),
+            Err(e) => {
+                warn!("Could not re-check timed-out BUY {sig}: {e}; keeping it in the position");
+                return;
+            }
+        };
+        warn!("Timed-out BUY {sig} of {mint} {outcome}; dropping it from the position");
+        let dropped = positions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .drop_buy(&mint, &sig.to_string(), lamports, est_out);
+        if let Err(e) = dropped {
+            error!("Failed to drop BUY {sig} from the position: {e}");
+        }
+    });
+}
+
+/// Rounds down (so caps are never exceeded) to a multiple of `granularity`.
+fn round_down_lamports(lamports: u64, granularity: u64) -> u64 {
+    if granularity <= 1 {
+        return lamports;
+    }
+    lamports - lamports % granularity
+}
+
+/// Per-swap settings shared by mirrored trades and the exit monitor.
+#[derive(Debug, Clone)]
+pub struct SwapSettings {
+    pub slippage_bps: u16,
+    pub confirm_timeout: Duration,
+    /// Max % the built swap's out amount may fall short of the quote (0 = unchecked).
+    pub max_divergence_pct: f64,
+    /// Re-check the blockhash right before sending and re-sign if it expired.
+    pub validate_blockhash: bool,
+    pub priority_fee: PriorityFee,
+    /// `ENABLE_RAYDIUM_FALLBACK`: when Jupiter quotes still fail after their retries,
+    /// swap against the token's Raydium AMM v4 pool directly.
+    pub raydium_fallback: bool,
+    /// `ENABLE_PUMPFUN_ROUTING`: buy a token Jupiter cannot quote on its Pump.fun bonding
+    /// curve when it has not graduated yet (tried before the Raydium fallback).
+    pub pumpfun_routing: bool,
+    /// `EXACT_OUT_SELLS`: partial sells ask for exactly the SOL their share is quoted at
+    /// (`SwapMode::ExactOut`), so slippage sells a little more of the token instead of
+    /// returning less SOL. Full exits always sell the whole balance.
+    pub exact_out_sells: bool,
+    /// `REQUOTE_SLIPPAGE_BUMP_BPS`: added to the slippage of a fresh quote when building a
+    /// BUY's swap tx fails (0 = no re-quote).
+    pub requote_bump_bps: u16,
+    /// `REQUOTE_MAX_SLIPPAGE_BPS`: the re-quote's slippage never goes above this.
+    pub requote_max_slippage_bps: u16,
+    /// `SLIPPAGE_RETRIES`: times a swap that failed its slippage check is re-quoted with
+    /// escalated slippage and resent (0 = never).
+    pub slippage_retries: u32,
+    /// `MAX_SLIPPAGE_BPS`: escalation never goes above this.
+    pub max_slippage_bps: u16,
+    /// `TX_GUARD`: checks every swap tx before it is signed (see `TxGuard`).
+    pub tx_guard: Option<TxGuard>,
+    /// `SIMULATE_BEFORE_SEND`: simulate each signed swap and skip the send if it would
+    /// fail. Costs a round trip per swap.
+    pub simulate_before_send: bool,
+}
+
+impl SwapSettings {
+    /// Slippage for a re-quote after `slippage_bps` failed to build, `None` when
+    /// re-quoting is off or the cap leaves no room above `slippage_bps`.
+    pub fn requote_slippage_bps(&self, slippage_bps: u16) -> Option<u16> {
+        if self.requote_bump_bps == 0 {
+            return None;
+        }
+        let bumped = slippage_bps.saturating_add(self.requote_bump_bps).min(self.requote_max_slippage_bps);
+        (bumped > slippage_bps).then_some(bumped)
+    }
+
+    /// Next slippage after a slippage failure at `slippage_bps`: half as much again, capped
+    /// at `max_slippage_bps`. `None` once the cap is reached.
+    pub fn escalated_slippage_bps(&self, slippage_bps: u16) -> Option<u16> {
+        let escalated = slippage_bps.saturating_add((slippage_bps / 2).max(1)).min(self.max_slippage_bps);
+        (escalated > slippage_bps).then_some(escalated)
+    }
+}
+
+/// Quotes `amount` of `input_mint` into `output_mint` afresh at `slippage_bps` and builds
+/// its swap tx, returning it with the new quote's out amount. The new quote must still
+/// pass `max_price_impact_pct` (0 = unchecked).
+#[allow(clippy::too_many_arguments)]
+async fn requote_exact_in(
+    jupiter: &JupiterClient,
+    owner: &Pubkey,
+    input_mint: &str,
+    output_mint: &str,
+    amount: u64,
+    slippage_bps: u16,
+    max_price_impact_pct: f64,
+    priority_fee_lamports: u64,
+) -> Result<(SwapResponse, u64)> {
+    let quote = jupiter
+        .quote(input_mint, output_mint, amount, slippage_bps, SwapMode::ExactIn)
+        .await?;
+    if let Some(impact) = excess_price_impact(&quote, max_price_impact_pct) {
+        return Err(anyhow!("price impact {impact:.2}% exceeds MAX_PRICE_IMPACT_PCT={max_price_impact_pct}"));
+    }
+    let est_out = quote.out_amount();
+    let swap = jupiter.swap_tx(quote, *owner, priority_fee_lamports).await?;
+    Ok((swap, est_out))
+}
+
+/// The quote's price impact (percent) when it exceeds `MAX_PRICE_IMPACT_PCT` = `max_pct`
+/// (0 = unchecked). A quote without `priceImpactPct` passes.
+fn excess_price_impact(quote: &QuoteResponse, max_pct: f64) -> Option<f64> {
+    quote.price_impact_pct().filter(|impact| max_pct > 0.0 && *impact > max_pct)
+}
+
+/// Aborts when the swap tx Jupiter built is more than `max_pct` percent worse than the
+/// quote we requested it for: less out for `ExactIn`, more in for `ExactOut`. `quoted`
+/// is the quote's `quoted_amount`.
+fn check_swap_divergence(mode: SwapMode, quoted: u64, swap_b64: &str, max_pct: f64) -> Result<()> {
+    if max_pct <= 0.0 || quoted == 0 {
+        return Ok(());
+    }
+    let built = swap_quoted_out_amount(swap_b64)?
+        .ok_or_else(|| anyhow!("swap tx has no Jupiter route instruction to verify"))?;
+    let (worse, side) = match mode {
+        SwapMode::ExactIn => (quoted.saturating_sub(built), "out"),
+        SwapMode::ExactOut => (built.saturating_sub(quoted), "in"),
+    };
+    let divergence_pct = worse as f64 / quoted as f64 * 100.0;
+    if divergence_pct > max_pct {
+        return Err(anyhow!(
+            "swap expects {built} {side} vs quoted {quoted} ({divergence_pct:.2}% worse, MAX_QUOTE_SWAP_DIVERGENCE_PCT={max_pct})"
+        ));
+    }
+    Ok(())
+}
+
+/// Outcome of a sent mirrored sell.
+pub(crate) struct SellFill {
+    pub signature: Signature,
+    /// Share of our holding that was sold (1.0 on a full exit).
+    pub fraction_of_holding: f64,
+    /// Quoted SOL proceeds.
+    pub out_lamports: u64,
+    pub confirmation: TxConfirmation,
+}
+
+/// Sells `fraction` of our holding of `input_mint` back to SOL via Jupiter (or Raydium
+/// directly, see `SwapSettings::raydium_fallback`).
+/// Returns `Ok(None)` when there is nothing worth selling.
+pub(crate) async fn mirror_sell(
+    jupiter: &JupiterClient,
+    state: &AppState,
+    jito: Option<&JitoConfig>,
+    input_mint: Pubkey,
+    fraction: f64,
+    settings: &SwapSettings,
+) -> Result<Option<SellFill>> {
+    let balance = token_balance(&state.rpc_nonblocking_client, &state.wallet_pubkey, &input_mint)
+        .await
+        .map_err(|e| anyhow!("Token balance lookup failed: {e}"))?;
+
+    if balance == 0 {
+        info!("SELL intent for mint {input_mint} but we hold none. Skipping.");
+        return Ok(None);
+    }
+
+    let amount = sell_amount(balance, fraction)?;
+    if amount == 0 {
+        info!("SELL fraction {fraction} of our {balance} rounds to zero. Skipping.");
+        return Ok(None);
+    }
+
+    let quote = match jupiter.quote(&input_mint.to_string(), SOL_MINT, amount, settings.slippage_bps, SwapMode::ExactIn).await {
+        Ok(quote) => quote,
+        Err(e) if settings.raydium_fallback => {
+            warn!("Quote failed: {e}; building the SELL of {input_mint} against Raydium directly");
+            let rpc = &state.rpc_nonblocking_client;
+            let priority_fee = settings.priority_fee.lamports_for(rpc, &[]).await;
+            let sol = parse_pubkey("SOL_MINT", SOL_MINT)?;
+            let swap = raydium_swap(rpc, &state.wallet_pubkey, &input_mint, &sol, amount, settings.slippage_bps, priority_fee)
+                .await
+                .map_err(|e| anyhow!("Raydium fallback failed: {e}"))?;
+            info!(
+                "Mirroring SELL: {amount}/{balance} raw units of mint {input_mint} through Raydium pool {} for ~{} lamports",
+                swap.pool, swap.out_amount
+            );
+            let (sig, confirmation) = send_swap(jupiter, state, jito, &swap.swap_transaction, settings)
+                .await
+                .map_err(|e| anyhow!("Send failed: {e}"))?;
+            return Ok(Some(SellFill {
+                signature: sig,
+                fraction_of_holding: amount as f64 / balance as f64,
+                out_lamports: swap.out_amount,
+                confirmation,
+            }));
+        }
+        Err(e) => return Err(anyhow!("Quote failed: {e}")),
+    };
+
+    // Lamport-equivalent of what we are about to sell
+    let out_lamports = quote.out_amount();
+    if out_lamports == 0 {
+        info!("SELL of {amount} raw units of mint {input_mint} quotes to 0 lamports. Skipping.");
+        return Ok(None);
+    }
+
+    let (mut quote, mut amount) = (quote, amount);
+    if settings.exact_out_sells && amount < balance {
+        let exact = jupiter
+            .quote(&input_mint.to_string(), SOL_MINT, out_lamports, settings.slippage_bps, SwapMode::ExactOut)
+            .await;
+        match exact {
+            Ok(exact) if exact.max_in_amount() <= balance => {
+                amount = exact.in_amount();
+                quote = exact;
+            }
+            Ok(exact) => info!(
+                "ExactOut SELL of {input_mint} may take up to {} raw units, more than the {balance} held; selling ExactIn",
+                exact.max_in_amount()
+            ),
+            Err(e) => warn!("ExactOut quote for {input_mint} failed: {e}; selling ExactIn"),
+        }
+    }
+    info!(
+        "Mirroring SELL: {amount}/{balance} raw units ({:.2}%) of mint {input_mint} for ~{out_lamports} lamports ({})",
+        fraction * 100.0,
+        quote.swap_mode.as_str()
+    );
+
+    let priority_fee = settings
+        .priority_fee
+        .lamports_for(&state.rpc_nonblocking_client, &quote.pool_accounts())
+        .await;
+    let (mode, quoted) = (quote.swap_mode, quote.quoted_amount());
+    let swap = jupiter.swap_tx(quote, state.wallet_pubkey, priority_fee)
+        .await
+        .map_err(|e| anyhow!("Swap tx build failed: {e}"))?;
+    check_swap_divergence(mode, quoted, &swap.swap_transaction, settings.max_divergence_pct)
+        .map_err(|e| anyhow!("SELL of mint {input_mint} aborted: {e}"))?;
+
+    let (mut sig, mut confirmation) = send_swap(jupiter, state, jito, &swap.swap_transaction, settings)
+        .await
+        .map_err(|e| anyhow!("Send failed: {e}"))?;
+    let (mut out_lamports, mut attempt_bps) = (out_lamports, settings.slippage_bps);
+    for step in 1..=settings.slippage_retries {
+        let TxConfirmation::Failed(err) = &confirmation else { break };
+        let Some(next_bps) = settings.escalated_slippage_bps(attempt_bps).filter(|_| is_slippage_error(err)) else {
+            break;
+        };
+        info!(
+            "SELL of {input_mint} hit slippage at {attempt_bps} bps ({err}); escalation {step}/{}: re-quoting at {next_bps} bps",
+            settings.slippage_retries
+        );
+        let fee = settings.priority_fee.lamports_for(&state.rpc_nonblocking_client, &[]).await;
+        let (swap, out) = match requote_exact_in(jupiter, &state.wallet_pubkey, &input_mint.to_string(), SOL_MINT, amount, next_bps, 0.0, fee).await {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Slippage escalation for SELL of {input_mint} stopped: {e}");
+                break;
+            }
+        };
+        if let Err(e) = check_swap_divergence(SwapMode::ExactIn, out, &swap.swap_transaction, settings.max_divergence_pct) {
+            warn!("Slippage escalation for SELL of {input_mint} stopped: {e}");
+            break;
+        }
+        (out_lamports, attempt_bps) = (out, next_bps);
+        (sig, confirmation) = send_swap(jupiter, state, jito, &swap.swap_transaction, settings)
+            .await
+            .map_err(|e| anyhow!("Send failed: {e}"))?;
+    }
+    Ok(Some(SellFill {
+        signature: sig,
+        fraction_of_holding: amount as f64 / balance as f64,
+        out_lamports,
+        confirmation,
+    }))
+}
+
+/// The priority fee estimate that pauses `intent` as congestion, if it does: above
+/// `CONGESTION_FEE_THRESHOLD` (0 = off). Buys can wait out congestion; sells are never held
+/// back by it.
+fn congestion_pause(intent: &MirrorIntent, fee: &FeeQuote, threshold: u64) -> Option<u64> {
+    if threshold == 0 || !matches!(intent, MirrorIntent::Buy { .. }) {
+        return None;
+    }
+    fee.estimate_lamports.filter(|e| *e > threshold)
+}
+
+/// Raw amount to sell when the target sold `fraction` of its holding and we hold `balance`.
+/// A (near-)full exit sells everything so no dust is left behind; otherwise the fraction is
+/// applied in integer space to avoid f64 precision loss on large raw balances.
+/// Fails on a fraction outside 0..=1, which a trade hook's `Decision::Modify` can produce.
+fn sell_amount(balance: u64, fraction: f64) -> Result<u64> {
+    if !(0.0..=1.0).contains(&fraction) {
+        return Err(anyhow!("sell fraction {fraction} outside 0..=1"));
+    }
+    if fraction >= FULL_EXIT_FRACTION {
+        return Ok(balance);
+    }
+    let parts_per_billion = (fraction * 1_000_000_000.0).round() as u128;
+    Ok((balance as u128 * parts_per_billion / 1_000_000_000) as u64)
+}
+
 // StreamExt import
 use futures_util::StreamExt;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linked_wallets_aggregate_into_one_targets_stats() {
+        let (a, b, c) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let groups = parse_target_groups(&format!("{a}+{b}"), &[a, b, c]).unwrap();
+        assert_eq!(logical_target(&groups, b), a);
+        assert_eq!(logical_target(&groups, c), c);
+
+        let path = std::env::temp_dir().join(format!("ammalgam-stats-test-{}.json", std::process::id()));
+        let mut stats = StatsBook::load(&path, 0).unwrap();
+        // A buy from A closed by a sell from B, and one from B alone
+        stats.record_realized(&logical_target(&groups, a), None, 1_000).unwrap();
+        stats.record_realized(&logical_target(&groups, b), None, -400).unwrap();
+        stats.record_realized(&logical_target(&groups, c), None, 50).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let leader = stats.get(&a).unwrap();
+        assert_eq!((leader.realized_pnl_lamports, leader.closed_trades), (600, 2));
+        assert!(stats.get(&b).is_none());
+        assert_eq!(stats.get(&c).unwrap().closed_trades, 1);
+    }
+
+    #[test]
+    fn target_groups_reject_unknown_and_repeated_wallets() {
+        let (a, b, c) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        assert!(parse_target_groups(&format!("{a}+{c}"), &[a, b]).is_err());
+        assert!(parse_target_groups(&format!("{a}+{b},{b}"), &[a, b]).is_err());
+        assert!(parse_target_groups("", &[a, b]).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn confirm_notification_carries_the_filled_amount() {
+        let (owner, mint, pool) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let sig = Signature::new_unique();
+        let balance = |amount: &str| {
+            json!({
+                "accountIndex": 2,
+                "mint": mint.to_string(),
+                "owner": owner.to_string(),
+                "uiTokenAmount": { "amount": amount, "decimals": 6, "uiAmount": null },
+            })
+        };
+        // A buy that filled 1234.5 tokens for 0.25 SOL plus the 5000 lamport fee
+        let tx = json!({
+            "slot": 1,
+            "transaction": {
+                "message": {
+                    "header": { "numRequiredSignatures": 1 },
+                    "accountKeys": [owner.to_string(), pool.to_string(), Pubkey::new_unique().to_string()],
+                    "instructions": [],
+                },
+                "signatures": [sig.to_string()],
+            },
+            "meta": {
+                "err": null,
+                "fee": 5000,
+                "preBalances": [1_000_000_000u64, 0, 0],
+                "postBalances": [749_995_000u64, 0, 0],
+                "preTokenBalances": [balance("500000")],
+                "postTokenBalances": [balance("1235000000")],
+            },
+        });
+        let rpc = AsyncRpcClient::new_mock_with_mocks(
+            "succeeds".to_string(),
+            HashMap::from([(RpcRequest::GetTransaction, tx)]),
+        );
+
+        let fill = confirmed_fill(&rpc, &sig, &owner, &mint).await.unwrap();
+        assert_eq!(fill, (1234.5, -250_005_000));
+        assert_eq!(
+            fill_message("BUY", &mint, &sig, Some(fill)),
+            format!("BUY filled: +1234.5 of {mint} for -0.250005 SOL\n{sig}")
+        );
+        assert_eq!(fill_message("BUY", &mint, &sig, None), format!("BUY confirmed: {mint}\n{sig}"));
+    }
+
+    #[test]
+    fn amounts_round_down_to_the_granularity() {
+        // 0 and 1 leave the amount alone
+        assert_eq!(round_down_lamports(123_456_789, 0), 123_456_789);
+        assert_eq!(round_down_lamports(123_456_789, 1), 123_456_789);
+        assert_eq!(round_down_lamports(123_456_789, 1_000), 123_456_000);
+        assert_eq!(round_down_lamports(123_456_000, 1_000), 123_456_000);
+        // Never up, so a clamped amount stays under its cap
+        assert_eq!(round_down_lamports(999, 1_000), 0);
+    }
+
+    #[test]
+    fn timed_out_buy_counts_against_the_mint_cap() {
+        let mint = Pubkey::new_unique();
+        let path = std::env::temp_dir().join(format!("ammalgam-mint-cap-test-{}.json", std::process::id()));
+        let mut positions = PositionTracker::load(&path).unwrap();
+        let cap = Some(1_000_000_000);
+
+        // A 0.6 SOL buy timed out; the trade loop still records it
+        positions.open_position(&mint, &Pubkey::new_unique(), 600_000_000, 5_000, "timed-out", 1).unwrap();
+        let spent = positions.get_position(&mint).unwrap().lamports_spent;
+        assert_eq!(clamp_to_mint_cap(600_000_000, spent, cap), 400_000_000);
+
+        // Once it is seen to fail, its exposure is released again
+        assert!(positions.drop_buy(&mint, "timed-out", 600_000_000, 5_000).unwrap());
+        assert!(positions.get_position(&mint).is_none());
+        assert!(!positions.drop_buy(&mint, "timed-out", 600_000_000, 5_000).unwrap());
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(clamp_to_mint_cap(600_000_000, 0, cap), 600_000_000);
+    }
+
+    /// A built swap tx with one Jupiter route instruction whose tail carries `tail_amount`
+    /// (`quoted_out_amount`, or `quoted_in_amount` on ExactOut routes).
+    fn swap_with_tail_amount(tail_amount: u64) -> String {
+        use base64::{engine::general_purpose::STANDARD as B64, Engine};
+        use solana_sdk::hash::Hash;
+        use solana_sdk::instruction::{AccountMeta, Instruction};
+        use solana_sdk::message::{Message, VersionedMessage};
+        use solana_sdk::transaction::VersionedTransaction;
+        use std::str::FromStr;
+
+        let payer = Pubkey::new_unique();
+        // discriminator, a one-step route plan, then in_amount, the tail amount, slippage_bps, platform_fee_bps
+        let mut data = vec![0xe5, 0x17, 0xcb, 0x97, 0x7a, 0xe3, 0xad, 0x2a, 1, 0, 0, 0, 7, 100, 0, 1];
+        data.extend_from_slice(&100_000_000u64.to_le_bytes());
+        data.extend_from_slice(&tail_amount.to_le_bytes());
+        data.extend_from_slice(&50u16.to_le_bytes());
+        data.push(0);
+        let route = Instruction::new_with_bytes(
+            Pubkey::from_str(crate::dex::jupiter::JUPITER_PROGRAM_ID).unwrap(),
+            &data,
+            vec![AccountMeta::new(payer, true)],
+        );
+        let message = Message::new_with_blockhash(&[route], Some(&payer), &Hash::new_unique());
+        let tx = VersionedTransaction {
+            signatures: vec![Signature::default()],
+            message: VersionedMessage::Legacy(message),
+        };
+        B64.encode(bincode::serialize(&tx).unwrap())
+    }
+
+    #[test]
+    fn degraded_swap_is_aborted() {
+        // Quoted 1_000_000 out; the swap was built for 3% less
+        let degraded = swap_with_tail_amount(970_000);
+        let err = check_swap_divergence(SwapMode::ExactIn, 1_000_000, &degraded, 2.0).unwrap_err();
+        assert!(err.to_string().contains("MAX_QUOTE_SWAP_DIVERGENCE_PCT"), "{err}");
+        assert!(check_swap_divergence(SwapMode::ExactIn, 1_000_000, &degraded, 5.0).is_ok());
+        assert!(check_swap_divergence(SwapMode::ExactIn, 1_000_000, &swap_with_tail_amount(990_000), 2.0).is_ok());
+        // A better fill is never a divergence, and 0 turns the check off
+        assert!(check_swap_divergence(SwapMode::ExactIn, 1_000_000, &swap_with_tail_amount(1_200_000), 2.0).is_ok());
+        assert!(check_swap_divergence(SwapMode::ExactIn, 1_000_000, &degraded, 0.0).is_ok());
+    }
+
+    #[test]
+    fn exact_out_swap_is_checked_on_its_input() {
+        // Quoted 1_000_000 in for the exact out; taking 3% more in is the degraded side
+        let degraded = swap_with_tail_amount(1_030_000);
+        let err = check_swap_divergence(SwapMode::ExactOut, 1_000_000, &degraded, 2.0).unwrap_err();
+        assert!(err.to_string().contains("1030000 in"), "{err}");
+        assert!(check_swap_divergence(SwapMode::ExactOut, 1_000_000, &swap_with_tail_amount(1_010_000), 2.0).is_ok());
+        assert!(check_swap_divergence(SwapMode::ExactOut, 1_000_000, &swap_with_tail_amount(900_000), 2.0).is_ok());
+    }
+
+    #[tokio::test]
+    async fn existing_holdings_become_imported_positions() {
+        use crate::common::utils::{TOKEN_2022_PROGRAM_ID, TOKEN_PROGRAM_ID};
+        use crate::dex::jupiter::RetryPolicy;
+        use solana_client::rpc_client::RpcClient;
+        use solana_sdk::{commitment_config::CommitmentConfig, signature::Keypair};
+        use wiremock::matchers::{body_partial_json, method, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let wallet = Keypair::new();
+        let owner = wallet.pubkey();
+        let (priced, unpriced, kept) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let account = |program: &str, mint: &Pubkey, amount: u64| {
+            json!({
+                "pubkey": Pubkey::new_unique().to_string(),
+                "account": {
+                    "lamports": 2_039_280,
+                    "owner": program,
+                    "executable": false,
+                    "rentEpoch": 0,
+                    "data": {
+                        "program": "spl-token",
+                        "parsed": {
+                            "type": "account",
+                            "info": {
+                                "mint": mint.to_string(),
+                                "owner": owner.to_string(),
+                                "tokenAmount": { "amount": amount.to_string(), "decimals": 6 },
+                            },
+                        },
+                        "space": 165,
+                    },
+                },
+            })
+        };
+        let accounts = |program: &str, list: Vec<serde_json::Value>| {
+            Mock::given(method("POST"))
+                .and(body_partial_json(json!({ "method": "getTokenAccountsByOwner", "params": [owner.to_string(), { "programId": program }] })))
+                .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                    "jsonrpc": "2.0",
+                    "id": 1,
+                    "result": { "context": { "slot": 1 }, "value": list },
+                })))
+        };
+
+        let rpc_server = MockServer::start().await;
+        // The client asks for the node version before its first token account query
+        Mock::given(body_partial_json(json!({ "method": "getVersion" })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "result": { "solana-core": "1.16.27", "feature-set": 0 },
+            })))
+            .mount(&rpc_server)
+            .await;
+        accounts(TOKEN_PROGRAM_ID, vec![account(TOKEN_PROGRAM_ID, &priced, 5_000), account(TOKEN_PROGRAM_ID, &kept, 7)])
+            .mount(&rpc_server)
+            .await;
+        accounts(TOKEN_2022_PROGRAM_ID, vec![account(TOKEN_2022_PROGRAM_ID, &unpriced, 900)])
+            .mount(&rpc_server)
+            .await;
+
+        let jupiter_server = MockServer::start().await;
+        Mock::given(path("/quote"))
+            .and(query_param("inputMint", priced.to_string()))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "inputMint": priced.to_string(),
+                "outputMint": SOL_MINT,
+                "inAmount": "5000",
+                "outAmount": "250000",
+                "otherAmountThreshold": "248750",
+                "swapMode": "ExactIn",
+                "priceImpactPct": "0.001",
+                "routePlan": [],
+            })))
+            .mount(&jupiter_server)
+            .await;
+        Mock::given(path("/quote"))
+            .and(query_param("inputMint", unpriced.to_string()))
+            .respond_with(ResponseTemplate::new(400).set_body_string("no route"))
+            .mount(&jupiter_server)
+            .await;
+
+        let state = AppState {
+            rpc_client: Arc::new(RpcClient::new_mock("succeeds".to_string())),
+            rpc_nonblocking_client: Arc::new(AsyncRpcClient::new(rpc_server.uri())),
+            wallet: Arc::new(wallet),
+            wallet_pubkey: owner,
+            fee_payer: None,
+            confirm_commitment: CommitmentConfig::confirmed(),
+        };
+        let retry = RetryPolicy { max_retries: 0, base_delay: Duration::from_millis(1), window: Duration::from_secs(1) };
+        let http = reqwest::Client::builder().no_proxy().build().unwrap();
+        let jupiter = JupiterClient::new(http, &jupiter_server.uri(), None, retry);
+        let path = std::env::temp_dir().join(format!("ammalgam-import-test-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let positions = Mutex::new(PositionTracker::load(&path).unwrap());
+
+        import_existing_holdings(&jupiter, &state, &positions, &HashSet::from([kept]), 50).await.unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let positions = positions.into_inner().unwrap();
+        let position = positions.get_position(&priced).unwrap();
+        assert!(position.imported && position.target.is_none());
+        assert_eq!((position.lamports_spent, position.est_out_amount), (250_000, 5_000));
+        // Not quotable: imported with an unknown (0) cost basis
+        let position = positions.get_position(&unpriced).unwrap();
+        assert_eq!((position.imported, position.lamports_spent, position.est_out_amount), (true, 0, 900));
+        // NEVER_SELL_MINTS are left alone
+        assert!(positions.get_position(&kept).is_none());
+    }
+
+    #[test]
+    fn price_impact_guard_rejects_quotes_above_the_limit() {
+        let quote = |impact: Option<&str>| -> QuoteResponse {
+            serde_json::from_value(json!({
+                "inAmount": "100000000",
+                "outAmount": "2000000",
+                "otherAmountThreshold": "1990000",
+                "swapMode": "ExactIn",
+                "priceImpactPct": impact,
+                "routePlan": [],
+            }))
+            .unwrap()
+        };
+        // priceImpactPct is a fraction: 0.07 = 7%
+        assert!(excess_price_impact(&quote(Some("0.07")), 5.0).is_some_and(|i| (i - 7.0).abs() < 1e-9));
+        assert_eq!(excess_price_impact(&quote(Some("0.049")), 5.0), None);
+        assert_eq!(excess_price_impact(&quote(Some("0.07")), 0.0), None);
+        assert_eq!(excess_price_impact(&quote(None), 5.0), None);
+    }
+
+    #[test]
+    fn sell_amount_rejects_fractions_outside_0_to_1() {
+        assert_eq!(sell_amount(1_000, 0.25).unwrap(), 250);
+        assert_eq!(sell_amount(1_000, 0.0).unwrap(), 0);
+        assert_eq!(sell_amount(1_000, 1.0).unwrap(), 1_000);
+        // Large balances are split in integer space
+        assert_eq!(sell_amount(u64::MAX, 0.5).unwrap(), u64::MAX / 2);
+        for fraction in [1.5, -0.1, f64::NAN] {
+            let err = sell_amount(1_000, fraction).unwrap_err().to_string();
+            assert!(err.contains("outside 0..=1"), "{err}");
+        }
+    }
+
+    #[tokio::test]
+    async fn requote_replaces_a_stale_quote() {
+        use crate::dex::jupiter::RetryPolicy;
+        use wiremock::matchers::{body_partial_json, path, query_param};
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        let server = MockServer::start().await;
+        let quote = |out: u64, threshold: u64| {
+            json!({
+                "inAmount": "100000000",
+                "outAmount": out.to_string(),
+                "otherAmountThreshold": threshold.to_string(),
+                "swapMode": "ExactIn",
+                "priceImpactPct": "0.01",
+                "routePlan": [],
+            })
+        };
+        // The route quoted at 50 bps is gone by the time the swap is built
+        Mock::given(path("/quote"))
+            .and(query_param("slippageBps", "50"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(quote(5_000, 4_975)))
+            .mount(&server)
+            .await;
+        Mock::given(path("/swap"))
+            .and(body_partial_json(json!({ "quoteResponse": { "outAmount": "5000" } })))
+            .respond_with(ResponseTemplate::new(400).set_body_string("route not found"))
+            .mount(&server)
+            .await;
+        Mock::given(path("/quote"))
+            .and(query_param("slippageBps", "150"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(quote(4_800, 4_728)))
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(path("/swap"))
+            .and(body_partial_json(json!({ "quoteResponse": { "outAmount": "4800" }, "prioritizationFeeLamports": 7 })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "swapTransaction": "fresh" })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let retry = RetryPolicy { max_retries: 0, base_delay: Duration::from_millis(1), window: Duration::from_secs(1) };
+        let http = reqwest::Client::builder().no_proxy().build().unwrap();
+        let jupiter = JupiterClient::new(http, &server.uri(), None, retry);
+        let owner = Pubkey::new_unique();
+        let mint = Pubkey::new_unique().to_string();
+
+        let stale = jupiter.quote(SOL_MINT, &mint, 100_000_000, 50, SwapMode::ExactIn).await.unwrap();
+        let err = jupiter.swap_tx(stale, owner, 7).await.unwrap_err();
+        assert!(err.is_retriable(), "{err}");
+
+        let (swap, est_out) = requote_exact_in(&jupiter, &owner, SOL_MINT, &mint, 100_000_000, 150, 5.0, 7).await.unwrap();
+        assert_eq!((swap.swap_transaction.as_str(), est_out), ("fresh", 4_800));
+
+        // A re-quote with too much price impact is not built at all
+        let err = requote_exact_in(&jupiter, &owner, SOL_MINT, &mint, 100_000_000, 150, 0.5, 7).await.unwrap_err();
+        assert!(err.to_string().contains("MAX_PRICE_IMPACT_PCT"), "{err}");
+    }
+
+    #[test]
+    fn hook_modified_sell_is_still_validated() {
+        use crate::engine::hooks::Decision;
+        struct Oversell;
+        impl TradeHook for Oversell {
+            fn on_intent(&self, _target: &Pubkey, intent: &MirrorIntent) -> Decision {
+                Decision::Modify(MirrorIntent::Sell { input_mint: *intent.mint(), fraction: 2.0 })
+            }
+        }
+        let hooks: Vec<Arc<dyn TradeHook>> = vec![Arc::new(Oversell)];
+        let intent = MirrorIntent::Sell { input_mint: Pubkey::new_unique(), fraction: 0.5 };
+        let Some(MirrorIntent::Sell { fraction, .. }) = decide(&hooks, &Pubkey::new_unique(), intent) else {
+            panic!("hook should have replaced the sell");
+        };
+        assert!(sell_amount(1_000, fraction).is_err());
+    }
+
+    #[test]
+    fn congestion_pauses_buys_but_not_sells() {
+        let mint = Pubkey::new_unique();
+        let buy = MirrorIntent::Buy {
+            output_mint: mint,
+            max_input_sol: 0.1,
+            observed_input_sol: None,
+            token_program: Pubkey::new_unique(),
+        };
+        let sell = MirrorIntent::Sell { input_mint: mint, fraction: 1.0 };
+        let spike = FeeQuote { lamports: 100_000, estimate_lamports: Some(5_000_000) };
+        let calm = FeeQuote { lamports: 10_000, estimate_lamports: Some(10_000) };
+
+        assert_eq!(congestion_pause(&buy, &spike, 1_000_000), Some(5_000_000));
+        assert_eq!(congestion_pause(&sell, &spike, 1_000_000), None);
+        assert_eq!(congestion_pause(&buy, &calm, 1_000_000), None);
+        // 0 turns the check off
+        assert_eq!(congestion_pause(&buy, &spike, 0), None);
+    }
+}
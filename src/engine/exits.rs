@@ -0,0 +1,604 @@
+use crate::common::lru::LruCache;
+use crate::common::utils::{env_bool, env_f64, env_u64, env_var_opt, parse_pubkey, token_balance, AppState};
+use crate::dex::jito::JitoConfig;
+use crate::dex::jupiter::{JupiterClient, SwapMode, TxConfirmation, SOL_MINT};
+use crate::engine::copy_trader::{mirror_sell, SellFill, SwapSettings};
+use crate::engine::positions::{now_secs, ExitLadder, PositionTracker};
+use crate::engine::stats::StatsBook;
+use crate::helius::ws::PoolSubscription;
+use anyhow::{anyhow, Result};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+use tracing::{debug, error, info, warn};
+
+/// How an open position gets closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitPolicy {
+    /// Take-profit / stop-loss / trailing-stop thresholds, plus mirrored sells when
+    /// `MIRROR_SELLS` is on.
+    Thresholds,
+    /// Exit when the target fully exits, or when the position falls `EXIT_FLOOR_PCT`
+    /// below entry, whichever comes first.
+    TargetFollowWithFloor,
+}
+
+impl FromStr for ExitPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "thresholds" => Ok(Self::Thresholds),
+            "target_follow_with_floor" => Ok(Self::TargetFollowWithFloor),
+            other => Err(anyhow!(
+                "Invalid exit policy {other:?} (expected thresholds or target_follow_with_floor)"
+            )),
+        }
+    }
+}
+
+/// Exit policy selection: per mint, then per target, then global.
+#[derive(Debug, Clone)]
+pub struct ExitPolicies {
+    global: ExitPolicy,
+    by_target: HashMap<Pubkey, ExitPolicy>,
+    by_mint: HashMap<Pubkey, ExitPolicy>,
+    /// Hard floor (percent below entry) for `TargetFollowWithFloor` positions.
+    pub floor_pct: Option<f64>,
+}
+
+impl ExitPolicies {
+    /// Reads `EXIT_POLICY`, the `pubkey:policy` lists `EXIT_POLICY_TARGETS` and
+    /// `EXIT_POLICY_MINTS`, and `EXIT_FLOOR_PCT`.
+    pub fn from_env() -> Result<Self> {
+        let global = env_var_opt("EXIT_POLICY")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or(ExitPolicy::Thresholds);
+        let policies = Self {
+            global,
+            by_target: parse_policy_overrides("EXIT_POLICY_TARGETS")?,
+            by_mint: parse_policy_overrides("EXIT_POLICY_MINTS")?,
+            floor_pct: Some(env_f64("EXIT_FLOOR_PCT", 0.0)).filter(|v| *v > 0.0),
+        };
+        if policies.any_target_follow() && policies.floor_pct.is_none() {
+            return Err(anyhow!("EXIT_FLOOR_PCT must be set when target_follow_with_floor is used"));
+        }
+        Ok(policies)
+    }
+
+    pub fn resolve(&self, mint: &Pubkey, target: Option<&Pubkey>) -> ExitPolicy {
+        self.by_mint
+            .get(mint)
+            .or_else(|| target.and_then(|t| self.by_target.get(t)))
+            .copied()
+            .unwrap_or(self.global)
+    }
+
+    pub fn any_target_follow(&self) -> bool {
+        std::iter::once(&self.global)
+            .chain(self.by_target.values())
+            .chain(self.by_mint.values())
+            .any(|p| *p == ExitPolicy::TargetFollowWithFloor)
+    }
+}
+
+fn parse_policy_overrides(key: &str) -> Result<HashMap<Pubkey, ExitPolicy>> {
+    let mut overrides = HashMap::new();
+    let raw = env_var_opt(key).unwrap_or_default();
+    for part in raw.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (pubkey, policy) = part
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Invalid {key} entry {part:?} (expected pubkey:policy)"))?;
+        overrides.insert(parse_pubkey(key, pubkey.trim())?, policy.parse()?);
+    }
+    Ok(overrides)
+}
+
+/// Take-profit / stop-loss settings for the exit monitor.
+#[derive(Debug, Clone)]
+pub struct ExitConfig {
+    /// Sell once the position's quoted value is up this many percent.
+    pub take_profit_pct: Option<f64>,
+    /// Sell once the position's quoted value is down this many percent.
+    pub stop_loss_pct: Option<f64>,
+    /// Sell once the quoted value retraces this many percent from its high-water mark.
+    pub trailing_stop_pct: Option<f64>,
+    /// Sell regardless of price once a position has been held this long.
+    pub max_hold_secs: Option<u64>,
+    /// Partial take-profit rungs (`EXIT_LADDER`), for `Thresholds` positions.
+    pub ladder: ExitLadder,
+    pub poll_interval: Duration,
+    /// `REALTIME_POSITION_MONITOR`: also re-quote a position as soon as a pool its exit
+    /// route goes through changes, instead of only every `poll_interval`.
+    pub realtime: bool,
+    pub swap: SwapSettings,
+    pub policies: ExitPolicies,
+}
+
+impl ExitConfig {
+    /// `None` unless `TAKE_PROFIT_PCT`, `STOP_LOSS_PCT`, `TRAILING_STOP_PCT` or
+    /// `MAX_HOLD_SECONDS` is set to a positive value, `EXIT_LADDER` is set, or some
+    /// position may use the `target_follow_with_floor` floor.
+    pub fn from_env(swap: SwapSettings, policies: ExitPolicies) -> Result<Option<Self>> {
+        let pct = |key| Some(env_f64(key, 0.0)).filter(|v| *v > 0.0);
+        let take_profit_pct = pct("TAKE_PROFIT_PCT");
+        let stop_loss_pct = pct("STOP_LOSS_PCT");
+        let trailing_stop_pct = pct("TRAILING_STOP_PCT");
+        let max_hold_secs = Some(env_u64("MAX_HOLD_SECONDS", 0)).filter(|v| *v > 0);
+        let ladder: ExitLadder = env_var_opt("EXIT_LADDER")
+            .map(|v| v.parse())
+            .transpose()
+            .map_err(|e| anyhow!("Invalid EXIT_LADDER: {e}"))?
+            .unwrap_or_default();
+        if take_profit_pct.is_none()
+            && stop_loss_pct.is_none()
+            && trailing_stop_pct.is_none()
+            && max_hold_secs.is_none()
+            && ladder.is_empty()
+            && !policies.any_target_follow()
+        {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            take_profit_pct,
+            stop_loss_pct,
+            trailing_stop_pct,
+            max_hold_secs,
+            ladder,
+            poll_interval: Duration::from_secs(env_u64("RISK_POLL_SECS", env_u64("EXIT_POLL_SECS", 15)).max(1)),
+            realtime: env_bool("REALTIME_POSITION_MONITOR", false),
+            swap,
+            policies,
+        }))
+    }
+
+    /// Why a position under `policy` should be closed, if it should, given its change
+    /// from entry and its retrace from the high-water mark (both in percent).
+    fn trigger(&self, policy: ExitPolicy, change_pct: f64, retrace_pct: f64) -> Option<&'static str> {
+        if policy == ExitPolicy::TargetFollowWithFloor {
+            // The target-exit half of the policy is driven by mirrored sells
+            return self
+                .policies
+                .floor_pct
+                .filter(|floor| change_pct <= -floor)
+                .map(|_| "floor-stop");
+        }
+        if self.take_profit_pct.is_some_and(|tp| change_pct >= tp) {
+            Some("take-profit")
+        } else if self.stop_loss_pct.is_some_and(|sl| change_pct <= -sl) {
+            Some("stop-loss")
+        } else if self.trailing_stop_pct.is_some_and(|ts| retrace_pct >= ts) {
+            Some("trailing-stop")
+        } else {
+            None
+        }
+    }
+}
+
+/// The pool account watch behind `REALTIME_POSITION_MONITOR`: pools to (un)subscribe go
+/// out on `subscribe`, pools whose account changed come back on `changed`.
+pub struct PoolWatch {
+    pub subscribe: mpsc::UnboundedSender<PoolSubscription>,
+    pub changed: mpsc::UnboundedReceiver<Pubkey>,
+}
+
+/// Which mints' exit routes go through each watched pool, for the last
+/// `POOL_CACHE_SIZE` pools used.
+#[derive(Debug)]
+struct PoolIndex {
+    mints_by_pool: LruCache<Pubkey, HashSet<String>>,
+}
+
+impl PoolIndex {
+    /// Records that `mint` quotes through `pools`; returns the subscriptions this adds
+    /// and the ones evicted to make room.
+    fn record(&mut self, mint: &str, pools: &[Pubkey]) -> Vec<PoolSubscription> {
+        let mut changes = Vec::new();
+        for pool in pools {
+            if let Some(mints) = self.mints_by_pool.get_mut(pool) {
+                mints.insert(mint.to_string());
+                continue;
+            }
+            changes.push(PoolSubscription::Subscribe(*pool));
+            if let Some(evicted) = self.mints_by_pool.insert(*pool, HashSet::from([mint.to_string()])) {
+                changes.push(PoolSubscription::Unsubscribe(evicted));
+            }
+        }
+        changes
+    }
+
+    fn mints(&mut self, pool: &Pubkey) -> HashSet<String> {
+        self.mints_by_pool.get(pool).cloned().unwrap_or_default()
+    }
+}
+
+/// Periodically quotes every open position back to SOL and sells it in full when
+/// its exit policy says so. With `pools`, a change of a pool some position's exit quote
+/// routes through re-checks just those positions right away. Errors on one position are
+/// logged and never stop the monitor.
+pub fn spawn_exit_monitor(
+    cfg: ExitConfig,
+    state: AppState,
+    jupiter: JupiterClient,
+    jito: Option<JitoConfig>,
+    positions: Arc<Mutex<PositionTracker>>,
+    stats: Arc<Mutex<StatsBook>>,
+    mut pools: Option<PoolWatch>,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(cfg.poll_interval);
+        let mut index = PoolIndex { mints_by_pool: LruCache::from_env("pool", 1_000) };
+        loop {
+            let only = tokio::select! {
+                _ = ticker.tick() => None,
+                Some(pool) = next_pool_change(pools.as_mut()) => {
+                    let mints = index.mints(&pool);
+                    if mints.is_empty() {
+                        continue;
+                    }
+                    debug!("Pool {pool} changed; re-checking {} position(s)", mints.len());
+                    Some(mints)
+                }
+            };
+
+            let open = open_positions(&positions.lock().unwrap_or_else(|e| e.into_inner()), only.as_ref());
+            for position in open {
+                match check_position(&cfg, &state, &jupiter, jito.as_ref(), &positions, &stats, &position).await {
+                    Ok(quoted_pools) => {
+                        if let Some(watch) = &pools {
+                            for change in index.record(&position.mint, &quoted_pools) {
+                                let _ = watch.subscribe.send(change);
+                            }
+                        }
+                    }
+                    Err(e) => warn!("Exit check for mint {} failed: {e}", position.mint),
+                }
+            }
+        }
+    });
+}
+
+/// The next changed pool, or never without a watch.
+async fn next_pool_change(pools: Option<&mut PoolWatch>) -> Option<Pubkey> {
+    match pools {
+        Some(watch) => watch.changed.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// The tracked positions, or only those in `only` when set.
+fn open_positions(positions: &PositionTracker, only: Option<&HashSet<String>>) -> Vec<OpenPosition> {
+    positions
+        .all_positions()
+        .into_iter()
+        .filter(|p| only.is_none_or(|mints| mints.contains(&p.mint)))
+        .map(|p| OpenPosition {
+            mint: p.mint.clone(),
+            target: p.target.clone(),
+            cost_lamports: p.lamports_spent,
+            opened_at: p.opened_at,
+        })
+        .collect()
+}
+
+/// Snapshot of a tracked position, taken so the tracker is not locked across awaits.
+struct OpenPosition {
+    mint: String,
+    target: Option<String>,
+    cost_lamports: u64,
+    opened_at: u64,
+}
+
+/// Returns the pools the position's exit was quoted through, if it was quoted.
+async fn check_position(
+    cfg: &ExitConfig,
+    state: &AppState,
+    jupiter: &JupiterClient,
+    jito: Option<&JitoConfig>,
+    positions: &Mutex<PositionTracker>,
+    stats: &Mutex<StatsBook>,
+    position: &OpenPosition,
+) -> Result<Vec<Pubkey>> {
+    let (mint, cost_lamports) = (position.mint.as_str(), position.cost_lamports);
+    let held_secs = now_secs().saturating_sub(position.opened_at);
+    let expired = cfg.max_hold_secs.is_some_and(|max| held_secs >= max);
+    // Without a cost basis only the max hold time can apply
+    if cost_lamports == 0 && !expired {
+        return Ok(Vec::new());
+    }
+    let mint_key = Pubkey::from_str(mint).map_err(|e| anyhow!("Invalid mint in positions file: {e}"))?;
+    let target = position.target.as_deref().and_then(|t| Pubkey::from_str(t).ok());
+    let policy = cfg.policies.resolve(&mint_key, target.as_ref());
+
+    let balance = token_balance(&state.rpc_nonblocking_client, &state.wallet_pubkey, &mint_key).await?;
+    if balance == 0 {
+        let mut positions = positions.lock().unwrap_or_else(|e| e.into_inner());
+        // An in-flight sell settles the position itself
+        if positions.claim_exit(&mint_key, "empty-balance").is_ok() {
+            info!("Position in {mint} has no token balance left; dropping it from the tracker");
+            let closed = positions.close_position(&mint_key);
+            positions.release_exit(&mint_key);
+            closed?;
+        }
+        return Ok(Vec::new());
+    }
+
+    let mut pools = Vec::new();
+    let reason = if expired {
+        info!("max hold time hit on {mint}: held {held_secs}s (MAX_HOLD_SECONDS={:?})", cfg.max_hold_secs);
+        "max-hold-time"
+    } else {
+        let quote = jupiter.quote(mint, SOL_MINT, balance, cfg.swap.slippage_bps, SwapMode::ExactIn).await?;
+        let value_lamports = quote.out_amount();
+        pools = quote.pool_accounts();
+        let change_pct = (value_lamports as f64 - cost_lamports as f64) / cost_lamports as f64 * 100.0;
+
+        // Persisted so a restart does not reset the trail
+        let peak = positions
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .update_high_water(&mint_key, value_lamports)?
+            .unwrap_or(cost_lamports);
+        let retrace_pct = retrace_pct(peak, value_lamports);
+        debug!(
+            "Position {mint}: cost {cost_lamports} lamports, quoted {value_lamports} ({change_pct:+.2}%), \
+             peak {peak} (-{retrace_pct:.2}%)"
+        );
+
+        let Some(reason) = cfg.trigger(policy, change_pct, retrace_pct) else {
+            if policy == ExitPolicy::Thresholds && !cfg.ladder.is_empty() {
+                let multiple = value_lamports as f64 / cost_lamports as f64;
+                return ladder_exit(cfg, state, jupiter, jito, positions, stats, &mint_key, target.as_ref(), balance, multiple)
+                    .await
+                    .map(|_| pools);
+            }
+            return Ok(pools);
+        };
+        info!("{reason} hit on {mint} at {change_pct:+.2}%");
+        reason
+    };
+    if let Err(holder) = positions
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .claim_exit(&mint_key, reason)
+    {
+        debug!("{reason} hit on {mint} but a {holder} sell is already in flight");
+        return Ok(pools);
+    }
+    info!("Exiting {mint} on {reason}: selling {balance} raw units");
+
+    let sold = mirror_sell(jupiter, state, jito, mint_key, 1.0, &cfg.swap).await;
+    let settled = settle_exit(positions, stats, &mint_key, target.as_ref(), reason, sold);
+    positions.lock().unwrap_or_else(|e| e.into_inner()).release_exit(&mint_key);
+    settled.map(|_| pools)
+}
+
+/// Sells the exit ladder rungs reached at `multiple` (value / cost) in one swap. Rung
+/// percentages apply to the position's original balance, not what is left of it.
+#[allow(clippy::too_many_arguments)]
+async fn ladder_exit(
+    cfg: &ExitConfig,
+    state: &AppState,
+    jupiter: &JupiterClient,
+    jito: Option<&JitoConfig>,
+    positions: &Mutex<PositionTracker>,
+    stats: &Mutex<StatsBook>,
+    mint_key: &Pubkey,
+    target: Option<&Pubkey>,
+    balance: u64,
+    multiple: f64,
+) -> Result<()> {
+    let Some((base, fired)) = positions
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .ladder_state(mint_key, balance)?
+    else {
+        return Ok(());
+    };
+    let due = cfg.ladder.due_rungs(multiple, &fired);
+    if due.is_empty() {
+        return Ok(());
+    }
+
+    let pct: f64 = due.iter().map(|r| r.sell_pct).sum();
+    let amount = ((base as f64 * pct / 100.0).round() as u64).min(balance);
+    if amount == 0 {
+        return Ok(());
+    }
+    let fraction = amount as f64 / balance as f64;
+    let multiples: Vec<f64> = due.iter().map(|r| r.multiple).collect();
+
+    if let Err(holder) = positions
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .claim_exit(mint_key, "exit-ladder")
+    {
+        debug!("Exit ladder rung(s) {multiples:?}x hit on {mint_key} but a {holder} sell is already in flight");
+        return Ok(());
+    }
+    info!(
+        "Exit ladder on {mint_key} at {multiple:.2}x: rung(s) {multiples:?}x sell {pct}% of the original {base} \
+         ({amount} of {balance} raw units held)"
+    );
+
+    let sold = mirror_sell(jupiter, state, jito, *mint_key, fraction, &cfg.swap).await;
+    let settled = settle_exit(positions, stats, mint_key, target, "exit-ladder", sold).and_then(|confirmed| {
+        if confirmed {
+            positions
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .mark_rungs_fired(mint_key, &multiples)?;
+        }
+        Ok(())
+    });
+    positions.lock().unwrap_or_else(|e| e.into_inner()).release_exit(mint_key);
+    settled
+}
+
+/// Books a monitor-triggered sell (full or partial) once it has been sent.
+/// Returns whether it confirmed.
+fn settle_exit(
+    positions: &Mutex<PositionTracker>,
+    stats: &Mutex<StatsBook>,
+    mint_key: &Pubkey,
+    target: Option<&Pubkey>,
+    reason: &str,
+    sold: Result<Option<SellFill>>,
+) -> Result<bool> {
+    let mint = mint_key.to_string();
+    let Some(fill) = sold? else {
+        return Ok(false);
+    };
+
+    match &fill.confirmation {
+        TxConfirmation::Confirmed => {}
+        TxConfirmation::Timeout => {
+            warn!("{reason} sell {} for {mint} not confirmed in time; position kept", fill.signature);
+            return Ok(false);
+        }
+        TxConfirmation::Failed(err) => {
+            error!("{reason} sell {} for {mint} failed on-chain: {err}", fill.signature);
+            return Ok(false);
+        }
+    }
+
+    let (strategy, released) = {
+        let mut positions = positions.lock().unwrap_or_else(|e| e.into_inner());
+        let strategy = positions.strategy_of(mint_key);
+        (strategy, positions.reduce_position(mint_key, fill.fraction_of_holding)?)
+    };
+    if let Some(cost_lamports) = released {
+        let pnl = fill.out_lamports as i64 - cost_lamports as i64;
+        positions.lock().unwrap_or_else(|e| e.into_inner()).record_outcome(mint_key, pnl);
+        let closed = fill.fraction_of_holding >= 1.0;
+        if closed {
+            info!("Closed {mint} on {reason} ({}): realized PnL {pnl} lamports (quoted)", fill.signature);
+        } else {
+            info!(
+                "Sold {:.2}% of {mint} on {reason} ({}): realized PnL {pnl} lamports (quoted)",
+                fill.fraction_of_holding * 100.0,
+                fill.signature
+            );
+        }
+        if let Some(target) = target {
+            let mut stats = stats.lock().unwrap_or_else(|e| e.into_inner());
+            stats.record_realized(target, strategy.as_deref(), pnl)?;
+            if closed {
+                stats.record_exit(target, reason)?;
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// How far `value_lamports` is below the high-water mark `peak`, in percent.
+fn retrace_pct(peak: u64, value_lamports: u64) -> f64 {
+    if peak == 0 {
+        return 0.0;
+    }
+    peak.saturating_sub(value_lamports) as f64 / peak as f64 * 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dex::priority_fee::PriorityFee;
+
+    fn config(trailing_stop_pct: Option<f64>) -> ExitConfig {
+        let swap = SwapSettings {
+            slippage_bps: 50,
+            confirm_timeout: Duration::from_secs(1),
+            max_divergence_pct: 0.0,
+            validate_blockhash: false,
+            priority_fee: PriorityFee::Fixed(0),
+            raydium_fallback: false,
+            pumpfun_routing: false,
+            exact_out_sells: false,
+            requote_bump_bps: 0,
+            requote_max_slippage_bps: 0,
+            slippage_retries: 0,
+            max_slippage_bps: 50,
+            tx_guard: None,
+            simulate_before_send: false,
+        };
+        let policies = ExitPolicies {
+            global: ExitPolicy::Thresholds,
+            by_target: HashMap::new(),
+            by_mint: HashMap::new(),
+            floor_pct: None,
+        };
+        ExitConfig {
+            take_profit_pct: None,
+            stop_loss_pct: None,
+            trailing_stop_pct,
+            max_hold_secs: None,
+            ladder: ExitLadder::default(),
+            poll_interval: Duration::from_secs(1),
+            realtime: false,
+            swap,
+            policies,
+        }
+    }
+
+    #[test]
+    fn trailing_stop_fires_at_the_pullback_from_the_peak() {
+        let cfg = config(Some(10.0));
+        let mint = Pubkey::new_unique();
+        let path = std::env::temp_dir().join(format!("ammalgam-trailing-test-{}.json", std::process::id()));
+        let mut positions = PositionTracker::load(&path).unwrap();
+        positions.open_position(&mint, &Pubkey::new_unique(), 100_000, 1_000, "buy", 1).unwrap();
+
+        // Quoted values per poll, and whether the trail should fire at each
+        let polls = [(95_000, false), (110_000, false), (130_000, false), (125_000, false), (118_000, false), (117_000, true)];
+        for (value, fires) in polls {
+            let peak = positions.update_high_water(&mint, value).unwrap().unwrap();
+            let change_pct = (value as f64 - 100_000.0) / 100_000.0 * 100.0;
+            let trigger = cfg.trigger(ExitPolicy::Thresholds, change_pct, retrace_pct(peak, value));
+            assert_eq!(trigger, fires.then_some("trailing-stop"), "at {value} (peak {peak})");
+        }
+        let _ = std::fs::remove_file(&path);
+
+        // Off without TRAILING_STOP_PCT
+        assert_eq!(config(None).trigger(ExitPolicy::Thresholds, 17.0, 50.0), None);
+    }
+
+    #[test]
+    fn pool_change_rechecks_only_the_positions_quoted_through_it() {
+        let (bonk, wif, target) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let (bonk_pool, shared_pool) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut index = PoolIndex { mints_by_pool: LruCache::new("pool", 2) };
+        // Only pools not yet watched are subscribed
+        assert_eq!(
+            index.record(&bonk.to_string(), &[bonk_pool, shared_pool]),
+            vec![PoolSubscription::Subscribe(bonk_pool), PoolSubscription::Subscribe(shared_pool)]
+        );
+        assert_eq!(index.record(&wif.to_string(), &[shared_pool]), vec![]);
+
+        let path = std::env::temp_dir().join(format!("ammalgam-pool-index-test-{}.json", std::process::id()));
+        let mut positions = PositionTracker::load(&path).unwrap();
+        positions.open_position(&bonk, &target, 100_000, 1_000, "buy-bonk", 1).unwrap();
+        positions.open_position(&wif, &target, 100_000, 1_000, "buy-wif", 1).unwrap();
+        let rechecked = |only: Option<HashSet<String>>| -> HashSet<String> {
+            open_positions(&positions, only.as_ref()).into_iter().map(|p| p.mint).collect()
+        };
+        let both = HashSet::from([bonk.to_string(), wif.to_string()]);
+        assert_eq!(rechecked(Some(index.mints(&bonk_pool))), HashSet::from([bonk.to_string()]));
+        assert_eq!(rechecked(Some(index.mints(&shared_pool))), both);
+        assert!(rechecked(Some(index.mints(&Pubkey::new_unique()))).is_empty());
+        // The poll checks everything
+        assert_eq!(rechecked(None), both);
+
+        // A third pool evicts the least recently used one, which is unsubscribed
+        let wif_pool = Pubkey::new_unique();
+        assert_eq!(
+            index.record(&wif.to_string(), &[wif_pool]),
+            vec![PoolSubscription::Subscribe(wif_pool), PoolSubscription::Unsubscribe(bonk_pool)]
+        );
+        assert!(index.mints(&bonk_pool).is_empty());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,72 @@
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use tokio::time::{Duration, Instant};
+
+/// Per-target feed health: a target counts as active while the subscription is up and a
+/// notification mentioning it arrived within `window`. Targets start out active from
+/// the moment the subscription connects.
+#[derive(Debug)]
+pub struct TargetHealth {
+    window: Duration,
+    last_seen: HashMap<Pubkey, Instant>,
+}
+
+impl TargetHealth {
+    pub fn new(targets: &[Pubkey], window: Duration) -> Self {
+        let now = Instant::now();
+        Self {
+            window,
+            last_seen: targets.iter().map(|t| (*t, now)).collect(),
+        }
+    }
+
+    /// Records a notification for `target`.
+    pub fn mark(&mut self, target: &Pubkey) {
+        self.last_seen.insert(*target, Instant::now());
+    }
+
+    pub fn active_count(&self) -> usize {
+        self.last_seen.values().filter(|at| at.elapsed() <= self.window).count()
+    }
+
+    /// The `MIN_ACTIVE_TARGETS` interlock: whether fewer than `min_active` targets are
+    /// active, so trading should pause. Never with `min_active == 0`.
+    pub fn below_minimum(&self, min_active: usize) -> bool {
+        self.active_count() < min_active
+    }
+
+    /// Targets with no notification within the window.
+    pub fn stale(&self) -> Vec<Pubkey> {
+        self.last_seen
+            .iter()
+            .filter(|(_, at)| at.elapsed() > self.window)
+            .map(|(t, _)| *t)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn stale_feeds_pause_trading_until_they_report_again() {
+        let (a, b, c) = (Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique());
+        let mut health = TargetHealth::new(&[a, b, c], Duration::from_millis(50));
+        assert!(!health.below_minimum(2));
+
+        // Only A keeps receiving; B and C go quiet past the window
+        std::thread::sleep(Duration::from_millis(80));
+        health.mark(&a);
+        assert_eq!(health.active_count(), 1);
+        assert!(health.below_minimum(2));
+        let stale: HashSet<Pubkey> = health.stale().into_iter().collect();
+        assert_eq!(stale, HashSet::from([b, c]));
+        assert!(!health.below_minimum(1));
+        assert!(!health.below_minimum(0));
+
+        health.mark(&c);
+        assert!(!health.below_minimum(2));
+    }
+}
@@ -0,0 +1,47 @@
+use crate::types::events::MirrorIntent;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use std::sync::Arc;
+use tracing::info;
+
+/// What a `TradeHook` wants done with an intent.
+#[derive(Debug, Clone)]
+pub enum Decision {
+    Allow,
+    /// Do not mirror it, for the logged reason.
+    Skip(String),
+    /// Mirror this intent instead, e.g. with a different size. The token filters have
+    /// already run and are not applied to it again.
+    Modify(MirrorIntent),
+}
+
+/// Custom trade logic registered on a `CopyTrader`. Both methods are called from the
+/// trade loop, so like `Notifier::notify` they must return at once.
+pub trait TradeHook: Send + Sync {
+    /// Called for every intent from `target` that passed the built-in filters, before
+    /// anything is quoted or sent.
+    fn on_intent(&self, _target: &Pubkey, _intent: &MirrorIntent) -> Decision {
+        Decision::Allow
+    }
+
+    /// Called once the swap for `intent` has been sent, before it confirms.
+    fn on_sent(&self, _intent: &MirrorIntent, _signature: &Signature) {}
+}
+
+/// Runs `intent` through every hook in order, each seeing what the previous ones made of
+/// it. `None` once one of them skips it.
+pub fn decide(hooks: &[Arc<dyn TradeHook>], target: &Pubkey, mut intent: MirrorIntent) -> Option<MirrorIntent> {
+    for hook in hooks {
+        match hook.on_intent(target, &intent) {
+            Decision::Allow => {}
+            Decision::Skip(reason) => {
+                info!("Intent {intent:?} from {target} skipped by trade hook: {reason}");
+                return None;
+            }
+            Decision::Modify(modified) => {
+                info!("Intent {intent:?} from {target} replaced by trade hook with {modified:?}");
+                intent = modified;
+            }
+        }
+    }
+    Some(intent)
+}
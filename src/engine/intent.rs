@@ -1,78 +1,1429 @@
-use crate::types::events::MirrorIntent;
-use anyhow::Result;
-use solana_sdk::pubkey::Pubkey;
+use crate::common::error::{BotError, BotResult};
+use crate::common::utils::{TOKEN_2022_PROGRAM_ID, TOKEN_PROGRAM_ID};
+use crate::dex::jupiter::{JUPITER_PROGRAM_ID, SOL_MINT, USDC_MINT, USDT_MINT};
+use crate::dex::pumpfun::{PUMPFUN_BUY_DISCRIMINATOR, PUMPFUN_PROGRAM_ID, PUMPFUN_SELL_DISCRIMINATOR};
+use crate::types::events::{MirrorIntent, TokenBalance, TransactionNotification, TxMeta};
+use anyhow::{anyhow, Result};
+use solana_sdk::{bs58, hash::hashv, pubkey::Pubkey};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::str::FromStr;
-use tracing::debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::{debug, info};
+
+/// How the BUY size is derived from the observed target trade.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeMode {
+    /// Always spend `max_buy_sol` (original behavior).
+    Fixed,
+    /// Spend `target_spent * size_ratio`, capped at `max_buy_sol`.
+    Proportional,
+}
+
+impl FromStr for SizeMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "fixed" => Ok(SizeMode::Fixed),
+            "proportional" => Ok(SizeMode::Proportional),
+            other => Err(anyhow!("Unknown SIZE_MODE '{other}' (expected fixed|proportional)")),
+        }
+    }
+}
+
+/// How a notified transaction is recognised as a swap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntentMode {
+    /// Token balance deltas only (original behavior).
+    Heuristic,
+    /// Only transactions that invoke a program in `SWAP_PROGRAMS`; undecodable ones are skipped.
+    Strict,
+    /// Like `Strict`, but falls back to the balance heuristic when instructions cannot be decoded.
+    Hybrid,
+    /// Like `Strict`, and only the mints the target's swap instructions name (see
+    /// `swapped_mints`) can be intents, so a transfer riding along with a swap is not one.
+    Instructions,
+}
+
+impl FromStr for IntentMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "heuristic" => Ok(IntentMode::Heuristic),
+            "strict" => Ok(IntentMode::Strict),
+            "hybrid" => Ok(IntentMode::Hybrid),
+            "instructions" => Ok(IntentMode::Instructions),
+            other => Err(anyhow!("Unknown INTENT_MODE '{other}' (expected heuristic|strict|hybrid|instructions)")),
+        }
+    }
+}
+
+/// Notifications skipped because the target's transaction failed on-chain.
+static FAILED_TX_SKIPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Jupiter v6 route instructions: discriminator, index of the user transfer authority,
+/// and indexes of the mint accounts among the instruction's accounts.
+const JUPITER_ROUTES: &[([u8; 8], usize, &[usize])] = &[
+    // route, route_with_token_ledger: destination mint only
+    ([229, 23, 203, 151, 122, 227, 173, 42], 1, &[5]),
+    ([150, 86, 71, 116, 167, 93, 14, 104], 1, &[5]),
+    // shared_accounts_route(_with_token_ledger), shared_accounts_exact_out_route
+    ([193, 32, 155, 51, 65, 214, 156, 129], 2, &[7, 8]),
+    ([230, 121, 143, 80, 119, 159, 106, 170], 2, &[7, 8]),
+    ([176, 209, 105, 168, 154, 125, 69, 62], 2, &[7, 8]),
+    // exact_out_route
+    ([208, 51, 239, 151, 123, 43, 237, 92], 1, &[5, 6]),
+];
+
+/// Programs whose invocation (top-level or via CPI) marks a transaction as a swap.
+pub const SWAP_PROGRAMS: &[(&str, &str)] = &[
+    ("Jupiter v6", JUPITER_PROGRAM_ID),
+    ("Raydium AMM v4", "675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8"),
+    ("Raydium CLMM", "CAMMCzo5YL8w4VFF8KVHrK22GGUsp5VTaW7grrKgrWqK"),
+    ("Raydium CPMM", "CPMMoo8L3F4NbTegBCKVNunggL7H1ZpdTHKxQB5qKP1C"),
+    ("Pump.fun", PUMPFUN_PROGRAM_ID),
+    ("Pump.fun AMM", "pAMMBay6oceH9fJKBRHGP5D4bD4sWpmSwMn52FMfXEA"),
+    ("Orca Whirlpool", "whirLbMiicVdio4qvUfM5KAg6Ct8VwpYzGff3uctyCc"),
+    ("Meteora DLMM", "LBUZKhRxPF3XUpBCjp4YzTKgLccjZhTSDM9YuVaPwxo"),
+];
+
+/// Mints a multi-hop route passes through (SOL -> USDC -> token). Their balance changes
+/// are legs of a route, never intents, whatever `BASE_MINTS` says.
+pub const ROUTING_INTERMEDIATES: &[&str] = &[SOL_MINT, USDC_MINT, USDT_MINT];
+
+/// Inputs for intent inference that come from configuration rather than the transaction.
+#[derive(Debug, Clone)]
+pub struct IntentConfig {
+    pub max_buy_sol: f64,
+    pub size_mode: SizeMode,
+    pub size_ratio: f64,
+    /// Buys sized below this are skipped (0 = no floor).
+    pub min_buy_sol: f64,
+    pub mode: IntentMode,
+    /// `TARGET_PDA`: smart-wallet PDA holding a target's tokens, keyed by target.
+    pub target_pdas: HashMap<Pubkey, Pubkey>,
+    /// `BASE_MINTS`: quote-side mints (always including wSOL) that are never bought or
+    /// sold themselves; an increase in one is sale proceeds.
+    pub base_mints: HashSet<String>,
+    pub dust: DustConfig,
+    /// `IGNORE_MINTS`: mints (e.g. LP tokens) whose balance changes are never intents.
+    pub ignore_mints: HashSet<String>,
+    /// `IGNORE_LP_EVENTS`: skip liquidity removals entirely. When off, the pair token a
+    /// removal returns is mirrored as a buy (liquidity adds are always skipped).
+    pub ignore_lp_events: bool,
+    /// `ENABLE_PUMPFUN_DETECTION`: also decode Pump.fun `buy` instructions, for buys whose
+    /// token balance entry is missing from the notification.
+    pub pumpfun_detection: bool,
+    /// `ALLOW_NON_SIGNER`: also read transactions the target did not sign. The `mentions`
+    /// subscription delivers those too (airdrops, transfers to the target), and they are
+    /// never the target's trades.
+    pub allow_non_signer: bool,
+    /// `SKIP_TOKEN_2022`: never mirror Token-2022 mints (transfer fees make the amount
+    /// received differ from the amount swapped).
+    pub skip_token_2022: bool,
+    /// `WATCH_AIRDROPS`: report tokens the target receives without paying (no swap, or a
+    /// transaction it did not sign) as `MirrorIntent::Watch` instead of dropping them or,
+    /// in `Heuristic` mode, mistaking them for buys.
+    pub watch_airdrops: bool,
+}
+
+impl IntentConfig {
+    /// Accounts whose balances count as `target`'s: the target and its PDA, if any.
+    pub fn owners_of(&self, target: &Pubkey) -> Vec<Pubkey> {
+        std::iter::once(*target).chain(self.target_pdas.get(target).copied()).collect()
+    }
+
+    /// Base mints and routing intermediates: never bought or sold as intents.
+    fn is_quote_side(&self, mint: &str) -> bool {
+        self.base_mints.contains(mint) || ROUTING_INTERMEDIATES.contains(&mint)
+    }
+}
+
+/// When a token balance change is too small to be an intent.
+#[derive(Debug, Clone, Default)]
+pub struct DustConfig {
+    /// `MIN_DELTA_RAW`: changes smaller than this many base units are dust.
+    pub min_delta_raw: u64,
+    /// `MIN_BUY_DELTA_UI`: increases smaller than this many whole tokens are not buys (0 = off).
+    pub min_buy_delta_ui: f64,
+    /// `DUST_OVERRIDES_FILE`: mint -> smallest change in whole tokens that counts, replacing
+    /// both limits above for that mint.
+    pub overrides: HashMap<String, f64>,
+}
+
+impl DustConfig {
+    /// Whether a change of `delta` base units of `mint` is dust. `buy` = the balance went up.
+    pub fn is_dust(&self, mint: &str, delta: u64, decimals: u8, buy: bool) -> bool {
+        let ui = delta as f64 / 10f64.powi(decimals as i32);
+        if let Some(min) = self.overrides.get(mint) {
+            return ui < *min;
+        }
+        delta < self.min_delta_raw || (buy && ui < self.min_buy_delta_ui)
+    }
+}
 
 /// Very lightweight heuristic:
-/// - Look at token balance changes in `meta.preTokenBalances`/`postTokenBalances`.
-/// - If TARGET ends up with MORE of some mint after tx => treat as BUY of that mint.
+/// - Look at token balance changes in `meta.preTokenBalances`/`postTokenBalances`, counting
+///   only token accounts owned by `target` or its configured PDA (pool vaults and
+///   counterparties move too).
+/// - If TARGET received SOL/wSOL (or another base mint) and ends up with LESS of some
+///   mints => treat as a SELL of each, with the sold fraction taken from pre/post balances.
+/// - Otherwise, every mint TARGET ends up with MORE of => a BUY of that mint, largest first.
+///   A transaction with several legs (a bundle of buys) yields several intents; routing
+///   intermediates (`ROUTING_INTERMEDIATES`) are never among them.
 ///
-/// This avoids parsing all instructions/programs and still works for most swaps.
-/// Limitations: it can mis-detect non-swap token receives.
-pub fn infer_intent_from_tx(json_msg: &serde_json::Value, max_buy_sol: f64) -> Result<Option<MirrorIntent>> {
-    // Expected Solana WS shape:
-    // { "method":"transactionNotification", "params": { "result": { "transaction": [...], "meta": {...} } } }
-    let result = json_msg
-        .pointer("/params/result")
-        .or_else(|| json_msg.pointer("/result"));
-
-    let Some(r) = result else { return Ok(None); };
-
-    let meta = r.get("meta");
-    let Some(meta) = meta else { return Ok(None); };
-
-    let pre = meta.get("preTokenBalances").and_then(|v| v.as_array()).cloned().unwrap_or_default();
-    let post = meta.get("postTokenBalances").and_then(|v| v.as_array()).cloned().unwrap_or_default();
-
-    // Build map mint -> (pre_ui, post_ui)
-    // We don't need exact decimals conversion for decision; just compare uiAmount.
-    use std::collections::HashMap;
-    let mut pre_map: HashMap<String, f64> = HashMap::new();
-    let mut post_map: HashMap<String, f64> = HashMap::new();
-
-    for x in pre {
-        if let (Some(mint), Some(ui)) = (x.get("mint"), x.pointer("/uiTokenAmount/uiAmount")) {
-            if let (Some(m), Some(v)) = (mint.as_str(), ui.as_f64()) {
-                pre_map.insert(m.to_string(), v);
+/// In `IntentMode::Heuristic` that is all, so airdrops and plain transfers can look like
+/// buys. `Strict` and `Hybrid` first require the transaction to invoke one of `SWAP_PROGRAMS`;
+/// `Instructions` also drops every mint the target's swap instructions do not name.
+/// With `watch_airdrops`, airdrops are recognised first and returned as `Watch` intents.
+/// In every mode the target must have signed the transaction (see `allow_non_signer`).
+pub fn infer_intent_from_tx(
+    tx: &TransactionNotification,
+    target: &Pubkey,
+    cfg: &IntentConfig,
+) -> BotResult<Vec<MirrorIntent>> {
+    let Some(meta) = tx.meta.as_ref() else { return Ok(Vec::new()); };
+
+    // Failed txs still carry balance arrays, but nothing in them happened
+    if let Some(err) = meta.err.as_ref().filter(|e| !e.is_null()) {
+        let skipped = FAILED_TX_SKIPPED.fetch_add(1, Ordering::Relaxed) + 1;
+        debug!("Target tx failed on-chain ({err}); skip");
+        if skipped == 1 || skipped.is_multiple_of(100) {
+            info!("Skipped {skipped} failed target tx notification(s) so far");
+        }
+        return Ok(Vec::new());
+    }
+
+    if cfg.watch_airdrops {
+        let watches = airdrop_watches(tx, target, cfg)?;
+        if !watches.is_empty() {
+            return Ok(watches);
+        }
+    }
+
+    if !cfg.allow_non_signer {
+        match tx.signers() {
+            Some(signers) if !signers.contains(target) => {
+                debug!("Target {target} did not sign the transaction (only mentioned); skip");
+                return Ok(Vec::new());
             }
+            Some(_) => {}
+            None => debug!("Cannot decode signers; signer check skipped"),
         }
     }
-    for x in post {
-        if let (Some(mint), Some(ui)) = (x.get("mint"), x.pointer("/uiTokenAmount/uiAmount")) {
-            if let (Some(m), Some(v)) = (mint.as_str(), ui.as_f64()) {
-                post_map.insert(m.to_string(), v);
+
+    if cfg.mode != IntentMode::Heuristic {
+        match swap_program(tx) {
+            Some(Some(name)) => debug!("Transaction invokes {name}"),
+            Some(None) => {
+                debug!("No known swap program invoked; not a swap");
+                return Ok(Vec::new());
+            }
+            None if matches!(cfg.mode, IntentMode::Strict | IntentMode::Instructions) => {
+                debug!("Cannot decode instructions; skipped in {:?} INTENT_MODE", cfg.mode);
+                return Ok(Vec::new());
             }
+            None => debug!("Cannot decode instructions; falling back to balance heuristic"),
         }
     }
 
-    // Find mint where post > pre by meaningful delta
-    let mut best: Option<(String, f64)> = None;
-    for (mint, post_v) in &post_map {
-        let pre_v = pre_map.get(mint).copied().unwrap_or(0.0);
-        let delta = post_v - pre_v;
-        if delta > 0.0 {
-            // ignore dust
-            if delta < 0.0000001 { continue; }
-            best = match best {
-                None => Some((mint.clone(), delta)),
-                Some((bm, bd)) => {
-                    if delta > bd { Some((mint.clone(), delta)) } else { Some((bm, bd)) }
+    // mint -> target's raw balance; deltas are exact integers, UI amounts are only for ranking and logs
+    let owners = cfg.owners_of(target);
+    let mut pre_map = token_balances(&meta.pre_token_balances, &owners);
+    let mut post_map = token_balances(&meta.post_token_balances, &owners);
+    pre_map.retain(|mint, _| !cfg.ignore_mints.contains(mint));
+    post_map.retain(|mint, _| !cfg.ignore_mints.contains(mint));
+    if cfg.skip_token_2022 {
+        let token_2022 = token_2022_program();
+        for map in [&mut pre_map, &mut post_map] {
+            map.retain(|mint, b| {
+                let keep = cfg.base_mints.contains(mint) || b.program != token_2022;
+                if !keep {
+                    debug!("Mint {mint} is a Token-2022 token; ignored (SKIP_TOKEN_2022)");
                 }
+                keep
+            });
+        }
+    }
+
+    if cfg.mode == IntentMode::Instructions {
+        let swapped = swapped_mints(tx, &owners).unwrap_or_default();
+        debug!("Target's swap instructions name mints {swapped:?}");
+        for map in [&mut pre_map, &mut post_map] {
+            map.retain(|mint, _| swapped.contains(mint) || cfg.is_quote_side(mint));
+        }
+    }
+
+    // Net flow decides the direction: SOL or another base mint coming in means the target
+    // sold something, even if some other token balance also went up
+    let base_received = cfg.base_mints.iter().filter(|m| *m != SOL_MINT).any(|m| {
+        post_map.get(m).map_or(0, |p| p.raw) > pre_map.get(m).map_or(0, |p| p.raw)
+    });
+    let proceeds = base_received || target_sol_received(tx, &owners) > 0;
+
+    match classify_liquidity(&pre_map, &post_map, proceeds, cfg) {
+        Some((LiquidityEvent::Remove, lp_mints)) if !cfg.ignore_lp_events => {
+            // The LP tokens burnt are not a sell; what came back is treated as bought
+            debug!("Liquidity removal (burnt {lp_mints:?}); mirroring the returned token as a buy");
+            pre_map.retain(|mint, _| !lp_mints.contains(mint));
+            post_map.retain(|mint, _| !lp_mints.contains(mint));
+        }
+        Some((event, lp_mints)) => {
+            debug!("Liquidity {event} (LP mints {lp_mints:?}); not a swap");
+            return Ok(Vec::new());
+        }
+        None => {}
+    }
+
+    if proceeds {
+        let sells = infer_sells(&pre_map, &post_map, cfg)?;
+        if !sells.is_empty() {
+            return Ok(sells);
+        }
+    }
+
+    // Every mint whose balance grew, largest (UI) increase first
+    let mut bought: Vec<(String, f64, Pubkey)> = Vec::new();
+    for (mint, post_v) in &post_map {
+        // A base mint going up is the proceeds side of a sell, never something to buy
+        if cfg.is_quote_side(mint) { continue; }
+        let pre_raw = pre_map.get(mint).map_or(0, |p| p.raw);
+        if post_v.raw <= pre_raw || cfg.dust.is_dust(mint, post_v.raw - pre_raw, post_v.decimals, true) { continue; }
+        let delta = TokenAmount { raw: post_v.raw - pre_raw, ..*post_v }.ui();
+        bought.push((mint.clone(), delta, post_v.program));
+    }
+    let mut pumpfun_cost_lamports: u64 = 0;
+    if cfg.pumpfun_detection {
+        for buy in pumpfun_buys(tx, &owners) {
+            let mint = buy.mint.to_string();
+            pumpfun_cost_lamports = pumpfun_cost_lamports.saturating_add(buy.max_sol_cost);
+            if cfg.is_quote_side(&mint) || cfg.ignore_mints.contains(&mint) || bought.iter().any(|(m, _, _)| *m == mint) {
+                continue;
+            }
+            if cfg.skip_token_2022 && buy.token_program == token_2022_program() {
+                debug!("Mint {mint} is a Token-2022 token; ignored (SKIP_TOKEN_2022)");
+                continue;
+            }
+            debug!("Pump.fun buy instruction for mint {mint} without a matching balance change");
+            // Bonding-curve tokens have 6 decimals
+            bought.push((mint, buy.token_amount as f64 / 1e6, buy.token_program));
+        }
+    }
+    bought.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    // A token decrease without SOL or a base mint coming back is a transfer, not a sell
+    if bought.is_empty() {
+        debug!("No token increase and no sale proceeds; not a swap");
+        return Ok(Vec::new());
+    }
+
+    // The target's SOL spend cannot be attributed to individual legs, so proportional
+    // sizing splits it evenly between them
+    let observed_input_sol = target_sol_spent(tx, &owners)
+        .or_else(|| (pumpfun_cost_lamports > 0).then(|| pumpfun_cost_lamports as f64 / 1_000_000_000.0))
+        .map(|s| s / bought.len() as f64);
+    let max_input_sol = match cfg.size_mode {
+        SizeMode::Fixed => cfg.max_buy_sol,
+        SizeMode::Proportional => {
+            let Some(spent_sol) = observed_input_sol else {
+                debug!("Proportional sizing: could not determine target SOL spend; skip");
+                return Ok(Vec::new());
             };
+            (spent_sol * cfg.size_ratio).min(cfg.max_buy_sol)
         }
+    };
+    if max_input_sol < cfg.min_buy_sol {
+        let mints: Vec<&str> = bought.iter().map(|(m, _, _)| m.as_str()).collect();
+        info!("BUY of {mints:?} sized at {max_input_sol} SOL each, below MIN_BUY_SOL={}; skip", cfg.min_buy_sol);
+        return Ok(Vec::new());
+    }
+
+    bought
+        .into_iter()
+        .map(|(mint, delta, token_program)| {
+            debug!("Heuristic intent: BUY mint={mint}, delta_ui={delta}, program={token_program}");
+            Ok(MirrorIntent::Buy {
+                output_mint: parse_mint(&mint)?,
+                max_input_sol,
+                observed_input_sol,
+                token_program,
+            })
+        })
+        .collect()
+}
+
+/// Tokens the target received in an airdrop: a transaction that invokes no swap program
+/// or that the target did not sign, where its token balances only went up and it paid
+/// at most the rent of the token accounts opened for them.
+fn airdrop_watches(tx: &TransactionNotification, target: &Pubkey, cfg: &IntentConfig) -> BotResult<Vec<MirrorIntent>> {
+    let Some(meta) = tx.meta.as_ref() else { return Ok(Vec::new()); };
+    let signed = tx.signers().map(|signers| signers.contains(target));
+    let swapped = swap_program(tx).map(|name| name.is_some());
+    // Undecodable and signed by the target: could be a swap, so not an airdrop
+    if signed != Some(false) && swapped != Some(false) {
+        return Ok(Vec::new());
     }
 
-    let Some((mint, delta)) = best else {
-        debug!("No positive token delta detected; skip");
-        return Ok(None);
+    let owners = cfg.owners_of(target);
+    let pre_map = token_balances(&meta.pre_token_balances, &owners);
+    let post_map = token_balances(&meta.post_token_balances, &owners);
+    let mut received = Vec::new();
+    for mint in pre_map.keys().chain(post_map.keys()).collect::<HashSet<_>>() {
+        if cfg.is_quote_side(mint) || cfg.ignore_mints.contains(mint) { continue; }
+        let pre = pre_map.get(mint).map_or(0, |p| p.raw);
+        let Some(post) = post_map.get(mint) else { return Ok(Vec::new()); };
+        if post.raw < pre {
+            return Ok(Vec::new());
+        }
+        if post.raw == pre || cfg.dust.is_dust(mint, post.raw - pre, post.decimals, true) { continue; }
+        if cfg.skip_token_2022 && post.program == token_2022_program() { continue; }
+        received.push((mint.clone(), post.program));
+    }
+    if received.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let rent = TOKEN_ACCOUNT_RENT_LAMPORTS * received.len() as i64;
+    if target_sol_delta(tx, &owners) < -rent {
+        debug!("Target paid SOL in a transaction that received tokens; not an airdrop");
+        return Ok(Vec::new());
+    }
+    received
+        .into_iter()
+        .map(|(mint, token_program)| {
+            debug!("Airdrop to target: mint={mint}, program={token_program}");
+            Ok(MirrorIntent::Watch { mint: parse_mint(&mint)?, token_program })
+        })
+        .collect()
+}
+
+/// A transaction that moves liquidity rather than swapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LiquidityEvent {
+    /// Pair tokens (and SOL or a base mint) in, LP tokens out.
+    Add,
+    /// LP tokens burnt, pair tokens (and SOL or a base mint) back.
+    Remove,
+}
+
+impl std::fmt::Display for LiquidityEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Add => "add",
+            Self::Remove => "remove",
+        })
+    }
+}
+
+/// Classifies a transaction where the target's non-base tokens moved both ways, returning
+/// the LP mints with the event. A removal returns SOL or a base mint (`proceeds`) along
+/// with the pair token and burns LP tokens (the mints that went down); an add spends
+/// them and mints LP tokens (the mints that went up). A token-to-token swap has the same
+/// shape and is skipped too; only SOL/base-paired trades can be mirrored faithfully anyway.
+fn classify_liquidity(
+    pre_map: &HashMap<String, TokenAmount>,
+    post_map: &HashMap<String, TokenAmount>,
+    proceeds: bool,
+    cfg: &IntentConfig,
+) -> Option<(LiquidityEvent, Vec<String>)> {
+    let mints: HashSet<&String> = pre_map.keys().chain(post_map.keys()).filter(|m| !cfg.is_quote_side(m)).collect();
+    let (mut up, mut down) = (Vec::new(), Vec::new());
+    for mint in mints {
+        let pre = pre_map.get(mint).map_or(0, |p| p.raw);
+        let post = post_map.get(mint).map_or(0, |p| p.raw);
+        let decimals = post_map.get(mint).or_else(|| pre_map.get(mint)).map_or(0, |b| b.decimals);
+        if post > pre && !cfg.dust.is_dust(mint, post - pre, decimals, false) {
+            up.push(mint.clone());
+        } else if pre > post && !cfg.dust.is_dust(mint, pre - post, decimals, false) {
+            down.push(mint.clone());
+        }
+    }
+    if up.is_empty() || down.is_empty() {
+        return None;
+    }
+    Some(if proceeds { (LiquidityEvent::Remove, down) } else { (LiquidityEvent::Add, up) })
+}
+
+/// Raw token balance (base units) with its mint's decimals and token program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TokenAmount {
+    raw: u64,
+    decimals: u8,
+    program: Pubkey,
+}
+
+impl TokenAmount {
+    fn ui(&self) -> f64 {
+        self.raw as f64 / 10f64.powi(self.decimals as i32)
+    }
+}
+
+/// mint -> balance held by `owners` in a `preTokenBalances`/`postTokenBalances` section,
+/// summed over their token accounts of that mint. Read from `uiTokenAmount.amount` and
+/// `.decimals`, which are always present (the deprecated `uiAmount` can be null).
+fn token_balances(entries: &[TokenBalance], owners: &[Pubkey]) -> HashMap<String, TokenAmount> {
+    let mut balances: HashMap<String, TokenAmount> = HashMap::new();
+    for entry in entries.iter().filter(|e| owned_by(e, owners)) {
+        let Some(amount) = token_amount(entry) else { continue; };
+        balances
+            .entry(entry.mint.clone())
+            .and_modify(|b| b.raw = b.raw.saturating_add(amount.raw))
+            .or_insert(amount);
+    }
+    balances
+}
+
+fn owned_by(entry: &TokenBalance, owners: &[Pubkey]) -> bool {
+    entry
+        .owner
+        .as_deref()
+        .and_then(|o| Pubkey::from_str(o).ok())
+        .is_some_and(|o| owners.contains(&o))
+}
+
+/// Reads one balance entry. `programId` is missing from older RPC responses, which
+/// predate Token-2022, so it defaults to the SPL Token program.
+fn token_amount(entry: &TokenBalance) -> Option<TokenAmount> {
+    let program = entry.program_id.as_deref().unwrap_or(TOKEN_PROGRAM_ID);
+    Some(TokenAmount {
+        raw: entry.ui_token_amount.amount.parse().ok()?,
+        decimals: entry.ui_token_amount.decimals,
+        program: Pubkey::from_str(program).ok()?,
+    })
+}
+
+fn token_2022_program() -> Pubkey {
+    Pubkey::from_str(TOKEN_2022_PROGRAM_ID).unwrap_or_default()
+}
+
+/// Rent-exempt balance of a 165-byte SPL token account, refunded when one is closed.
+const TOKEN_ACCOUNT_RENT_LAMPORTS: i64 = 2_039_280;
+
+/// SOL the target (with its PDA, `owners`) paid for the swap. Rent for accounts the swap
+/// opens is included. Returns `None` if no SOL went out.
+fn target_sol_spent(tx: &TransactionNotification, owners: &[Pubkey]) -> Option<f64> {
+    let net = target_sol_delta(tx, owners);
+    (net < 0).then(|| -net as f64 / 1_000_000_000.0)
+}
+
+/// Lamports of sale proceeds the target received, not counting the rent refunded by
+/// token accounts it closed (a full transfer that closes the account also returns SOL).
+fn target_sol_received(tx: &TransactionNotification, owners: &[Pubkey]) -> u64 {
+    let closed = tx.meta.as_ref().map_or(0, |meta| closed_token_accounts(meta, owners)) as i64;
+    (target_sol_delta(tx, owners) - closed * TOKEN_ACCOUNT_RENT_LAMPORTS).max(0) as u64
+}
+
+/// Net lamports into `owners` over the transaction: each owner's lamport delta
+/// (`preBalances`/`postBalances` at its account index, with `meta.fee` added back for the
+/// fee payer) plus the change in the wSOL token balances they own, so buys paid from and
+/// sells paid into an existing wSOL account count too.
+fn target_sol_delta(tx: &TransactionNotification, owners: &[Pubkey]) -> i64 {
+    let Some(meta) = tx.meta.as_ref() else { return 0; };
+    let mut net: i64 = 0;
+
+    let keys = account_keys(tx).unwrap_or_default();
+    for owner in owners {
+        if let Some(idx) = keys.iter().position(|k| k == owner) {
+            if let (Some(pre), Some(post)) = (meta.pre_balances.get(idx), meta.post_balances.get(idx)) {
+                // The fee payer is always account 0
+                let fee = if idx == 0 { meta.fee } else { 0 };
+                net += *post as i64 - *pre as i64 + fee as i64;
+            }
+        }
+    }
+
+    // wSOL has 9 decimals, so its raw amount is already in lamports
+    let wsol = |entries| token_balances(entries, owners).get(SOL_MINT).map_or(0, |b| b.raw);
+    net + wsol(&meta.post_token_balances) as i64 - wsol(&meta.pre_token_balances) as i64
+}
+
+/// Token accounts of `owners` listed before the transaction but gone after it.
+fn closed_token_accounts(meta: &TxMeta, owners: &[Pubkey]) -> usize {
+    let owned_indexes = |entries: &[TokenBalance]| -> Vec<u64> {
+        entries.iter().filter(|e| owned_by(e, owners)).map(|e| e.account_index).collect()
     };
+    let post = owned_indexes(&meta.post_token_balances);
+    owned_indexes(&meta.pre_token_balances).iter().filter(|i| !post.contains(i)).count()
+}
 
-    let output_mint = Pubkey::from_str(&mint)?;
-    debug!("Heuristic intent: BUY mint={mint}, delta_ui={delta}");
+/// Which of `targets` take part in the notified transaction, judged by its account keys.
+///
+/// If the keys cannot be recovered we can only attribute the tx when there is a single target.
+pub fn mentioned_targets(tx: &TransactionNotification, targets: &[Pubkey]) -> Vec<Pubkey> {
+    match account_keys(tx) {
+        Some(keys) => targets.iter().filter(|t| keys.contains(t)).copied().collect(),
+        None if targets.len() == 1 => targets.to_vec(),
+        None => {
+            debug!("Cannot decode account keys; unable to attribute tx to one of {} targets", targets.len());
+            Vec::new()
+        }
+    }
+}
 
-    Ok(Some(MirrorIntent::Buy {
-        output_mint,
-        max_input_sol: max_buy_sol,
+/// Name of the first `SWAP_PROGRAMS` entry the transaction invokes, top-level or through
+/// `meta.innerInstructions`. `Some(None)` = decoded, no swap program; `None` = undecodable.
+fn swap_program(tx: &TransactionNotification) -> Option<Option<&'static str>> {
+    let programs = invoked_programs(tx)?;
+    Some(SWAP_PROGRAMS.iter().find_map(|(name, id)| {
+        let id = Pubkey::from_str(id).ok()?;
+        programs.contains(&id).then_some(*name)
     }))
 }
+
+/// Program ids of every instruction in the transaction, resolved against the full key
+/// list (static keys plus lookup-table addresses) that `programIdIndex` refers to.
+pub fn invoked_programs(tx: &TransactionNotification) -> Option<Vec<Pubkey>> {
+    let keys = account_keys(tx)?;
+    let ixs = instructions(tx)?;
+    Some(ixs.iter().filter_map(|ix| keys.get(ix.program).copied()).collect())
+}
+
+/// One instruction, top-level or inner, with indexes into the full account key list.
+struct RawInstruction {
+    program: usize,
+    accounts: Vec<usize>,
+    data: Vec<u8>,
+}
+
+/// Every instruction in the transaction: the message's own, then `meta.innerInstructions`
+/// (json-encoded, with base58 data).
+fn instructions(notification: &TransactionNotification) -> Option<Vec<RawInstruction>> {
+    let tx = &notification.transaction;
+
+    let mut ixs: Vec<RawInstruction> = if tx.get(0).is_some() {
+        notification
+            .decoded()?
+            .message
+            .instructions()
+            .iter()
+            .map(|ix| RawInstruction {
+                program: ix.program_id_index as usize,
+                accounts: ix.accounts.iter().map(|a| *a as usize).collect(),
+                data: ix.data.clone(),
+            })
+            .collect()
+    } else {
+        tx.pointer("/message/instructions")?
+            .as_array()?
+            .iter()
+            .filter_map(json_instruction)
+            .collect()
+    };
+
+    if let Some(meta) = &notification.meta {
+        let inner_ixs = meta.inner_instructions.iter().flat_map(|group| &group.instructions);
+        ixs.extend(inner_ixs.filter_map(|ix| {
+            Some(RawInstruction {
+                program: ix.program_id_index? as usize,
+                accounts: ix.accounts.iter().map(|a| *a as usize).collect(),
+                data: bs58::decode(&ix.data).into_vec().unwrap_or_default(),
+            })
+        }));
+    }
+
+    Some(ixs)
+}
+
+fn json_instruction(ix: &serde_json::Value) -> Option<RawInstruction> {
+    Some(RawInstruction {
+        program: ix.get("programIdIndex")?.as_u64()? as usize,
+        accounts: ix
+            .get("accounts")
+            .and_then(|a| a.as_array())
+            .map(|a| a.iter().filter_map(|i| i.as_u64()).map(|i| i as usize).collect())
+            .unwrap_or_default(),
+        data: ix
+            .get("data")
+            .and_then(|d| d.as_str())
+            .and_then(|d| bs58::decode(d).into_vec().ok())
+            .unwrap_or_default(),
+    })
+}
+
+/// A Pump.fun bonding-curve `buy(amount, max_sol_cost)` made by the target.
+struct PumpfunBuy {
+    mint: Pubkey,
+    /// Raw tokens bought.
+    token_amount: u64,
+    /// Most lamports the buyer allowed the curve to take.
+    max_sol_cost: u64,
+    token_program: Pubkey,
+}
+
+/// Mints the target (`owners`) swapped in the transaction, read from the instructions of
+/// `SWAP_PROGRAMS` rather than from which balances moved:
+/// - Jupiter routes name their mints as accounts; used when the target is the authority.
+/// - Pump.fun `buy`/`sell` name the mint, with the target as the user.
+/// - Any swap instruction, inner ones included, lists the token accounts it moves; those
+///   owned by the target give their mints through the balance entries' `accountIndex`.
+///
+/// `None` when the instructions cannot be decoded.
+fn swapped_mints(tx: &TransactionNotification, owners: &[Pubkey]) -> Option<HashSet<String>> {
+    let keys = account_keys(tx)?;
+    let ixs = instructions(tx)?;
+    let swap_programs: Vec<Pubkey> = SWAP_PROGRAMS.iter().filter_map(|(_, id)| Pubkey::from_str(id).ok()).collect();
+    let jupiter = Pubkey::from_str(JUPITER_PROGRAM_ID).ok()?;
+    let pumpfun = Pubkey::from_str(PUMPFUN_PROGRAM_ID).ok()?;
+
+    // Account index -> mint, for the target's token accounts
+    let mut token_accounts: HashMap<usize, String> = HashMap::new();
+    if let Some(meta) = tx.meta.as_ref() {
+        for entry in meta.pre_token_balances.iter().chain(&meta.post_token_balances) {
+            if owned_by(entry, owners) {
+                token_accounts.insert(entry.account_index as usize, entry.mint.clone());
+            }
+        }
+    }
+
+    let mut mints = HashSet::new();
+    for ix in ixs.iter().filter(|ix| keys.get(ix.program).is_some_and(|p| swap_programs.contains(p))) {
+        let account = |i: usize| ix.accounts.get(i).and_then(|k| keys.get(*k));
+        let program = keys[ix.program];
+        let named: &[usize] = if program == jupiter {
+            JUPITER_ROUTES
+                .iter()
+                .find(|(disc, authority, _)| ix.data.starts_with(disc) && account(*authority).is_some_and(|a| owners.contains(a)))
+                .map_or(&[], |(_, _, mints)| *mints)
+        } else if program == pumpfun
+            && (ix.data.starts_with(&PUMPFUN_BUY_DISCRIMINATOR) || ix.data.starts_with(&PUMPFUN_SELL_DISCRIMINATOR))
+            && account(6).is_some_and(|user| owners.contains(user))
+        {
+            &[2]
+        } else {
+            &[]
+        };
+        mints.extend(named.iter().filter_map(|i| account(*i)).map(|k| k.to_string()));
+        mints.extend(ix.accounts.iter().filter_map(|i| token_accounts.get(i).cloned()));
+    }
+    Some(mints)
+}
+
+/// Pump.fun `buy` instructions (top-level or via CPI) whose buyer is one of `owners`.
+/// Data: 8-byte discriminator, `amount: u64`, `max_sol_cost: u64` (little endian).
+/// Accounts: 2 = mint, 6 = user, 8 = token program.
+fn pumpfun_buys(tx: &TransactionNotification, owners: &[Pubkey]) -> Vec<PumpfunBuy> {
+    let (Some(keys), Some(ixs)) = (account_keys(tx), instructions(tx)) else {
+        return Vec::new();
+    };
+    let Ok(program) = Pubkey::from_str(PUMPFUN_PROGRAM_ID) else { return Vec::new(); };
+    let u64_at = |data: &[u8], at: usize| Some(u64::from_le_bytes(data.get(at..at + 8)?.try_into().ok()?));
+
+    ixs.iter()
+        .filter(|ix| keys.get(ix.program) == Some(&program) && ix.data.starts_with(&PUMPFUN_BUY_DISCRIMINATOR))
+        .filter_map(|ix| {
+            let account = |i: usize| ix.accounts.get(i).and_then(|k| keys.get(*k)).copied();
+            let user = account(6)?;
+            if !owners.contains(&user) {
+                return None;
+            }
+            Some(PumpfunBuy {
+                mint: account(2)?,
+                token_amount: u64_at(&ix.data, 8)?,
+                max_sol_cost: u64_at(&ix.data, 16)?,
+                token_program: account(8).unwrap_or_else(|| Pubkey::from_str(TOKEN_PROGRAM_ID).unwrap_or_default()),
+            })
+        })
+        .collect()
+}
+
+/// Account keys of the notified transaction, in the order `preBalances`/`postBalances` use:
+/// static message keys followed by `meta.loadedAddresses` (writable, then readonly).
+///
+/// Handles both the base64 `[data, "base64"]` encoding we subscribe with and the json
+/// `message.accountKeys` form (plain strings or `{ "pubkey": .. }` objects).
+pub fn account_keys(notification: &TransactionNotification) -> Option<Vec<Pubkey>> {
+    let tx = &notification.transaction;
+
+    let mut keys = if tx.get(0).is_some() {
+        notification.decoded()?.message.static_account_keys().to_vec()
+    } else {
+        tx.pointer("/message/accountKeys")?
+            .as_array()?
+            .iter()
+            .filter_map(|k| k.as_str().or_else(|| k.get("pubkey").and_then(|p| p.as_str())))
+            .filter_map(|k| Pubkey::from_str(k).ok())
+            .collect()
+    };
+
+    if let Some(meta) = &notification.meta {
+        let loaded = meta.loaded_addresses.writable.iter().chain(&meta.loaded_addresses.readonly);
+        keys.extend(loaded.filter_map(|k| Pubkey::from_str(k).ok()));
+    }
+
+    Some(keys)
+}
+
+/// Reports a SELL for every mint whose balance dropped, largest fraction first.
+/// Callers have already checked that SOL or wSOL came back to the target.
+///
+/// The fraction is dimensionless (delta / pre), so it can be applied to our own raw balance
+/// regardless of decimals. A mint missing from `post` means the token account was closed,
+/// i.e. the target sold everything.
+fn infer_sells(
+    pre_map: &HashMap<String, TokenAmount>,
+    post_map: &HashMap<String, TokenAmount>,
+    cfg: &IntentConfig,
+) -> BotResult<Vec<MirrorIntent>> {
+    let mut sold: Vec<(String, f64)> = Vec::new();
+    for (mint, pre_v) in pre_map {
+        if cfg.is_quote_side(mint) || pre_v.raw == 0 { continue; }
+        let post_raw = post_map.get(mint).map_or(0, |p| p.raw);
+        if post_raw >= pre_v.raw || cfg.dust.is_dust(mint, pre_v.raw - post_raw, pre_v.decimals, false) { continue; }
+        let fraction = (pre_v.raw - post_raw) as f64 / pre_v.raw as f64;
+        sold.push((mint.clone(), fraction));
+    }
+    sold.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    if sold.is_empty() {
+        debug!("Proceeds received but no token balance went down; not a sell");
+    }
+
+    sold.into_iter()
+        .map(|(mint, fraction)| {
+            debug!("Heuristic intent: SELL mint={mint}, fraction={fraction}");
+            Ok(MirrorIntent::Sell {
+                input_mint: parse_mint(&mint)?,
+                fraction,
+            })
+        })
+        .collect()
+}
+
+fn parse_mint(mint: &str) -> BotResult<Pubkey> {
+    Pubkey::from_str(mint).map_err(|e| BotError::IntentParse(format!("invalid mint {mint}: {e}")))
+}
+
+/// Deterministic id for the intent inferred from target tx `signature`, so records
+/// exported by several instances can be joined with on-chain data.
+///
+/// Derivation: the first 8 bytes of
+/// `sha256("ammalgam-intent-v1" | namespace | signature | mint | direction)`, hex-encoded,
+/// where `|` is a 0x00 byte, `mint` is base58 and `direction` is `buy` or `sell`.
+pub fn intent_id(namespace: &str, signature: &str, mint: &Pubkey, direction: &str) -> String {
+    let mint = mint.to_string();
+    let hash = hashv(&[
+        b"ammalgam-intent-v1",
+        b"\0",
+        namespace.as_bytes(),
+        b"\0",
+        signature.as_bytes(),
+        b"\0",
+        mint.as_bytes(),
+        b"\0",
+        direction.as_bytes(),
+    ]);
+    hash.as_ref()[..8].iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// The last `max` intent ids handed out, with what each was derived from, so a truncated
+/// hash that collides within the namespace is caught when it is claimed instead of
+/// silently merging two trades' records.
+#[derive(Debug)]
+pub struct IntentIds {
+    max: usize,
+    order: VecDeque<String>,
+    claimed: HashMap<String, String>,
+}
+
+impl IntentIds {
+    pub fn new(max: usize) -> Self {
+        Self { max: max.max(1), order: VecDeque::new(), claimed: HashMap::new() }
+    }
+
+    /// `intent_id` for these inputs, failing if the id was already claimed for different
+    /// ones. Claiming the same inputs again (a replayed notification) returns the same id.
+    pub fn claim(&mut self, namespace: &str, signature: &str, mint: &Pubkey, direction: &str) -> Result<String> {
+        let id = intent_id(namespace, signature, mint, direction);
+        let source = format!("{signature}/{mint}/{direction}");
+        match self.claimed.get(&id) {
+            Some(existing) if *existing == source => return Ok(id),
+            Some(existing) => {
+                return Err(anyhow!("Intent id {id} for {source} collides with {existing} in namespace '{namespace}'"))
+            }
+            None => {}
+        }
+        self.claimed.insert(id.clone(), source);
+        self.order.push_back(id.clone());
+        while self.order.len() > self.max {
+            if let Some(old) = self.order.pop_front() {
+                self.claimed.remove(&old);
+            }
+        }
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::events::{LoadedAddresses, UiTokenAmount};
+    use solana_sdk::transaction::VersionedTransaction;
+    use serde_json::json;
+
+    /// Wallet that signs the `tests/fixtures/helius` transactions.
+    const FIXTURE_TARGET: &str = "FmYgRtKAMf9bqS87Y5WjCZ9yAAcY7zGYE2aR5Nx9fpoq";
+    const BONK: &str = "DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263";
+    const WIF: &str = "EKpQGSJtjMFqKZ9KQanSqYXRcF8fBopzLHYxdM65zcjm";
+    const POPCAT: &str = "7GCihgDB8fe6KNjn2MYtkzZcRjQy3t9GHdC8uHYmW2hr";
+
+    #[test]
+    fn default_dust_config_keeps_every_change() {
+        let dust = DustConfig::default();
+        assert!(!dust.is_dust("mint", 1, 0, true));
+        assert!(!dust.is_dust("mint", 1, 9, false));
+    }
+
+    #[test]
+    fn dust_override_replaces_the_global_limits_for_its_mint() {
+        let dust = DustConfig {
+            min_delta_raw: 1_000,
+            min_buy_delta_ui: 10.0,
+            overrides: HashMap::from([("cheap".to_string(), 1_000_000.0)]),
+        };
+        // 50 whole tokens: a buy under the global limits, dust under the override
+        assert!(!dust.is_dust("other", 50_000_000, 6, true));
+        assert!(dust.is_dust("cheap", 50_000_000, 6, true));
+        // And the override lets through what the global raw floor would drop
+        let dust = DustConfig { overrides: HashMap::from([("whole".to_string(), 1.0)]), ..dust };
+        assert!(dust.is_dust("other", 5, 0, false));
+        assert!(!dust.is_dust("whole", 5, 0, false));
+
+        let target = Pubkey::new_unique();
+        let mint = Pubkey::new_unique().to_string();
+        let tx = notification(
+            &[target],
+            json!([]),
+            &[(2_000_000_000, 999_995_000)],
+            vec![],
+            vec![balance(1, &mint, &target, 50_000_000, 6)],
+        );
+        assert_eq!(infer_intent_from_tx(&tx, &target, &config()).unwrap().len(), 1);
+        let mut cfg = config();
+        cfg.dust.overrides.insert(mint, 100.0);
+        assert!(infer_intent_from_tx(&tx, &target, &cfg).unwrap().is_empty());
+    }
+
+    #[test]
+    fn liquidity_add_is_not_a_buy_of_the_lp_mint() {
+        let target = Pubkey::new_unique();
+        let (token, lp) = (Pubkey::new_unique().to_string(), Pubkey::new_unique().to_string());
+        // SOL and the pair token go in, LP tokens come out
+        let tx = notification(
+            &[target],
+            json!([]),
+            &[(3_000_000_000, 1_999_995_000)],
+            vec![balance(1, &token, &target, 500_000_000, 6)],
+            vec![balance(1, &token, &target, 0, 6), balance(2, &lp, &target, 70_000_000, 9)],
+        );
+        let cfg = config();
+        let pre = token_balances(&tx.meta.as_ref().unwrap().pre_token_balances, &[target]);
+        let post = token_balances(&tx.meta.as_ref().unwrap().post_token_balances, &[target]);
+        assert_eq!(classify_liquidity(&pre, &post, false, &cfg), Some((LiquidityEvent::Add, vec![lp.clone()])));
+        assert!(infer_intent_from_tx(&tx, &target, &cfg).unwrap().is_empty());
+    }
+
+    #[test]
+    fn liquidity_removal_is_skipped_or_mirrored_as_a_buy() {
+        let target = Pubkey::new_unique();
+        let (token, lp) = (Pubkey::new_unique(), Pubkey::new_unique().to_string());
+        // LP tokens burnt, SOL and the pair token back
+        let tx = notification(
+            &[target],
+            json!([]),
+            &[(1_000_000_000, 1_999_995_000)],
+            vec![balance(1, &lp, &target, 70_000_000, 9)],
+            vec![balance(1, &lp, &target, 0, 9), balance(2, &token.to_string(), &target, 500_000_000, 6)],
+        );
+        let cfg = config();
+        let pre = token_balances(&tx.meta.as_ref().unwrap().pre_token_balances, &[target]);
+        let post = token_balances(&tx.meta.as_ref().unwrap().post_token_balances, &[target]);
+        assert_eq!(classify_liquidity(&pre, &post, true, &cfg), Some((LiquidityEvent::Remove, vec![lp])));
+
+        // IGNORE_LP_EVENTS=true: neither a sell of the LP mint nor a buy of the pair token
+        assert!(infer_intent_from_tx(&tx, &target, &cfg).unwrap().is_empty());
+
+        let cfg = IntentConfig { ignore_lp_events: false, ..config() };
+        let intents = infer_intent_from_tx(&tx, &target, &cfg).unwrap();
+        assert!(matches!(intents.as_slice(), [MirrorIntent::Buy { output_mint, .. }] if *output_mint == token));
+    }
+
+    #[test]
+    fn ignored_mint_is_never_an_intent() {
+        let target = Pubkey::new_unique();
+        let lp = Pubkey::new_unique().to_string();
+        // A single-sided deposit looks exactly like a buy of the LP mint
+        let tx = notification(
+            &[target],
+            json!([]),
+            &[(3_000_000_000, 1_999_995_000)],
+            vec![],
+            vec![balance(1, &lp, &target, 70_000_000, 9)],
+        );
+        assert_eq!(infer_intent_from_tx(&tx, &target, &config()).unwrap().len(), 1);
+
+        let cfg = IntentConfig { ignore_mints: HashSet::from([lp]), ..config() };
+        assert!(infer_intent_from_tx(&tx, &target, &cfg).unwrap().is_empty());
+    }
+
+    #[test]
+    fn multi_hop_route_mirrors_only_the_final_mint() {
+        let target = Pubkey::new_unique();
+        let token = Pubkey::new_unique();
+        // SOL -> wSOL -> USDC -> token, with a little wSOL and USDC left over from the hops
+        let tx = notification(
+            &[target],
+            json!([]),
+            &[(2_000_000_000, 999_995_000)],
+            vec![],
+            vec![
+                balance(1, SOL_MINT, &target, 1_000, 9),
+                balance(2, USDC_MINT, &target, 12, 6),
+                balance(3, &token.to_string(), &target, 42_000_000, 6),
+            ],
+        );
+        let intents = infer_intent_from_tx(&tx, &target, &config()).unwrap();
+        assert!(matches!(intents.as_slice(), [MirrorIntent::Buy { output_mint, .. }] if *output_mint == token));
+    }
+
+    #[test]
+    fn bundle_of_buys_yields_one_intent_per_mint() {
+        let target = Pubkey::new_unique();
+        let (small, large) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let tx = notification(
+            &[target],
+            json!([]),
+            &[(2_000_000_000, 999_995_000)],
+            vec![],
+            vec![balance(1, &small.to_string(), &target, 1_000_000, 6), balance(2, &large.to_string(), &target, 9_000_000, 6)],
+        );
+        let mints: Vec<Pubkey> = infer_intent_from_tx(&tx, &target, &config()).unwrap().iter().map(|i| *i.mint()).collect();
+        assert_eq!(mints, vec![large, small]);
+    }
+
+    #[test]
+    fn token_2022_buy_carries_its_program_unless_skipped() {
+        let target = Pubkey::new_unique();
+        let (mint, legacy) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let token_2022 = |b: TokenBalance| TokenBalance { program_id: Some(TOKEN_2022_PROGRAM_ID.to_string()), ..b };
+        // Older RPC responses omit programId; those default to the SPL Token program
+        let no_program = |b: TokenBalance| TokenBalance { program_id: None, ..b };
+        let tx = notification(
+            &[target],
+            json!([]),
+            &[(2_000_000_000, 999_995_000)],
+            vec![],
+            vec![
+                token_2022(balance(1, &mint.to_string(), &target, 9_000_000, 6)),
+                no_program(balance(2, &legacy.to_string(), &target, 1_000_000, 6)),
+            ],
+        );
+        let programs: Vec<(Pubkey, Pubkey)> = infer_intent_from_tx(&tx, &target, &config())
+            .unwrap()
+            .into_iter()
+            .map(|i| match i {
+                MirrorIntent::Buy { output_mint, token_program, .. } => (output_mint, token_program),
+                other => panic!("expected a buy, got {other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            programs,
+            vec![(mint, token_2022_program()), (legacy, Pubkey::from_str(TOKEN_PROGRAM_ID).unwrap())]
+        );
+
+        let cfg = IntentConfig { skip_token_2022: true, ..config() };
+        let mints: Vec<Pubkey> = infer_intent_from_tx(&tx, &target, &cfg).unwrap().iter().map(|i| *i.mint()).collect();
+        assert_eq!(mints, vec![legacy]);
+    }
+
+    #[test]
+    fn pumpfun_buy_without_a_balance_entry_is_detected_from_the_instruction() {
+        let target = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let program = |id: &str| Pubkey::from_str(id).unwrap();
+        // Keys and accounts as in a bonding-curve buy: global, fee recipient, mint, bonding
+        // curve, its token account, the user's token account, user, system program, token
+        // program, rent, event authority, program
+        let keys = [
+            target,
+            program("4wTV1YmiEkRvAtNtsSGPtUrqRYQMe5SKy2uB4Jjaxnjf"),
+            program("CebN5WGQ4jvEPvsVU4EoHEpgzq1VV7AbicfhtW4xC9iM"),
+            mint,
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            solana_sdk::system_program::id(),
+            program(TOKEN_PROGRAM_ID),
+            program("SysvarRent111111111111111111111111111111111"),
+            program("Ce6TQqeHC9p8KetsN6JsjHK7UTZk7nasjjnr7XxXp9F1"),
+            program(PUMPFUN_PROGRAM_ID),
+        ];
+        let mut data = PUMPFUN_BUY_DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&35_000_000_000u64.to_le_bytes());
+        data.extend_from_slice(&1_020_000_000u64.to_le_bytes());
+        let ix = json!({
+            "programIdIndex": 11,
+            "accounts": [1, 2, 3, 4, 5, 6, 0, 7, 8, 9, 10, 11],
+            "data": bs58::encode(data).into_string(),
+        });
+        // The user's token account was opened in the same tx and has no postTokenBalances
+        // entry yet; the curve took 1 SOL plus the account's rent
+        let tx = notification(&keys, json!([ix]), &[(3_000_000_000, 1_997_955_720)], vec![], vec![]);
+
+        assert!(infer_intent_from_tx(&tx, &target, &config()).unwrap().is_empty());
+
+        let cfg = IntentConfig { pumpfun_detection: true, ..config() };
+        match infer_intent_from_tx(&tx, &target, &cfg).unwrap().as_slice() {
+            [MirrorIntent::Buy { output_mint, observed_input_sol: Some(spent), token_program, .. }] => {
+                assert_eq!(*output_mint, mint);
+                assert_eq!(*token_program, program(TOKEN_PROGRAM_ID));
+                assert!((spent - 1.00203928).abs() < 1e-9, "{spent}");
+            }
+            other => panic!("expected one buy, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn airdrop_creates_a_watch_not_a_buy() {
+        let (target, sender) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mint = Pubkey::new_unique();
+        // Signed and paid for by the sender; the target only receives the tokens
+        let tx = notification(
+            &[sender, target],
+            json!([]),
+            &[(1_000_000_000, 997_955_720), (500_000_000, 500_000_000)],
+            vec![],
+            vec![balance(2, &mint.to_string(), &target, 1_000_000_000, 6)],
+        );
+        let cfg = IntentConfig { watch_airdrops: true, ..config() };
+        let intents = infer_intent_from_tx(&tx, &target, &cfg).unwrap();
+        assert!(matches!(intents.as_slice(), [MirrorIntent::Watch { mint: m, .. }] if *m == mint));
+
+        // Without WATCH_AIRDROPS a transaction the target did not sign is dropped
+        assert!(infer_intent_from_tx(&tx, &target, &config()).unwrap().is_empty());
+        // and reading it anyway in the heuristic mode mistakes it for a buy
+        let cfg = IntentConfig { allow_non_signer: true, ..config() };
+        let intents = infer_intent_from_tx(&tx, &target, &cfg).unwrap();
+        assert!(matches!(intents.as_slice(), [MirrorIntent::Buy { .. }]));
+    }
+
+    #[test]
+    fn tokens_the_target_paid_for_are_not_an_airdrop() {
+        let target = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let tx = notification(
+            &[target],
+            json!([]),
+            &[(2_000_000_000, 999_995_000)],
+            vec![],
+            vec![balance(2, &mint.to_string(), &target, 1_000_000_000, 6)],
+        );
+        let cfg = IntentConfig { watch_airdrops: true, ..config() };
+        assert!(airdrop_watches(&tx, &target, &cfg).unwrap().is_empty());
+    }
+
+    fn base64_notification(tx: &VersionedTransaction, loaded: LoadedAddresses) -> TransactionNotification {
+        use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
+        TransactionNotification {
+            transaction: json!([B64.encode(bincode::serialize(tx).unwrap()), "base64"]),
+            meta: Some(TxMeta { loaded_addresses: loaded, ..Default::default() }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn account_keys_of_a_legacy_message() {
+        use solana_sdk::message::{Message, VersionedMessage};
+        let (payer, to) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let ix = solana_sdk::system_instruction::transfer(&payer, &to, 1);
+        let message = VersionedMessage::Legacy(Message::new(&[ix], Some(&payer)));
+        let tx = VersionedTransaction { signatures: vec![Default::default()], message };
+
+        let keys = account_keys(&base64_notification(&tx, LoadedAddresses::default())).unwrap();
+        assert_eq!(keys, vec![payer, to, solana_sdk::system_program::id()]);
+    }
+
+    #[test]
+    fn account_keys_of_a_v0_message_append_loaded_addresses() {
+        use solana_sdk::message::{v0, VersionedMessage};
+        let (payer, to) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (writable, readonly) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let ix = solana_sdk::system_instruction::transfer(&payer, &to, 1);
+        let message = v0::Message::try_compile(&payer, &[ix], &[], Default::default()).unwrap();
+        let tx = VersionedTransaction { signatures: vec![Default::default()], message: VersionedMessage::V0(message) };
+        let loaded = LoadedAddresses { writable: vec![writable.to_string()], readonly: vec![readonly.to_string()] };
+
+        let keys = account_keys(&base64_notification(&tx, loaded)).unwrap();
+        assert_eq!(keys, vec![payer, to, solana_sdk::system_program::id(), writable, readonly]);
+    }
+
+    #[test]
+    fn account_keys_of_a_json_message() {
+        let (payer, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let tx = TransactionNotification {
+            transaction: json!({ "message": { "accountKeys": [payer.to_string(), { "pubkey": other.to_string() }] } }),
+            ..Default::default()
+        };
+        assert_eq!(account_keys(&tx).unwrap(), vec![payer, other]);
+    }
+
+    #[test]
+    fn intent_ids_reclaim_same_inputs_and_reject_collisions() {
+        let mint = Pubkey::new_unique();
+        let mut ids = IntentIds::new(16);
+        let id = ids.claim("ns", "sig1", &mint, "buy").unwrap();
+        assert_eq!(id, intent_id("ns", "sig1", &mint, "buy"));
+        assert_eq!(ids.claim("ns", "sig1", &mint, "buy").unwrap(), id);
+
+        // Force the id of another trade onto this one's slot
+        let other = intent_id("ns", "sig2", &mint, "buy");
+        ids.claimed.insert(other.clone(), format!("sig1/{mint}/buy"));
+        let err = ids.claim("ns", "sig2", &mint, "buy").unwrap_err().to_string();
+        assert!(err.contains(&other) && err.contains("collides"), "{err}");
+    }
+
+    /// A Helius `transactionNotification` message from `tests/fixtures/helius`, parsed the
+    /// way the WS stream parses it.
+    fn fixture(name: &str) -> TransactionNotification {
+        let path = format!("{}/tests/fixtures/helius/{name}.json", env!("CARGO_MANIFEST_DIR"));
+        let text = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{path}: {e}"));
+        TransactionNotification::from_ws(&serde_json::from_str(&text).unwrap()).unwrap()
+    }
+
+    fn fixture_target() -> Pubkey {
+        Pubkey::from_str(FIXTURE_TARGET).unwrap()
+    }
+
+    fn config() -> IntentConfig {
+        IntentConfig {
+            max_buy_sol: 0.1,
+            size_mode: SizeMode::Fixed,
+            size_ratio: 1.0,
+            min_buy_sol: 0.0,
+            mode: IntentMode::Heuristic,
+            target_pdas: HashMap::new(),
+            base_mints: HashSet::from([SOL_MINT.to_string()]),
+            dust: DustConfig::default(),
+            ignore_mints: HashSet::new(),
+            ignore_lp_events: true,
+            pumpfun_detection: false,
+            skip_token_2022: false,
+            allow_non_signer: false,
+            watch_airdrops: false,
+        }
+    }
+
+    #[test]
+    fn jupiter_route_fixture_is_a_buy_in_every_mode() {
+        // v0 transaction: the pool accounts come from a lookup table
+        let tx = fixture("jupiter_route_buy_v0");
+        assert_eq!(swap_program(&tx), Some(Some("Jupiter v6")));
+        for mode in [IntentMode::Heuristic, IntentMode::Strict, IntentMode::Hybrid, IntentMode::Instructions] {
+            let intents = infer_intent_from_tx(&tx, &fixture_target(), &IntentConfig { mode, ..config() }).unwrap();
+            assert!(
+                matches!(intents.as_slice(), [MirrorIntent::Buy { output_mint, observed_input_sol: Some(sol), .. }]
+                    if output_mint.to_string() == BONK && (sol - 0.5).abs() < 1e-9),
+                "{mode:?}: {intents:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn raydium_swap_fixture_is_a_buy_in_every_mode() {
+        let tx = fixture("raydium_swap_buy");
+        assert_eq!(swap_program(&tx), Some(Some("Raydium AMM v4")));
+        for mode in [IntentMode::Heuristic, IntentMode::Strict, IntentMode::Hybrid, IntentMode::Instructions] {
+            let intents = infer_intent_from_tx(&tx, &fixture_target(), &IntentConfig { mode, ..config() }).unwrap();
+            assert!(
+                matches!(intents.as_slice(), [MirrorIntent::Buy { output_mint, observed_input_sol: Some(sol), .. }]
+                    if output_mint.to_string() == WIF && (sol - 0.32).abs() < 1e-9),
+                "{mode:?}: {intents:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn null_ui_amounts_fixture_is_a_buy_of_the_raw_amount() {
+        let tx = fixture("raydium_buy_ui_amount_null");
+        let meta = tx.meta.as_ref().unwrap();
+        assert!(meta.post_token_balances.iter().all(|b| b.ui_token_amount.ui_amount.is_none()));
+        assert_eq!(token_balances(&meta.post_token_balances, &[fixture_target()])[POPCAT].raw, 312_504_118_377);
+
+        // Paid in lamports, including the rent of the token account the swap opened
+        let intents = infer_intent_from_tx(&tx, &fixture_target(), &config()).unwrap();
+        assert!(
+            matches!(intents.as_slice(), [MirrorIntent::Buy { output_mint, observed_input_sol: Some(sol), .. }]
+                if output_mint.to_string() == POPCAT && (sol - 0.202_039_28).abs() < 1e-9),
+            "{intents:?}"
+        );
+    }
+
+    #[test]
+    fn pool_balance_rising_in_a_sell_fixture_is_not_a_buy() {
+        // The target sells 40% of its WIF; the pool's WIF vault rises by as much
+        let tx = fixture("raydium_sell_pool_balance_rises");
+        let meta = tx.meta.as_ref().unwrap();
+        let pool = Pubkey::from_str("5Q544fKrFoe6tsEbD7S8EmxGTJYAKtTVhAW5Q5pge4j1").unwrap();
+        let wif = |entries: &[TokenBalance]| token_balances(entries, &[pool])[WIF].raw;
+        assert!(wif(&meta.post_token_balances) > wif(&meta.pre_token_balances));
+
+        for mode in [IntentMode::Heuristic, IntentMode::Strict, IntentMode::Instructions] {
+            let intents = infer_intent_from_tx(&tx, &fixture_target(), &IntentConfig { mode, ..config() }).unwrap();
+            assert!(
+                matches!(intents.as_slice(), [MirrorIntent::Sell { input_mint, fraction }]
+                    if input_mint.to_string() == WIF && (fraction - 0.4).abs() < 1e-12),
+                "{mode:?}: {intents:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn squads_vault_fixture_is_the_targets_buy_only_with_its_pda() {
+        // The target signs as a multisig member; the vault PDA pays and receives the tokens
+        let tx = fixture("squads_vault_jupiter_buy");
+        let vault = Pubkey::from_str("B9KVMoiHm2HYXTPfZpCBb1n1knPV3qdmyn68ffEgZsmB").unwrap();
+        assert!(infer_intent_from_tx(&tx, &fixture_target(), &config()).unwrap().is_empty());
+
+        let cfg = IntentConfig {
+            mode: IntentMode::Instructions,
+            target_pdas: HashMap::from([(fixture_target(), vault)]),
+            ..config()
+        };
+        let intents = infer_intent_from_tx(&tx, &fixture_target(), &cfg).unwrap();
+        assert!(
+            matches!(intents.as_slice(), [MirrorIntent::Buy { output_mint, observed_input_sol: Some(sol), .. }]
+                if output_mint.to_string() == BONK && (sol - 0.25).abs() < 1e-9),
+            "{intents:?}"
+        );
+    }
+
+    #[test]
+    fn sell_fixtures_give_the_fraction_sold() {
+        for (name, mint, sold) in [("raydium_sell_closes_account", BONK, 1.0), ("raydium_sell_half", POPCAT, 0.5)] {
+            let intents = infer_intent_from_tx(&fixture(name), &fixture_target(), &config()).unwrap();
+            assert!(
+                matches!(intents.as_slice(), [MirrorIntent::Sell { input_mint, fraction }]
+                    if input_mint.to_string() == mint && *fraction == sold),
+                "{name}: {intents:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn transfer_fixture_closing_the_account_is_not_a_sell() {
+        // Closing the emptied account refunds its rent: SOL comes in, but no proceeds
+        let tx = fixture("token_transfer_closes_account");
+        assert!(target_sol_delta(&tx, &[fixture_target()]) > 0);
+        assert_eq!(target_sol_received(&tx, &[fixture_target()]), 0);
+        assert!(infer_intent_from_tx(&tx, &fixture_target(), &config()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn failed_swap_fixture_is_skipped_and_counted() {
+        let tx = fixture("jupiter_route_slippage_exceeded");
+        let meta = tx.meta.as_ref().unwrap();
+        assert_eq!(meta.err, Some(json!({ "InstructionError": [2, { "Custom": 6001 }] })));
+        assert!(!meta.pre_token_balances.is_empty());
+
+        let skipped = FAILED_TX_SKIPPED.load(Ordering::Relaxed);
+        assert!(infer_intent_from_tx(&tx, &fixture_target(), &config()).unwrap().is_empty());
+        assert_eq!(FAILED_TX_SKIPPED.load(Ordering::Relaxed), skipped + 1);
+    }
+
+    fn balance(account_index: u64, mint: &str, owner: &Pubkey, amount: u64, decimals: u8) -> TokenBalance {
+        TokenBalance {
+            account_index,
+            mint: mint.to_string(),
+            owner: Some(owner.to_string()),
+            program_id: Some(TOKEN_PROGRAM_ID.to_string()),
+            ui_token_amount: UiTokenAmount { amount: amount.to_string(), decimals, ui_amount: None },
+        }
+    }
+
+    /// A json-encoded notification signed by `keys[0]`. `lamports` holds the (pre, post)
+    /// SOL balance of the first keys; the fee is 5000 lamports.
+    fn notification(
+        keys: &[Pubkey],
+        instructions: serde_json::Value,
+        lamports: &[(u64, u64)],
+        pre_token_balances: Vec<TokenBalance>,
+        post_token_balances: Vec<TokenBalance>,
+    ) -> TransactionNotification {
+        let keys: Vec<String> = keys.iter().map(|k| k.to_string()).collect();
+        TransactionNotification {
+            signature: Some("sig".to_string()),
+            slot: Some(1),
+            transaction: json!({
+                "message": {
+                    "header": { "numRequiredSignatures": 1 },
+                    "accountKeys": keys,
+                    "instructions": instructions,
+                }
+            }),
+            meta: Some(TxMeta {
+                fee: 5000,
+                pre_balances: lamports.iter().map(|l| l.0).collect(),
+                post_balances: lamports.iter().map(|l| l.1).collect(),
+                pre_token_balances,
+                post_token_balances,
+                ..Default::default()
+            }),
+        }
+    }
+
+    #[test]
+    fn token_balances_sums_raw_amounts_of_the_owners_accounts() {
+        let (target, other) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let entries = vec![
+            balance(1, "ZeroDecimals", &target, 3, 0),
+            balance(2, "ZeroDecimals", &target, 4, 0),
+            balance(3, "ZeroDecimals", &other, 100, 0),
+            balance(4, "NineDecimals", &target, u64::MAX - 1, 9),
+        ];
+        let balances = token_balances(&entries, &[target]);
+        assert_eq!(balances["ZeroDecimals"].raw, 7);
+        assert_eq!(balances["ZeroDecimals"].decimals, 0);
+        assert_eq!(balances["NineDecimals"].raw, u64::MAX - 1);
+        assert_eq!(balances["NineDecimals"].decimals, 9);
+    }
+
+    #[test]
+    fn one_base_unit_of_a_zero_decimal_token_is_a_buy() {
+        let target = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        let tx = notification(
+            &[target],
+            json!([]),
+            &[(2_000_000_000, 999_995_000)],
+            vec![balance(1, &mint.to_string(), &target, 5, 0)],
+            vec![balance(1, &mint.to_string(), &target, 6, 0)],
+        );
+        let intents = infer_intent_from_tx(&tx, &target, &config()).unwrap();
+        assert!(matches!(intents.as_slice(), [MirrorIntent::Buy { output_mint, .. }] if *output_mint == mint));
+
+        let cfg = IntentConfig { dust: DustConfig { min_delta_raw: 2, ..Default::default() }, ..config() };
+        assert!(infer_intent_from_tx(&tx, &target, &cfg).unwrap().is_empty());
+    }
+
+    #[test]
+    fn nine_decimal_sell_delta_is_exact_beyond_float_precision() {
+        let target = Pubkey::new_unique();
+        let mint = Pubkey::new_unique();
+        // f64 steps are 2048 apart above 2^63, so float math cannot see this 1000-unit sell
+        let pre = 10_000_000_000_000_000_000;
+        let tx = notification(
+            &[target],
+            json!([]),
+            &[(1_000_000_000, 1_500_000_000)],
+            vec![balance(1, &mint.to_string(), &target, pre, 9)],
+            vec![balance(1, &mint.to_string(), &target, pre - 1000, 9)],
+        );
+        match infer_intent_from_tx(&tx, &target, &config()).unwrap().as_slice() {
+            [MirrorIntent::Sell { input_mint, fraction }] => {
+                assert_eq!(*input_mint, mint);
+                assert!(*fraction > 0.0 && *fraction < 1e-15, "{fraction}");
+            }
+            other => panic!("expected one sell, got {other:?}"),
+        }
+    }
+}
@@ -1,2 +1,15 @@
+pub mod airdrops;
+pub mod balance;
+pub mod budget;
+pub mod cooldown;
 pub mod copy_trader;
+pub mod exits;
+pub mod health;
+pub mod hooks;
 pub mod intent;
+pub mod positions;
+pub mod ramp;
+pub mod risk;
+pub mod rollback;
+pub mod seen;
+pub mod stats;
@@ -0,0 +1,523 @@
+use crate::common::lru::LruCache;
+use crate::common::notify::Notifier;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io};
+use tracing::{error, warn};
+
+/// What we hold of a single mint as a result of mirrored buys.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Position {
+    pub mint: String,
+    /// Total lamports spent on this mint across all recorded buys.
+    pub lamports_spent: u64,
+    /// Sum of Jupiter's quoted `outAmount` (raw token units) for those buys.
+    pub est_out_amount: u64,
+    /// Unix seconds the first buy was sent (not confirmed).
+    pub opened_at: u64,
+    /// Signatures of the buys that make up this position.
+    pub signatures: Vec<String>,
+    /// Target whose buy opened the position.
+    #[serde(default)]
+    pub target: Option<String>,
+    /// Found in the wallet at startup (`IMPORT_EXISTING_HOLDINGS`) rather than bought by us.
+    /// `lamports_spent` is then the value quoted at import, or 0 if it could not be priced.
+    /// `target` is `None`, so selling it books no per-target PnL.
+    #[serde(default)]
+    pub imported: bool,
+    /// Highest quoted SOL value (lamports) seen by the exit monitor, for the trailing stop.
+    #[serde(default)]
+    pub high_water_lamports: u64,
+    /// Raw token balance the exit ladder's rung percentages apply to, fixed the first
+    /// time the ladder sees the position (0 = not yet set).
+    #[serde(default)]
+    pub ladder_base_amount: u64,
+    /// Multiples of the exit ladder rungs that have already sold.
+    #[serde(default)]
+    pub ladder_fired: Vec<f64>,
+    /// `STRATEGY` the bot ran under when the position was opened, for per-strategy PnL.
+    #[serde(default)]
+    pub strategy: Option<String>,
+}
+
+/// One rung of an exit ladder: sell `sell_pct` percent of the original position once its
+/// quoted value reaches `multiple` times its cost.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LadderRung {
+    pub multiple: f64,
+    pub sell_pct: f64,
+}
+
+/// Partial take-profit ladder, parsed from e.g. `2x:50,3x:25,5x:25`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExitLadder {
+    /// Sorted by ascending multiple.
+    pub rungs: Vec<LadderRung>,
+}
+
+impl ExitLadder {
+    pub fn is_empty(&self) -> bool {
+        self.rungs.is_empty()
+    }
+
+    /// Rungs reached at `multiple` that are not in `fired` yet.
+    pub fn due_rungs(&self, multiple: f64, fired: &[f64]) -> Vec<LadderRung> {
+        self.rungs
+            .iter()
+            .filter(|r| multiple >= r.multiple && !fired.contains(&r.multiple))
+            .copied()
+            .collect()
+    }
+}
+
+impl FromStr for ExitLadder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut rungs = Vec::new();
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (multiple, pct) = part
+                .split_once(':')
+                .ok_or_else(|| anyhow!("Invalid exit ladder rung {part:?} (expected e.g. 2x:50)"))?;
+            let multiple: f64 = multiple
+                .trim()
+                .trim_end_matches(['x', 'X'])
+                .parse()
+                .map_err(|e| anyhow!("Invalid exit ladder multiple in {part:?}: {e}"))?;
+            let sell_pct: f64 = pct
+                .trim()
+                .trim_end_matches('%')
+                .parse()
+                .map_err(|e| anyhow!("Invalid exit ladder percent in {part:?}: {e}"))?;
+            if multiple <= 1.0 || !(0.0..=100.0).contains(&sell_pct) || sell_pct == 0.0 {
+                return Err(anyhow!("Exit ladder rung {part:?} needs a multiple above 1x and a percent in (0, 100]"));
+            }
+            rungs.push(LadderRung { multiple, sell_pct });
+        }
+        rungs.sort_by(|a, b| a.multiple.total_cmp(&b.multiple));
+        if rungs.windows(2).any(|w| w[0].multiple == w[1].multiple) {
+            return Err(anyhow!("Exit ladder has duplicate multiples"));
+        }
+        let total: f64 = rungs.iter().map(|r| r.sell_pct).sum();
+        if total > 100.0 + f64::EPSILON {
+            return Err(anyhow!("Exit ladder sells {total}% in total; must be at most 100%"));
+        }
+        Ok(Self { rungs })
+    }
+}
+
+/// Tracks open positions and persists them as JSON so they survive restarts.
+pub struct PositionTracker {
+    path: PathBuf,
+    positions: HashMap<String, Position>,
+    /// Mints with a sell in flight, mapped to the trigger holding the claim. Not persisted:
+    /// a claim lives only as long as the sell that took it.
+    exits_in_flight: HashMap<String, String>,
+    /// Realized PnL (lamports) of the last sell of each mint and when it settled (unix
+    /// seconds), for the loss cooldown. Not persisted; the last `OUTCOME_CACHE_SIZE` mints.
+    outcomes: LruCache<String, (i64, u64)>,
+    /// Tag for positions opened from now on.
+    strategy: Option<String>,
+    /// Why the last write of the positions file failed; cleared by the next one that works.
+    persist_failure: Option<String>,
+    /// `HALT_ON_PERSIST_FAIL`: pause mirroring while `persist_failure` is set.
+    halt_on_persist_fail: bool,
+    /// Alerted when writes start failing and when they work again.
+    notifier: Option<Arc<dyn Notifier>>,
+}
+
+impl PositionTracker {
+    /// Loads positions from `path`, starting empty if the file does not exist yet.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let positions = match fs::read_to_string(&path) {
+            Ok(raw) => serde_json::from_str(&raw)
+                .map_err(|e| anyhow!("Invalid positions file {}: {e}", path.display()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(anyhow!("Failed to read positions file {}: {e}", path.display())),
+        };
+        Ok(Self {
+            path,
+            positions,
+            exits_in_flight: HashMap::new(),
+            outcomes: LruCache::from_env("outcome", 10_000),
+            strategy: None,
+            persist_failure: None,
+            halt_on_persist_fail: false,
+            notifier: None,
+        })
+    }
+
+    /// What happens when the positions file cannot be written: always a loud error and an
+    /// alert through `notifier`; with `halt` (`HALT_ON_PERSIST_FAIL`) mirroring also pauses
+    /// (see `persist_halt`) until a write works again.
+    pub fn set_persist_failure_response(&mut self, notifier: Arc<dyn Notifier>, halt: bool) {
+        self.notifier = Some(notifier);
+        self.halt_on_persist_fail = halt;
+    }
+
+    /// Why mirroring must pause, if it must: the positions file cannot be written and
+    /// `HALT_ON_PERSIST_FAIL` is on, so risk state would live in memory only.
+    pub fn persist_halt(&self) -> Option<String> {
+        let failure = self.persist_failure.as_ref().filter(|_| self.halt_on_persist_fail)?;
+        Some(format!("positions cannot be persisted ({failure}); HALT_ON_PERSIST_FAIL"))
+    }
+
+    /// Sets the strategy tag recorded on positions opened or imported from now on.
+    pub fn set_strategy(&mut self, strategy: Option<String>) {
+        self.strategy = strategy;
+    }
+
+    /// Strategy the position in `mint` was opened under, if tagged.
+    pub fn strategy_of(&self, mint: &Pubkey) -> Option<String> {
+        self.get_position(mint).and_then(|p| p.strategy.clone())
+    }
+
+    /// Records a successful buy sent at `sent_at` (unix seconds). Returns `false` (and
+    /// changes nothing) if `signature` was already recorded, so a replayed notification
+    /// never double-counts.
+    pub fn open_position(
+        &mut self,
+        mint: &Pubkey,
+        target: &Pubkey,
+        lamports_spent: u64,
+        est_out_amount: u64,
+        signature: &str,
+        sent_at: u64,
+    ) -> Result<bool> {
+        if self
+            .positions
+            .values()
+            .any(|p| p.signatures.iter().any(|s| s == signature))
+        {
+            return Ok(false);
+        }
+
+        let strategy = self.strategy.clone();
+        let position = self
+            .positions
+            .entry(mint.to_string())
+            .or_insert_with(|| Position {
+                mint: mint.to_string(),
+                lamports_spent: 0,
+                est_out_amount: 0,
+                opened_at: sent_at,
+                signatures: Vec::new(),
+                target: Some(target.to_string()),
+                imported: false,
+                high_water_lamports: 0,
+                ladder_base_amount: 0,
+                ladder_fired: Vec::new(),
+                strategy,
+            });
+        position.lamports_spent = position.lamports_spent.saturating_add(lamports_spent);
+        position.est_out_amount = position.est_out_amount.saturating_add(est_out_amount);
+        position.signatures.push(signature.to_string());
+
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Takes back a buy recorded by [`Self::open_position`] whose tx never landed, closing
+    /// the position if it was the only buy in it. Returns `false` if `signature` is not
+    /// recorded for `mint`.
+    pub fn drop_buy(&mut self, mint: &Pubkey, signature: &str, lamports_spent: u64, est_out_amount: u64) -> Result<bool> {
+        let key = mint.to_string();
+        let Some(position) = self.positions.get_mut(&key) else {
+            return Ok(false);
+        };
+        let Some(idx) = position.signatures.iter().position(|s| s == signature) else {
+            return Ok(false);
+        };
+        position.signatures.remove(idx);
+        if position.signatures.is_empty() && !position.imported {
+            self.positions.remove(&key);
+        } else {
+            position.lamports_spent = position.lamports_spent.saturating_sub(lamports_spent);
+            position.est_out_amount = position.est_out_amount.saturating_sub(est_out_amount);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Adds a position for tokens already held before the bot started. Returns `false`
+    /// (and changes nothing) if `mint` is already tracked.
+    pub fn import_position(&mut self, mint: &Pubkey, est_cost_lamports: u64, amount: u64) -> Result<bool> {
+        if self.positions.contains_key(&mint.to_string()) {
+            return Ok(false);
+        }
+        self.positions.insert(
+            mint.to_string(),
+            Position {
+                mint: mint.to_string(),
+                lamports_spent: est_cost_lamports,
+                est_out_amount: amount,
+                opened_at: now_secs(),
+                signatures: Vec::new(),
+                target: None,
+                imported: true,
+                high_water_lamports: 0,
+                ladder_base_amount: 0,
+                ladder_fired: Vec::new(),
+                strategy: self.strategy.clone(),
+            },
+        );
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn get_position(&self, mint: &Pubkey) -> Option<&Position> {
+        self.positions.get(&mint.to_string())
+    }
+
+    /// Removes and returns the position for `mint`, if any.
+    pub fn close_position(&mut self, mint: &Pubkey) -> Result<Option<Position>> {
+        let closed = self.positions.remove(&mint.to_string());
+        if closed.is_some() {
+            self.save()?;
+        }
+        Ok(closed)
+    }
+
+    /// Releases `fraction` of the position's cost basis after a sell and returns the
+    /// lamports released, closing the position when `fraction >= 1.0`.
+    /// Returns `None` if we have no position in `mint`.
+    pub fn reduce_position(&mut self, mint: &Pubkey, fraction: f64) -> Result<Option<u64>> {
+        let key = mint.to_string();
+        let Some(position) = self.positions.get_mut(&key) else {
+            return Ok(None);
+        };
+
+        let released = if fraction >= 1.0 {
+            let lamports = position.lamports_spent;
+            self.positions.remove(&key);
+            lamports
+        } else {
+            let keep = 1.0 - fraction.max(0.0);
+            let lamports = position.lamports_spent - (position.lamports_spent as f64 * keep).round() as u64;
+            position.lamports_spent -= lamports;
+            position.est_out_amount = (position.est_out_amount as f64 * keep).round() as u64;
+            position.high_water_lamports = (position.high_water_lamports as f64 * keep).round() as u64;
+            lamports
+        };
+
+        self.save()?;
+        Ok(Some(released))
+    }
+
+    /// Raises the position's high-water mark to `quoted_lamports` if it is higher (the
+    /// mark never starts below the cost basis) and returns the resulting mark.
+    /// Returns `None` if we have no position in `mint`.
+    pub fn update_high_water(&mut self, mint: &Pubkey, quoted_lamports: u64) -> Result<Option<u64>> {
+        let Some(position) = self.positions.get_mut(&mint.to_string()) else {
+            return Ok(None);
+        };
+        let peak = position
+            .high_water_lamports
+            .max(position.lamports_spent)
+            .max(quoted_lamports);
+        if peak != position.high_water_lamports {
+            position.high_water_lamports = peak;
+            self.save()?;
+        }
+        Ok(Some(peak))
+    }
+
+    /// Fixes the exit ladder's base amount for `mint` (if not set yet) and returns it
+    /// along with the rungs already fired.
+    pub fn ladder_state(&mut self, mint: &Pubkey, current_amount: u64) -> Result<Option<(u64, Vec<f64>)>> {
+        let Some(position) = self.positions.get_mut(&mint.to_string()) else {
+            return Ok(None);
+        };
+        let state = if position.ladder_base_amount == 0 {
+            position.ladder_base_amount = current_amount;
+            let state = (current_amount, position.ladder_fired.clone());
+            self.save()?;
+            state
+        } else {
+            (position.ladder_base_amount, position.ladder_fired.clone())
+        };
+        Ok(Some(state))
+    }
+
+    /// Marks exit ladder rungs (by multiple) as sold for `mint`.
+    pub fn mark_rungs_fired(&mut self, mint: &Pubkey, multiples: &[f64]) -> Result<()> {
+        let Some(position) = self.positions.get_mut(&mint.to_string()) else {
+            return Ok(());
+        };
+        position.ladder_fired.extend_from_slice(multiples);
+        self.save()
+    }
+
+    /// Claims `mint` for a sell by `trigger`. Returns the trigger already holding it
+    /// instead if another sell is in flight, so two exit paths never sell the same
+    /// position at once. Release with [`Self::release_exit`] once the sell is settled.
+    pub fn claim_exit(&mut self, mint: &Pubkey, trigger: &str) -> Result<(), String> {
+        match self.exits_in_flight.get(&mint.to_string()) {
+            Some(holder) => Err(holder.clone()),
+            None => {
+                self.exits_in_flight.insert(mint.to_string(), trigger.to_string());
+                Ok(())
+            }
+        }
+    }
+
+    pub fn release_exit(&mut self, mint: &Pubkey) {
+        self.exits_in_flight.remove(&mint.to_string());
+    }
+
+    /// Trigger of the sell currently in flight for `mint`, if any.
+    pub fn exit_in_flight(&self, mint: &Pubkey) -> Option<&str> {
+        self.exits_in_flight.get(&mint.to_string()).map(String::as_str)
+    }
+
+    /// Records the realized PnL of a settled sell of `mint`.
+    pub fn record_outcome(&mut self, mint: &Pubkey, pnl_lamports: i64) {
+        self.outcomes.insert(mint.to_string(), (pnl_lamports, now_secs()));
+    }
+
+    /// PnL and settle time (unix seconds) of the last sell of `mint`, if any.
+    pub fn last_outcome(&mut self, mint: &Pubkey) -> Option<(i64, u64)> {
+        self.outcomes.get(&mint.to_string()).copied()
+    }
+
+    pub fn all_positions(&self) -> Vec<&Position> {
+        self.positions.values().collect()
+    }
+
+    /// Writes the positions, reporting the first failure of a run of them and the write
+    /// that ends it.
+    fn save(&mut self) -> Result<()> {
+        match self.write() {
+            Ok(()) => {
+                if self.persist_failure.take().is_some() {
+                    warn!("Positions file {} is written again", self.path.display());
+                    self.alert("Positions are persisted again");
+                }
+                Ok(())
+            }
+            Err(e) => {
+                if self.persist_failure.is_none() {
+                    let response = if self.halt_on_persist_fail { "mirroring paused" } else { "trading continues" };
+                    error!("!!! POSITIONS NOT PERSISTED: {e}. Risk state is in memory only; {response} !!!");
+                    self.alert(&format!("Positions cannot be persisted: {e}. {response}"));
+                }
+                self.persist_failure = Some(e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    fn alert(&self, text: &str) {
+        if let Some(notifier) = &self.notifier {
+            notifier.notify(text);
+        }
+    }
+
+    /// Writes to a temp file and renames it over the old one so a crash mid-write
+    /// never leaves a truncated positions file behind.
+    fn write(&self) -> Result<()> {
+        let tmp = self.path.with_extension("json.tmp");
+        let raw = serde_json::to_string_pretty(&self.positions)?;
+        fs::write(&tmp, raw)
+            .and_then(|_| fs::rename(&tmp, &self.path))
+            .map_err(|e| anyhow!("Failed to write positions file {}: {e}", self.path.display()))
+    }
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(name: &str) -> (PositionTracker, PathBuf) {
+        let path = std::env::temp_dir().join(format!("ammalgam-positions-{name}-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        (PositionTracker::load(&path).unwrap(), path)
+    }
+
+    #[test]
+    fn high_water_mark_only_rises() {
+        let (mut positions, path) = tracker("high-water");
+        let mint = Pubkey::new_unique();
+        assert_eq!(positions.update_high_water(&mint, 1).unwrap(), None);
+        positions.open_position(&mint, &Pubkey::new_unique(), 1_000, 10, "buy", 1).unwrap();
+
+        // Starts at the cost basis, not below it
+        let marks: Vec<u64> = [800, 1_200, 1_100, 1_500, 900]
+            .into_iter()
+            .map(|value| positions.update_high_water(&mint, value).unwrap().unwrap())
+            .collect();
+        assert_eq!(marks, [1_000, 1_200, 1_200, 1_500, 1_500]);
+
+        // Persisted across restarts, and scaled down with a partial sell
+        let mut reloaded = PositionTracker::load(&path).unwrap();
+        assert_eq!(reloaded.get_position(&mint).unwrap().high_water_lamports, 1_500);
+        reloaded.reduce_position(&mint, 0.5).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(reloaded.get_position(&mint).unwrap().high_water_lamports, 750);
+    }
+
+    #[test]
+    fn imported_position_has_no_target_and_outlives_dropped_buys() {
+        let (mut positions, path) = tracker("import");
+        let mint = Pubkey::new_unique();
+        assert!(positions.import_position(&mint, 0, 900).unwrap());
+        // Already tracked: a second import leaves it alone
+        assert!(!positions.import_position(&mint, 5_000, 1).unwrap());
+
+        // A timed-out buy on top of it is taken back without closing the holding
+        positions.open_position(&mint, &Pubkey::new_unique(), 1_000, 10, "timed-out", 1).unwrap();
+        assert!(positions.drop_buy(&mint, "timed-out", 1_000, 10).unwrap());
+        let _ = fs::remove_file(&path);
+        let position = positions.get_position(&mint).unwrap();
+        assert!(position.imported && position.target.is_none());
+        assert_eq!((position.lamports_spent, position.est_out_amount), (0, 900));
+    }
+
+    #[derive(Default)]
+    struct Alerts(std::sync::Mutex<Vec<String>>);
+
+    impl Notifier for Alerts {
+        fn notify(&self, text: &str) {
+            self.0.lock().unwrap().push(text.to_string());
+        }
+    }
+
+    #[test]
+    fn failed_write_alerts_and_pauses_with_halt_on_persist_fail() {
+        for halt in [false, true] {
+            let (mut positions, path) = tracker(&format!("persist-{halt}"));
+            let alerts = Arc::new(Alerts::default());
+            positions.set_persist_failure_response(alerts.clone(), halt);
+            let mint = Pubkey::new_unique();
+
+            // The temp file is written next to the positions file; a directory there fails it
+            let tmp = path.with_extension("json.tmp");
+            fs::create_dir_all(&tmp).unwrap();
+            assert!(positions.open_position(&mint, &Pubkey::new_unique(), 1_000, 10, "buy", 1).is_err());
+            assert!(positions.reduce_position(&mint, 0.5).is_err());
+            assert_eq!(positions.persist_halt().is_some(), halt);
+            assert_eq!(alerts.0.lock().unwrap().len(), 1, "one alert per run of failures");
+            assert!(alerts.0.lock().unwrap()[0].contains("cannot be persisted"));
+
+            fs::remove_dir(&tmp).unwrap();
+            positions.reduce_position(&mint, 0.5).unwrap();
+            let _ = fs::remove_file(&path);
+            assert_eq!(positions.persist_halt(), None);
+            assert_eq!(alerts.0.lock().unwrap().last().unwrap(), "Positions are persisted again");
+        }
+    }
+}
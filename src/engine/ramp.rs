@@ -0,0 +1,72 @@
+use crate::common::utils::{env_f64, env_u64};
+use tokio::time::{Duration, Instant};
+
+/// Soft launch: buy sizes start at `RAMP_START_PCT` of `MAX_BUY_SOL` and grow linearly
+/// to the full amount over `RAMP_DURATION_SECS` since startup. Targets are only read at
+/// startup, so a newly added target or config starts its ramp with the restart.
+#[derive(Debug, Clone)]
+pub struct SizeRamp {
+    /// Fraction of the max buy allowed at startup (0..1).
+    start: f64,
+    duration: Duration,
+    started: Instant,
+}
+
+impl SizeRamp {
+    /// `None` when no ramp is configured (`RAMP_DURATION_SECS=0`).
+    pub fn from_env() -> Option<Self> {
+        let duration = env_u64("RAMP_DURATION_SECS", 0);
+        if duration == 0 {
+            return None;
+        }
+        Some(Self {
+            start: (env_f64("RAMP_START_PCT", 10.0) / 100.0).clamp(0.0, 1.0),
+            duration: Duration::from_secs(duration),
+            started: Instant::now(),
+        })
+    }
+
+    /// Share of the max buy currently allowed, from `start` up to 1.
+    pub fn factor(&self) -> f64 {
+        let progress = (self.started.elapsed().as_secs_f64() / self.duration.as_secs_f64()).min(1.0);
+        self.start + (1.0 - self.start) * progress
+    }
+
+    /// Largest buy (lamports) allowed right now, given the configured max.
+    pub fn cap_lamports(&self, max_lamports: u64) -> u64 {
+        (max_lamports as f64 * self.factor()).floor() as u64
+    }
+
+    pub fn start_pct(&self) -> f64 {
+        self.start * 100.0
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ramp_started(ago: Duration) -> SizeRamp {
+        SizeRamp {
+            start: 0.1,
+            duration: Duration::from_secs(100),
+            started: Instant::now().checked_sub(ago).unwrap(),
+        }
+    }
+
+    #[test]
+    fn max_buy_grows_from_start_pct_to_full() {
+        let max = 1_000_000_000;
+        let caps: Vec<u64> = [0, 50, 100, 500]
+            .into_iter()
+            .map(|secs| ramp_started(Duration::from_secs(secs)).cap_lamports(max))
+            .collect();
+        assert!((100_000_000..101_000_000).contains(&caps[0]), "{caps:?}");
+        assert!((550_000_000..551_000_000).contains(&caps[1]), "{caps:?}");
+        assert_eq!(&caps[2..], [max, max]);
+    }
+}
@@ -0,0 +1,88 @@
+use crate::common::notify::Notifier;
+use crate::engine::budget::SpendBudget;
+use std::sync::{Arc, Mutex};
+use tokio::time::{Duration, Instant};
+use tracing::warn;
+
+/// Circuit breakers checked before every mirrored buy: the rolling 24h spend cap
+/// (`MAX_DAILY_SOL`) and a pause of `BREAKER_COOLDOWN_SECS` after
+/// `MAX_CONSECUTIVE_FAILURES` failed sends in a row. Halting and resuming are logged
+/// and notified.
+pub struct RiskGuard {
+    budget: Option<Arc<Mutex<SpendBudget>>>,
+    /// 0 = failure breaker off.
+    max_failures: u64,
+    cooldown: Duration,
+    failures: u64,
+    tripped_until: Option<Instant>,
+    halted: bool,
+    notifier: Arc<dyn Notifier>,
+}
+
+impl RiskGuard {
+    pub fn new(budget: Option<Arc<Mutex<SpendBudget>>>, notifier: Arc<dyn Notifier>, max_failures: u64, cooldown: Duration) -> Self {
+        Self {
+            budget,
+            max_failures,
+            cooldown,
+            failures: 0,
+            tripped_until: None,
+            halted: false,
+            notifier,
+        }
+    }
+
+    pub fn max_failures(&self) -> u64 {
+        self.max_failures
+    }
+
+    pub fn cooldown(&self) -> Duration {
+        self.cooldown
+    }
+
+    /// Why buys are halted right now, if they are. The failure breaker resets once its
+    /// cooldown is over; the spend cap once the 24h window has rolled.
+    pub fn check(&mut self) -> Option<String> {
+        if self.tripped_until.is_some_and(|until| Instant::now() >= until) {
+            self.tripped_until = None;
+            self.failures = 0;
+        }
+        let reason = if let Some(until) = self.tripped_until {
+            Some(format!(
+                "{} consecutive send failures; breaker open for another {}s",
+                self.max_failures,
+                until.saturating_duration_since(Instant::now()).as_secs()
+            ))
+        } else {
+            self.budget.as_ref().and_then(|budget| {
+                let available = budget.lock().unwrap_or_else(|e| e.into_inner()).available_for_buy();
+                (available == 0).then(|| "MAX_DAILY_SOL reached".to_string())
+            })
+        };
+
+        let halted = reason.is_some();
+        if halted != self.halted {
+            self.halted = halted;
+            let text = match &reason {
+                Some(reason) => format!("Mirroring halted: {reason}"),
+                None => "Mirroring resumed".to_string(),
+            };
+            warn!("{text}");
+            self.notifier.notify(&text);
+        }
+        reason
+    }
+
+    /// A send went through; the failure streak is over.
+    pub fn record_success(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Counts a failed send and opens the breaker once the streak reaches the limit.
+    pub fn record_failure(&mut self) {
+        self.failures += 1;
+        if self.max_failures > 0 && self.failures >= self.max_failures && self.tripped_until.is_none() {
+            self.tripped_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use crate::common::utils::{env_bool, env_f64, env_u64};
+use anyhow::Result;
+use solana_client::nonblocking::rpc_client::RpcClient as AsyncRpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::signature::Signature;
+use std::collections::VecDeque;
+use tokio::time::{sleep, Duration, Instant};
+use tracing::warn;
+
+/// Tracks how often `processed` notifications never reach `confirmed`, from a sample
+/// of the stream. Above `MAX_ROLLBACK_RATE` intents are only mirrored once the target's
+/// transaction is confirmed; mirroring goes back to `processed` once the rate falls
+/// to half the limit.
+#[derive(Debug)]
+pub struct RollbackMonitor {
+    /// Check every Nth notification (`ROLLBACK_SAMPLE_EVERY`).
+    sample_every: u64,
+    /// Rolled-back fraction that switches to confirmed-before-mirror (0..1).
+    max_rate: f64,
+    /// No switching until this many samples are in the window.
+    min_samples: usize,
+    window_size: usize,
+    /// `true` = that sample was rolled back.
+    window: VecDeque<bool>,
+    notifications: u64,
+    confirm_first: bool,
+    /// How long a sampled signature gets to reach `confirmed`.
+    pub check_timeout: Duration,
+}
+
+impl RollbackMonitor {
+    /// `None` when sampling is off (`ROLLBACK_SAMPLE_EVERY=0`).
+    pub fn from_env() -> Option<Self> {
+        let sample_every = env_u64("ROLLBACK_SAMPLE_EVERY", 20);
+        if sample_every == 0 {
+            return None;
+        }
+        let window_size = env_u64("ROLLBACK_WINDOW", 100).max(1) as usize;
+        Some(Self {
+            sample_every,
+            max_rate: env_f64("MAX_ROLLBACK_RATE", 0.05).clamp(0.0, 1.0),
+            min_samples: (env_u64("ROLLBACK_MIN_SAMPLES", 20) as usize).min(window_size),
+            window_size,
+            window: VecDeque::new(),
+            notifications: 0,
+            confirm_first: env_bool("CONFIRM_BEFORE_MIRROR", false),
+            check_timeout: Duration::from_secs(env_u64("ROLLBACK_CHECK_SECS", 60).max(1)),
+        })
+    }
+
+    pub fn max_rate(&self) -> f64 {
+        self.max_rate
+    }
+
+    /// Whether intents currently wait for the target's transaction to confirm.
+    pub fn confirm_first(&self) -> bool {
+        self.confirm_first
+    }
+
+    /// Counts a notification; `true` when it should be checked.
+    pub fn should_sample(&mut self) -> bool {
+        self.notifications += 1;
+        self.notifications.is_multiple_of(self.sample_every)
+    }
+
+    /// Rolled-back fraction of the samples in the window, `None` before any sample.
+    pub fn rate(&self) -> Option<f64> {
+        if self.window.is_empty() {
+            return None;
+        }
+        let rolled_back = self.window.iter().filter(|r| **r).count();
+        Some(rolled_back as f64 / self.window.len() as f64)
+    }
+
+    pub fn samples(&self) -> usize {
+        self.window.len()
+    }
+
+    /// Records one sample's outcome and re-evaluates the mode. Logs when it switches.
+    pub fn record(&mut self, rolled_back: bool) {
+        self.window.push_back(rolled_back);
+        while self.window.len() > self.window_size {
+            self.window.pop_front();
+        }
+        if self.window.len() < self.min_samples {
+            return;
+        }
+        let Some(rate) = self.rate() else {
+            return;
+        };
+        if !self.confirm_first && rate > self.max_rate {
+            self.confirm_first = true;
+            warn!(
+                "Processed rollback rate {:.1}% over {} samples exceeds MAX_ROLLBACK_RATE {:.1}%; mirroring only confirmed transactions",
+                rate * 100.0,
+                self.window.len(),
+                self.max_rate * 100.0
+            );
+        } else if self.confirm_first && rate <= self.max_rate / 2.0 {
+            self.confirm_first = false;
+            warn!(
+                "Processed rollback rate down to {:.1}% over {} samples; mirroring processed transactions again",
+                rate * 100.0,
+                self.window.len()
+            );
+        }
+    }
+}
+
+/// Waits up to `timeout` for `sig` to reach `confirmed`. `false` means it never did,
+/// i.e. the processed notification was rolled back (or dropped). A transaction that
+/// landed with an error still counts as confirmed.
+pub async fn wait_confirmed(rpc: &AsyncRpcClient, sig: &Signature, timeout: Duration) -> Result<bool> {
+    let start = Instant::now();
+    loop {
+        let statuses = rpc.get_signature_statuses(&[*sig]).await?;
+        if let Some(status) = statuses.value.into_iter().next().flatten() {
+            if status.satisfies_commitment(CommitmentConfig::confirmed()) {
+                return Ok(true);
+            }
+        }
+        if start.elapsed() > timeout {
+            return Ok(false);
+        }
+        sleep(Duration::from_millis(500)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(max_rate: f64, min_samples: usize, window_size: usize) -> RollbackMonitor {
+        RollbackMonitor {
+            sample_every: 1,
+            max_rate,
+            min_samples,
+            window_size,
+            window: VecDeque::new(),
+            notifications: 0,
+            confirm_first: false,
+            check_timeout: Duration::from_secs(1),
+        }
+    }
+
+    #[test]
+    fn rate_is_the_rolled_back_share_of_the_window() {
+        let mut m = monitor(1.0, 0, 4);
+        assert_eq!(m.rate(), None);
+        for rolled_back in [true, false, false, false] {
+            m.record(rolled_back);
+        }
+        assert_eq!(m.rate(), Some(0.25));
+        // The window only keeps the newest samples
+        m.record(false);
+        assert_eq!((m.rate(), m.samples()), (Some(0.0), 4));
+    }
+
+    #[test]
+    fn no_switch_before_min_samples() {
+        let mut m = monitor(0.1, 5, 10);
+        for _ in 0..4 {
+            m.record(true);
+        }
+        assert!(!m.confirm_first());
+        m.record(true);
+        assert!(m.confirm_first());
+    }
+
+    #[test]
+    fn switches_to_confirmed_above_the_limit_and_back_at_half() {
+        let mut m = monitor(0.2, 10, 10);
+        // 2 of 10 rolled back is at the limit, not above it
+        for i in 0..10 {
+            m.record(i >= 8);
+        }
+        assert!(!m.confirm_first());
+        m.record(true);
+        assert_eq!(m.rate(), Some(0.3));
+        assert!(m.confirm_first());
+
+        // Stays on until the rate is down to half the limit
+        for _ in 0..8 {
+            m.record(false);
+        }
+        assert_eq!(m.rate(), Some(0.2));
+        assert!(m.confirm_first());
+        m.record(false);
+        assert_eq!(m.rate(), Some(0.1));
+        assert!(!m.confirm_first());
+    }
+
+    #[test]
+    fn samples_every_nth_notification() {
+        let mut m = monitor(0.1, 1, 1);
+        m.sample_every = 3;
+        let sampled: Vec<bool> = (0..6).map(|_| m.should_sample()).collect();
+        assert_eq!(sampled, [false, false, true, false, false, true]);
+    }
+}
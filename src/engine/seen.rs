@@ -0,0 +1,78 @@
+use anyhow::{anyhow, Result};
+use std::collections::{HashSet, VecDeque};
+use std::path::PathBuf;
+use std::{fs, io};
+
+/// The last `max` target signatures we acted on, persisted so a WS replay after a
+/// restart is not mirrored twice. Oldest signatures are evicted first.
+#[derive(Debug)]
+pub struct SeenSignatures {
+    path: PathBuf,
+    max: usize,
+    order: VecDeque<String>,
+    set: HashSet<String>,
+}
+
+impl SeenSignatures {
+    pub fn load(path: impl Into<PathBuf>, max: usize) -> Result<Self> {
+        let path = path.into();
+        let order: VecDeque<String> = match fs::read_to_string(&path) {
+            Ok(raw) => serde_json::from_str(&raw)
+                .map_err(|e| anyhow!("Invalid state file {}: {e}", path.display()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => return Err(anyhow!("Failed to read state file {}: {e}", path.display())),
+        };
+
+        let mut seen = Self {
+            path,
+            max: max.max(1),
+            order: VecDeque::new(),
+            set: HashSet::new(),
+        };
+        for sig in order {
+            seen.push(sig);
+        }
+        Ok(seen)
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    pub fn contains(&self, signature: &str) -> bool {
+        self.set.contains(signature)
+    }
+
+    /// Records `signature` and persists the set. Already-seen signatures are a no-op.
+    pub fn insert(&mut self, signature: &str) -> Result<()> {
+        if self.contains(signature) {
+            return Ok(());
+        }
+        self.push(signature.to_string());
+        self.save()
+    }
+
+    fn push(&mut self, signature: String) {
+        if !self.set.insert(signature.clone()) {
+            return;
+        }
+        self.order.push_back(signature);
+        while self.order.len() > self.max {
+            if let Some(old) = self.order.pop_front() {
+                self.set.remove(&old);
+            }
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let tmp = self.path.with_extension("json.tmp");
+        let raw = serde_json::to_string(&self.order)?;
+        fs::write(&tmp, raw)
+            .and_then(|_| fs::rename(&tmp, &self.path))
+            .map_err(|e| anyhow!("Failed to write state file {}: {e}", self.path.display()))
+    }
+}
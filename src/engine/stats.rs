@@ -0,0 +1,179 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::{fs, io};
+use tracing::error;
+
+/// Realized results of one strategy's positions from a target.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StrategyStats {
+    pub realized_pnl_lamports: i64,
+    pub closed_trades: u64,
+}
+
+/// Running results of mirroring one target wallet.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TargetStats {
+    /// Realized PnL in lamports (sell proceeds minus the cost basis they released).
+    pub realized_pnl_lamports: i64,
+    pub closed_trades: u64,
+    /// Fully closed positions, counted by the trigger that closed them.
+    #[serde(default)]
+    pub exits_by_trigger: BTreeMap<String, u64>,
+    /// The same realized results split by the `STRATEGY` the positions were opened under.
+    #[serde(default)]
+    pub by_strategy: BTreeMap<String, StrategyStats>,
+    /// Set once drawdown exceeds the limit. Stays set until cleared by hand in the stats file.
+    pub disabled: bool,
+}
+
+/// Per-target stats, persisted as JSON next to the positions file.
+#[derive(Debug)]
+pub struct StatsBook {
+    path: PathBuf,
+    /// Drawdown (lamports) after which a target is disabled; 0 = never.
+    max_drawdown_lamports: u64,
+    targets: HashMap<String, TargetStats>,
+}
+
+impl StatsBook {
+    pub fn load(path: impl Into<PathBuf>, max_drawdown_lamports: u64) -> Result<Self> {
+        let path = path.into();
+        let targets = match fs::read_to_string(&path) {
+            Ok(raw) => serde_json::from_str(&raw)
+                .map_err(|e| anyhow!("Invalid stats file {}: {e}", path.display()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(anyhow!("Failed to read stats file {}: {e}", path.display())),
+        };
+        Ok(Self {
+            path,
+            max_drawdown_lamports,
+            targets,
+        })
+    }
+
+    pub fn is_disabled(&self, target: &Pubkey) -> bool {
+        self.targets
+            .get(&target.to_string())
+            .is_some_and(|t| t.disabled)
+    }
+
+    pub fn get(&self, target: &Pubkey) -> Option<&TargetStats> {
+        self.targets.get(&target.to_string())
+    }
+
+    /// Realized PnL (lamports) and closed trades summed over every target.
+    pub fn total_realized(&self) -> (i64, u64) {
+        self.targets.values().fold((0, 0), |(pnl, trades), t| {
+            (pnl + t.realized_pnl_lamports, trades + t.closed_trades)
+        })
+    }
+
+    /// Books a realized result for `target` (and `strategy`, when the position was
+    /// tagged) and disables the target if its cumulative losses now exceed the
+    /// configured drawdown.
+    pub fn record_realized(&mut self, target: &Pubkey, strategy: Option<&str>, pnl_lamports: i64) -> Result<()> {
+        let stats = self.targets.entry(target.to_string()).or_default();
+        stats.realized_pnl_lamports = stats.realized_pnl_lamports.saturating_add(pnl_lamports);
+        stats.closed_trades += 1;
+        if let Some(strategy) = strategy {
+            let by_strategy = stats.by_strategy.entry(strategy.to_string()).or_default();
+            by_strategy.realized_pnl_lamports = by_strategy.realized_pnl_lamports.saturating_add(pnl_lamports);
+            by_strategy.closed_trades += 1;
+        }
+
+        if self.max_drawdown_lamports > 0
+            && !stats.disabled
+            && stats.realized_pnl_lamports <= -(self.max_drawdown_lamports as i64)
+        {
+            stats.disabled = true;
+            error!(
+                "ALERT: target {target} disabled: realized PnL {} lamports exceeds max drawdown of {} lamports. \
+                 Clear `disabled` in {} to re-enable.",
+                stats.realized_pnl_lamports,
+                self.max_drawdown_lamports,
+                self.path.display()
+            );
+        }
+
+        self.save()
+    }
+
+    /// Counts a position opened from `target` as closed by `trigger`.
+    pub fn record_exit(&mut self, target: &Pubkey, trigger: &str) -> Result<()> {
+        let stats = self.targets.entry(target.to_string()).or_default();
+        *stats.exits_by_trigger.entry(trigger.to_string()).or_default() += 1;
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        let tmp = self.path.with_extension("json.tmp");
+        let raw = serde_json::to_string_pretty(&self.targets)?;
+        fs::write(&tmp, raw)
+            .and_then(|_| fs::rename(&tmp, &self.path))
+            .map_err(|e| anyhow!("Failed to write stats file {}: {e}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(name: &str, max_drawdown_lamports: u64) -> (StatsBook, PathBuf) {
+        let path = std::env::temp_dir().join(format!("ammalgam-stats-{name}-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        (StatsBook::load(&path, max_drawdown_lamports).unwrap(), path)
+    }
+
+    #[test]
+    fn only_the_target_past_its_drawdown_is_disabled() {
+        let (mut stats, path) = book("drawdown", 1_000);
+        let (sour, fine) = (Pubkey::new_unique(), Pubkey::new_unique());
+        stats.record_realized(&sour, None, -600).unwrap();
+        stats.record_realized(&fine, None, -900).unwrap();
+        assert!(!stats.is_disabled(&sour));
+
+        stats.record_realized(&sour, None, -400).unwrap();
+        assert!(stats.is_disabled(&sour));
+        assert!(!stats.is_disabled(&fine));
+
+        // The flag is persisted and survives later wins
+        stats.record_realized(&sour, None, 5_000).unwrap();
+        let reloaded = StatsBook::load(&path, 1_000).unwrap();
+        let _ = fs::remove_file(&path);
+        assert!(reloaded.is_disabled(&sour));
+        assert!(!reloaded.is_disabled(&fine));
+    }
+
+    #[test]
+    fn zero_drawdown_never_disables() {
+        let (mut stats, path) = book("no-drawdown", 0);
+        let target = Pubkey::new_unique();
+        stats.record_realized(&target, None, -1_000_000_000_000).unwrap();
+        let _ = fs::remove_file(&path);
+        assert!(!stats.is_disabled(&target));
+        assert_eq!(stats.get(&target).unwrap().realized_pnl_lamports, -1_000_000_000_000);
+    }
+
+    #[test]
+    fn realized_results_are_split_by_strategy() {
+        let (mut stats, path) = book("strategy", 0);
+        let target = Pubkey::new_unique();
+        stats.record_realized(&target, Some("scalp"), 300).unwrap();
+        stats.record_realized(&target, Some("swing"), -100).unwrap();
+        stats.record_realized(&target, Some("scalp"), 200).unwrap();
+        // Positions opened before STRATEGY was set only count towards the target
+        stats.record_realized(&target, None, 50).unwrap();
+        let _ = fs::remove_file(&path);
+
+        let target = stats.get(&target).unwrap();
+        assert_eq!((target.realized_pnl_lamports, target.closed_trades), (450, 4));
+        let scalp = &target.by_strategy["scalp"];
+        assert_eq!((scalp.realized_pnl_lamports, scalp.closed_trades), (500, 2));
+        let swing = &target.by_strategy["swing"];
+        assert_eq!((swing.realized_pnl_lamports, swing.closed_trades), (-100, 1));
+        assert_eq!(target.by_strategy.len(), 2);
+    }
+}
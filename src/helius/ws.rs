@@ -1,22 +1,36 @@
+use crate::common::error::{BotError, BotResult};
+use crate::common::proxy::{connect_tcp, ProxyConfig};
+use crate::common::utils::{env_u64, env_var_opt};
+use crate::types::events::TransactionNotification;
 use anyhow::{anyhow, Result};
+use futures_util::stream::{self, BoxStream};
 use futures_util::{SinkExt, StreamExt};
 use serde_json::json;
-use tokio::time::{sleep, Duration};
-use tokio_tungstenite::{connect_async, tungstenite::Message};
-use tracing::{debug, error, info};
+use solana_sdk::pubkey::Pubkey;
+use std::collections::HashMap;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::{interval, sleep, sleep_until, timeout_at, Duration, Instant, MissedTickBehavior};
+use tokio_tungstenite::{client_async_tls, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tracing::{debug, error, info, warn};
 use url::Url;
 
-/// Connects to Helius WS endpoint and subscribes to transactions mentioning `target_pubkey`
-/// using `transactionSubscribe` with `mentions`.
+/// Connects to Helius WS endpoint and subscribes to transactions mentioning any of
+/// `target_pubkeys` using `transactionSubscribe` with `mentions`.
 ///
-/// Yields raw JSON messages (as serde_json::Value).
+/// The TCP connection goes through `proxy` when one is configured.
+///
+/// Yields the transaction notifications; other messages (the subscription ack) are
+/// dropped. The stream owns the write half to answer server pings and send its own every
+/// third of `idle_timeout`; it ends on close, error, or when nothing at all arrives for
+/// that long, so a silently dropped connection is noticed.
 pub async fn stream_transactions(
     ws_endpoint: &str,
-    target_pubkey: &str,
-) -> Result<impl futures_util::Stream<Item = serde_json::Value>> {
-    let url = Url::parse(ws_endpoint)?;
-    let (ws_stream, _) = connect_async(url).await?;
-    let (mut write, read) = ws_stream.split();
+    target_pubkeys: &[String],
+    proxy: Option<&ProxyConfig>,
+    idle_timeout: Duration,
+) -> BotResult<BoxStream<'static, TransactionNotification>> {
+    let (mut write, read) = connect(ws_endpoint, proxy).await?.split();
 
     // Helius supports standard Solana WS methods; we use transactionSubscribe.
     // Using "processed" for low latency.
@@ -25,7 +39,7 @@ pub async fn stream_transactions(
         "id": 1,
         "method": "transactionSubscribe",
         "params": [
-            { "mentions": [target_pubkey] },
+            { "mentions": target_pubkeys },
             {
               "commitment": "processed",
               "encoding": "base64",
@@ -36,55 +50,503 @@ pub async fn stream_transactions(
         ]
     });
 
-    write.send(Message::Text(sub.to_string())).await?;
-    info!("Subscribed to Helius WS transaction stream for TARGET_PUBKEY={target_pubkey}");
-
-    // Convert tungstenite messages -> JSON Values
-    let stream = read.filter_map(|msg| async move {
-        match msg {
-            Ok(Message::Text(t)) => match serde_json::from_str::<serde_json::Value>(&t) {
-                Ok(v) => Some(v),
-                Err(e) => {
-                    debug!("Non-json text msg: {e}");
-                    None
-                }
-            },
-            Ok(Message::Binary(b)) => {
-                // Sometimes servers send binary; try parse as utf8 json.
-                match String::from_utf8(b) {
-                    Ok(s) => serde_json::from_str::<serde_json::Value>(&s).ok(),
-                    Err(_) => None,
+    write
+        .send(Message::Text(sub.to_string()))
+        .await
+        .map_err(|e| BotError::WsDisconnected(format!("subscribe failed: {e}")))?;
+    info!("Subscribed to Helius WS transaction stream for targets: {}", target_pubkeys.join(", "));
+
+    let idle_timeout = idle_timeout.max(Duration::from_secs(3));
+    let mut ping = interval(idle_timeout / 3);
+    ping.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    ping.reset();
+
+    // Convert tungstenite messages -> notifications, keeping the connection alive in between
+    let stream = stream::unfold(
+        (write, read, ping, Instant::now()),
+        move |(mut write, mut read, mut ping, mut last_seen)| async move {
+            loop {
+                tokio::select! {
+                    _ = sleep_until(last_seen + idle_timeout) => {
+                        warn!("No WS message for {}s; dropping the connection", idle_timeout.as_secs());
+                        return None;
+                    }
+                    _ = ping.tick() => {
+                        if let Err(e) = write.send(Message::Ping(Vec::new())).await {
+                            error!("WS ping failed: {e}");
+                            return None;
+                        }
+                    }
+                    msg = read.next() => {
+                        last_seen = Instant::now();
+                        let value = match msg {
+                            Some(Ok(Message::Text(t))) => notification(&t),
+                            Some(Ok(Message::Binary(b))) => {
+                                // Sometimes servers send binary; try parse as utf8 json.
+                                match String::from_utf8(b) {
+                                    Ok(s) => notification(&s),
+                                    Err(_) => None,
+                                }
+                            }
+                            Some(Ok(Message::Ping(payload))) => {
+                                if let Err(e) = write.send(Message::Pong(payload)).await {
+                                    error!("WS pong failed: {e}");
+                                    return None;
+                                }
+                                None
+                            }
+                            Some(Ok(Message::Close(_))) => {
+                                error!("WS closed by server");
+                                return None;
+                            }
+                            Some(Err(e)) => {
+                                error!("WS error: {e}");
+                                return None;
+                            }
+                            None => {
+                                error!("WS stream ended");
+                                return None;
+                            }
+                            Some(Ok(_)) => None,
+                        };
+                        if let Some(v) = value {
+                            return Some((v, (write, read, ping, last_seen)));
+                        }
+                    }
                 }
             }
-            Ok(Message::Ping(_)) | Ok(Message::Pong(_)) => None,
-            Ok(Message::Close(_)) => {
-                error!("WS closed by server");
-                None
-            }
-            Err(e) => {
-                error!("WS error: {e}");
-                None
+        },
+    );
+
+    Ok(stream.boxed())
+}
+
+/// Opens a WS connection to `ws_endpoint`, through `proxy` when one is configured.
+async fn connect(ws_endpoint: &str, proxy: Option<&ProxyConfig>) -> BotResult<WebSocketStream<MaybeTlsStream<TcpStream>>> {
+    let url = Url::parse(ws_endpoint).map_err(|e| BotError::WsDisconnected(format!("invalid endpoint: {e}")))?;
+    let host = url
+        .host_str()
+        .ok_or_else(|| BotError::WsDisconnected("endpoint has no host".to_string()))?
+        .to_string();
+    let port = url.port_or_known_default().unwrap_or(443);
+    let tcp = connect_tcp(proxy, &host, port)
+        .await
+        .map_err(|e| BotError::WsDisconnected(e.to_string()))?;
+    let (ws_stream, _) = client_async_tls(url.as_str(), tcp)
+        .await
+        .map_err(|e| BotError::WsDisconnected(format!("handshake with {host}:{port} failed: {e}")))?;
+    Ok(ws_stream)
+}
+
+/// Parses one text frame; `None` (logged at debug) for non-json or non-notification messages.
+fn notification(text: &str) -> Option<TransactionNotification> {
+    let value = match serde_json::from_str::<serde_json::Value>(text) {
+        Ok(v) => v,
+        Err(e) => {
+            debug!("Non-json text msg: {e}");
+            return None;
+        }
+    };
+    let tx = TransactionNotification::from_ws(&value);
+    if tx.is_none() {
+        debug!("WS msg without a transaction notification: {value}");
+    }
+    tx
+}
+
+/// A change to the pool accounts `spawn_account_watch` is subscribed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolSubscription {
+    Subscribe(Pubkey),
+    Unsubscribe(Pubkey),
+}
+
+/// Watches pool accounts for `REALTIME_POSITION_MONITOR`: subscribes (`accountSubscribe`)
+/// to the pools received on `pools` and sends the pool on `changed` each time its account
+/// changes. A dropped connection is retried every 3s and every pool subscribed again. Runs
+/// until `pools` is closed.
+pub fn spawn_account_watch(
+    ws_endpoint: String,
+    proxy: Option<ProxyConfig>,
+    mut pools: mpsc::UnboundedReceiver<PoolSubscription>,
+    changed: mpsc::UnboundedSender<Pubkey>,
+) {
+    tokio::spawn(async move {
+        let mut watched = Vec::new();
+        loop {
+            match watch_accounts(&ws_endpoint, proxy.as_ref(), &mut watched, &mut pools, &changed).await {
+                Ok(()) => return,
+                Err(e) => warn!("Pool account watch dropped: {e}; reconnecting in 3s"),
             }
-            _ => None,
+            sleep(Duration::from_secs(3)).await;
         }
     });
+}
 
-    Ok(stream)
+/// One connection of the account watch; `Ok` once `pools` is closed.
+async fn watch_accounts(
+    ws_endpoint: &str,
+    proxy: Option<&ProxyConfig>,
+    watched: &mut Vec<Pubkey>,
+    pools: &mut mpsc::UnboundedReceiver<PoolSubscription>,
+    changed: &mpsc::UnboundedSender<Pubkey>,
+) -> Result<()> {
+    let (mut write, mut read) = connect(ws_endpoint, proxy).await?.split();
+    let mut next_request = 0u64;
+    let mut request = |method: &str, param: serde_json::Value| {
+        next_request += 1;
+        let sub = json!({ "jsonrpc": "2.0", "id": next_request, "method": method, "params": [param] });
+        (next_request, Message::Text(sub.to_string()))
+    };
+    let account = |pool: &Pubkey| json!(pool.to_string());
+    // Request id -> pool until acked, then subscription id -> pool
+    let mut pending: HashMap<u64, Pubkey> = HashMap::new();
+    let mut subscriptions: HashMap<u64, Pubkey> = HashMap::new();
+    for pool in watched.iter() {
+        let (id, frame) = request("accountSubscribe", account(pool));
+        write.send(frame).await?;
+        pending.insert(id, *pool);
+    }
+    if !watched.is_empty() {
+        info!("Watching {} pool accounts for position updates", watched.len());
+    }
+
+    let mut ping = interval(Duration::from_secs(10));
+    loop {
+        tokio::select! {
+            update = pools.recv() => match update {
+                None => return Ok(()),
+                Some(PoolSubscription::Subscribe(pool)) => {
+                    if !watched.contains(&pool) {
+                        watched.push(pool);
+                        let (id, frame) = request("accountSubscribe", account(&pool));
+                        write.send(frame).await?;
+                        pending.insert(id, pool);
+                    }
+                }
+                Some(PoolSubscription::Unsubscribe(pool)) => {
+                    watched.retain(|p| *p != pool);
+                    pending.retain(|_, p| *p != pool);
+                    let subscription = subscriptions.iter().find(|(_, p)| **p == pool).map(|(s, _)| *s);
+                    if let Some(subscription) = subscription {
+                        subscriptions.remove(&subscription);
+                        write.send(request("accountUnsubscribe", json!(subscription)).1).await?;
+                    }
+                }
+            },
+            _ = ping.tick() => write.send(Message::Ping(Vec::new())).await?,
+            msg = read.next() => match msg {
+                Some(Ok(Message::Text(text))) => match account_frame(&text) {
+                    Some(AccountFrame::Subscribed { request, subscription }) => {
+                        if let Some(pool) = pending.remove(&request) {
+                            subscriptions.insert(subscription, pool);
+                        }
+                    }
+                    Some(AccountFrame::Changed { subscription }) => {
+                        if let Some(pool) = subscriptions.get(&subscription) {
+                            let _ = changed.send(*pool);
+                        }
+                    }
+                    None => debug!("Unrecognised account watch message: {text}"),
+                },
+                Some(Ok(Message::Ping(payload))) => write.send(Message::Pong(payload)).await?,
+                Some(Ok(Message::Close(_))) | None => return Err(anyhow!("closed by server")),
+                Some(Err(e)) => return Err(e.into()),
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+/// An account watch frame: the ack of a subscription request, or a change of a
+/// subscribed account.
+#[derive(Debug, PartialEq, Eq)]
+enum AccountFrame {
+    Subscribed { request: u64, subscription: u64 },
+    Changed { subscription: u64 },
+}
+
+fn account_frame(text: &str) -> Option<AccountFrame> {
+    let value: serde_json::Value = serde_json::from_str(text).ok()?;
+    if value.get("method").and_then(|m| m.as_str()) == Some("accountNotification") {
+        let subscription = value.pointer("/params/subscription")?.as_u64()?;
+        return Some(AccountFrame::Changed { subscription });
+    }
+    Some(AccountFrame::Subscribed {
+        request: value.get("id")?.as_u64()?,
+        subscription: value.get("result")?.as_u64()?,
+    })
+}
+
+/// The WS endpoint in use: `RPC_WEBSOCKET_ENDPOINT`, or `RPC_WEBSOCKET_ENDPOINT_BACKUP`
+/// after `WS_FAILOVER_AFTER` (default 3) consecutive failures. A failure is a connect or
+/// subscribe error, or a connection that ends before delivering a notification. Failures
+/// on the backup alternate back to the primary the same way, and while on the backup the
+/// primary is tried again every `WS_PRIMARY_RETRY_SECS` (default 300). A connection is
+/// dropped after `WS_IDLE_TIMEOUT_SECS` (default 30) without any message.
+///
+/// Logs only say "primary"/"backup": the URLs usually carry an API key.
+#[derive(Debug, Clone)]
+pub struct WsEndpoints {
+    primary: String,
+    backup: Option<String>,
+    failover_after: u32,
+    primary_retry: Duration,
+    idle_timeout: Duration,
+    on_backup: bool,
+    failures: u32,
+    /// Set while on the backup: when to try the primary again.
+    retry_primary_at: Option<Instant>,
 }
 
-/// Small reconnect helper: tries to connect forever and returns a stream each time.
+impl WsEndpoints {
+    pub fn new(
+        primary: String,
+        backup: Option<String>,
+        failover_after: u32,
+        primary_retry: Duration,
+        idle_timeout: Duration,
+    ) -> Self {
+        Self {
+            primary,
+            backup,
+            failover_after: failover_after.max(1),
+            primary_retry,
+            idle_timeout,
+            on_backup: false,
+            failures: 0,
+            retry_primary_at: None,
+        }
+    }
+
+    pub fn from_env(primary: String) -> Self {
+        let backup = env_var_opt("RPC_WEBSOCKET_ENDPOINT_BACKUP").filter(|v| !v.trim().is_empty());
+        if backup.is_some() {
+            info!("Backup WS endpoint configured");
+        }
+        Self::new(
+            primary,
+            backup.map(|b| b.trim().to_string()),
+            env_u64("WS_FAILOVER_AFTER", 3) as u32,
+            Duration::from_secs(env_u64("WS_PRIMARY_RETRY_SECS", 300).max(1)),
+            Duration::from_secs(env_u64("WS_IDLE_TIMEOUT_SECS", 30)),
+        )
+    }
+
+    /// `RPC_WEBSOCKET_ENDPOINT`, for connections that do not fail over.
+    pub fn primary(&self) -> &str {
+        &self.primary
+    }
+
+    fn current(&self) -> &str {
+        match (&self.backup, self.on_backup) {
+            (Some(backup), true) => backup,
+            _ => &self.primary,
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        if self.on_backup { "backup" } else { "primary" }
+    }
+
+    fn record_success(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Counts a failure on the current endpoint; switches to the other one at the limit.
+    fn record_failure(&mut self) {
+        self.failures += 1;
+        if self.backup.is_none() || self.failures < self.failover_after {
+            return;
+        }
+        let from = self.label();
+        self.on_backup = !self.on_backup;
+        self.failures = 0;
+        self.retry_primary_at = self.on_backup.then(|| Instant::now() + self.primary_retry);
+        warn!("WS endpoint switched from {from} to {} after {} consecutive failures", self.label(), self.failover_after);
+    }
+
+    fn back_to_primary(&mut self) {
+        self.on_backup = false;
+        self.failures = 0;
+        self.retry_primary_at = None;
+        warn!("WS endpoint switched from backup back to primary");
+    }
+}
+
+/// Reconnecting stream: whenever the current connection ends (close, error, idle
+/// timeout) a new one is opened and subscribed, so the stream itself never ends.
+/// Fails over between the endpoints as `WsEndpoints` describes.
 /// (Used internally by engine.)
 pub async fn connect_forever(
-    ws_endpoint: String,
-    target_pubkey: String,
-) -> Result<impl futures_util::Stream<Item = serde_json::Value>> {
+    mut endpoints: WsEndpoints,
+    target_pubkeys: Vec<String>,
+    proxy: Option<ProxyConfig>,
+) -> Result<BoxStream<'static, TransactionNotification>> {
+    let first = connect_retrying(&mut endpoints, &target_pubkeys, proxy.as_ref()).await;
+    let stream = stream::unfold(
+        (first, endpoints, target_pubkeys, proxy, false),
+        |(mut current, mut endpoints, target_pubkeys, proxy, mut delivered)| async move {
+            loop {
+                let next = match endpoints.retry_primary_at {
+                    Some(at) => timeout_at(at, current.next()).await,
+                    None => Ok(current.next().await),
+                };
+                match next {
+                    Ok(Some(v)) => {
+                        endpoints.record_success();
+                        return Some((v, (current, endpoints, target_pubkeys, proxy, true)));
+                    }
+                    Ok(None) => {
+                        warn!("WS connection to {} endpoint lost; reconnecting", endpoints.label());
+                        if !delivered {
+                            endpoints.record_failure();
+                        }
+                        current = connect_retrying(&mut endpoints, &target_pubkeys, proxy.as_ref()).await;
+                        delivered = false;
+                    }
+                    // Time to see whether the primary is back; the backup stays up until it is
+                    Err(_) => match stream_transactions(&endpoints.primary, &target_pubkeys, proxy.as_ref(), endpoints.idle_timeout).await {
+                        Ok(primary) => {
+                            endpoints.back_to_primary();
+                            current = primary;
+                            delivered = false;
+                        }
+                        Err(e) => {
+                            debug!("Primary WS endpoint still unavailable: {e}");
+                            endpoints.retry_primary_at = Some(Instant::now() + endpoints.primary_retry);
+                        }
+                    },
+                }
+            }
+        },
+    );
+    Ok(stream.boxed())
+}
+
+/// Tries to connect every 3s until it succeeds, failing over between the endpoints.
+async fn connect_retrying(
+    endpoints: &mut WsEndpoints,
+    target_pubkeys: &[String],
+    proxy: Option<&ProxyConfig>,
+) -> BoxStream<'static, TransactionNotification> {
     loop {
-        match stream_transactions(&ws_endpoint, &target_pubkey).await {
-            Ok(s) => return Ok(s),
+        match stream_transactions(endpoints.current(), target_pubkeys, proxy, endpoints.idle_timeout).await {
+            Ok(s) => return s,
             Err(e) => {
-                error!("WS connect failed: {e}. Reconnecting in 3s...");
+                error!("WS connect to {} endpoint failed: {e}. Reconnecting in 3s...", endpoints.label());
+                endpoints.record_failure();
                 sleep(Duration::from_secs(3)).await;
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoints(backup: Option<&str>) -> WsEndpoints {
+        WsEndpoints::new(
+            "wss://primary".to_string(),
+            backup.map(str::to_string),
+            3,
+            Duration::from_secs(300),
+            Duration::from_secs(30),
+        )
+    }
+
+    #[test]
+    fn fails_over_to_backup_after_consecutive_failures() {
+        let mut ws = endpoints(Some("wss://backup"));
+        ws.record_failure();
+        ws.record_failure();
+        // A success in between resets the count
+        ws.record_success();
+        ws.record_failure();
+        ws.record_failure();
+        assert_eq!((ws.current(), ws.label()), ("wss://primary", "primary"));
+
+        ws.record_failure();
+        assert_eq!((ws.current(), ws.label()), ("wss://backup", "backup"));
+        assert!(ws.retry_primary_at.is_some());
+
+        ws.back_to_primary();
+        assert_eq!(ws.current(), "wss://primary");
+        assert!(ws.retry_primary_at.is_none());
+    }
+
+    #[test]
+    fn failing_backup_switches_back_to_primary() {
+        let mut ws = endpoints(Some("wss://backup"));
+        (0..3).for_each(|_| ws.record_failure());
+        assert_eq!(ws.current(), "wss://backup");
+        (0..3).for_each(|_| ws.record_failure());
+        assert_eq!(ws.current(), "wss://primary");
+        assert!(ws.retry_primary_at.is_none());
+    }
+
+    #[test]
+    fn without_backup_stays_on_primary() {
+        let mut ws = endpoints(None);
+        (0..10).for_each(|_| ws.record_failure());
+        assert_eq!(ws.current(), "wss://primary");
+    }
+
+    #[test]
+    fn account_frames_are_acks_or_changes() {
+        assert_eq!(
+            account_frame(r#"{"jsonrpc":"2.0","result":23784,"id":3}"#),
+            Some(AccountFrame::Subscribed { request: 3, subscription: 23784 })
+        );
+        let change = r#"{"jsonrpc":"2.0","method":"accountNotification","params":{"result":{"context":{"slot":5199307},"value":{"lamports":33594,"data":["","base64"]}},"subscription":23784}}"#;
+        assert_eq!(account_frame(change), Some(AccountFrame::Changed { subscription: 23784 }));
+        assert_eq!(account_frame(r#"{"jsonrpc":"2.0","error":{"code":-32602},"id":3}"#), None);
+        assert_eq!(account_frame("not json"), None);
+    }
+
+    #[tokio::test]
+    async fn account_watch_forwards_changes_of_subscribed_pools() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("ws://{}", listener.local_addr().unwrap());
+        let (pools_tx, pools_rx) = mpsc::unbounded_channel();
+        let (changed_tx, mut changed_rx) = mpsc::unbounded_channel();
+        spawn_account_watch(endpoint, None, pools_rx, changed_tx);
+        let pool = Pubkey::new_unique();
+        pools_tx.send(PoolSubscription::Subscribe(pool)).unwrap();
+
+        let (tcp, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(tcp).await.unwrap();
+        let request = next_request(&mut ws).await;
+        assert_eq!(request["method"], "accountSubscribe");
+        assert_eq!(request["params"][0], pool.to_string());
+
+        let ack = json!({ "jsonrpc": "2.0", "result": 42, "id": request["id"] });
+        ws.send(Message::Text(ack.to_string())).await.unwrap();
+        // Changes under an unknown subscription are dropped
+        for subscription in [7, 42] {
+            let change = json!({
+                "jsonrpc": "2.0",
+                "method": "accountNotification",
+                "params": { "result": { "context": { "slot": 1 }, "value": {} }, "subscription": subscription }
+            });
+            ws.send(Message::Text(change.to_string())).await.unwrap();
+        }
+        let changed = tokio::time::timeout(Duration::from_secs(5), changed_rx.recv()).await.unwrap();
+        assert_eq!(changed, Some(pool));
+        assert!(changed_rx.try_recv().is_err());
+
+        pools_tx.send(PoolSubscription::Unsubscribe(pool)).unwrap();
+        let request = next_request(&mut ws).await;
+        assert_eq!(request["method"], "accountUnsubscribe");
+        assert_eq!(request["params"], json!([42]));
+    }
+
+    async fn next_request(ws: &mut tokio_tungstenite::WebSocketStream<tokio::net::TcpStream>) -> serde_json::Value {
+        loop {
+            if let Message::Text(text) = ws.next().await.unwrap().unwrap() {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+}
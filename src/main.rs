@@ -1,11 +1,34 @@
+use ammalgram_assistant::common::config::Config;
 use ammalgram_assistant::common::logger::init_tracing;
+use ammalgram_assistant::common::metrics::{spawn_metrics_server, spawn_pushgateway};
+use ammalgram_assistant::common::proxy::{build_http_client, ProxyConfig};
+use ammalgram_assistant::common::utils::{env_u16, env_u64, env_var_opt};
 use ammalgram_assistant::engine::copy_trader::run_copy_trader;
 use anyhow::Result;
 use dotenvy::dotenv;
+use std::time::Duration;
+use tracing::info;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     dotenv().ok();
+    // Before logging starts, so the file can set RUST_LOG too
+    let config = Config::load()?;
     init_tracing()?;
-    run_copy_trader().await
+    if let Some(path) = &config.source {
+        info!("Config loaded from {} (environment variables take precedence)", path.display());
+    }
+    // Prometheus `/metrics` endpoint (0 = off)
+    let metrics_port = env_u16("METRICS_PORT", 0);
+    if metrics_port > 0 {
+        spawn_metrics_server(metrics_port).await?;
+    }
+    // Pushgateway, for deployments that cannot be scraped (unset = off)
+    if let Some(url) = env_var_opt("PROMETHEUS_PUSHGATEWAY_URL").filter(|u| !u.trim().is_empty()) {
+        let http = build_http_client(ProxyConfig::from_env()?.as_ref())?;
+        let job = env_var_opt("PROMETHEUS_PUSH_JOB").unwrap_or_else(|| "ammalgam".to_string());
+        let interval = Duration::from_secs(env_u64("PROMETHEUS_PUSH_INTERVAL_SECS", 15).max(1));
+        spawn_pushgateway(http, url.trim().to_string(), job, interval);
+    }
+    run_copy_trader(config).await
 }
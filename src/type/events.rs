@@ -1,5 +1,144 @@
-use serde::{Deserialize, Serialize};
-use solana_sdk::pubkey::Pubkey;
+use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
+use serde::{Deserialize, Deserializer, Serialize};
+use solana_sdk::{pubkey::Pubkey, transaction::VersionedTransaction};
+use std::str::FromStr;
+
+/// One `transactionNotification` from the WS stream. Every field is optional or defaults
+/// and unknown fields are ignored, so differences between providers never fail parsing.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct TransactionNotification {
+    pub signature: Option<String>,
+    pub slot: Option<u64>,
+    /// The raw transaction: `[data, "base64"]` or the json encoding, as sent.
+    pub transaction: serde_json::Value,
+    pub meta: Option<TxMeta>,
+}
+
+impl TransactionNotification {
+    /// Reads the notification out of a WS message. `None` for anything else (e.g. the
+    /// subscription ack). Handles both `result.{transaction, meta}` and Helius'
+    /// `result.transaction.{transaction, meta}` nesting.
+    pub fn from_ws(msg: &serde_json::Value) -> Option<Self> {
+        let result = msg.pointer("/params/result").or_else(|| msg.pointer("/result"))?;
+        let mut tx: Self = serde_json::from_value(result.clone()).ok()?;
+        if let Some(inner) = tx.transaction.get("transaction").cloned() {
+            if tx.meta.is_none() {
+                tx.meta = tx
+                    .transaction
+                    .get("meta")
+                    .and_then(|m| serde_json::from_value(m.clone()).ok());
+            }
+            tx.transaction = inner;
+        }
+        Some(tx)
+    }
+
+    /// The transaction decoded from the `[data, "base64"]` encoding we subscribe with,
+    /// legacy or v0. `None` for the json encoding or undecodable data.
+    pub fn decoded(&self) -> Option<VersionedTransaction> {
+        let encoded = self.transaction.get(0)?.as_str()?;
+        let bytes = B64.decode(encoded).ok()?;
+        bincode::deserialize(&bytes).ok()
+    }
+
+    /// Accounts that signed the transaction: the first `numRequiredSignatures` static keys
+    /// (the fee payer first). Signers are never loaded from lookup tables.
+    pub fn signers(&self) -> Option<Vec<Pubkey>> {
+        if let Some(decoded) = self.decoded() {
+            let count = decoded.message.header().num_required_signatures as usize;
+            return Some(decoded.message.static_account_keys().iter().take(count).copied().collect());
+        }
+        let message = self.transaction.get("message")?;
+        let count = message.pointer("/header/numRequiredSignatures")?.as_u64()? as usize;
+        let keys = message.get("accountKeys")?.as_array()?;
+        keys.iter()
+            .take(count)
+            .map(|k| k.as_str().or_else(|| k.get("pubkey")?.as_str()))
+            .map(|k| Pubkey::from_str(k?).ok())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct TxMeta {
+    /// Set when the transaction failed on-chain.
+    pub err: Option<serde_json::Value>,
+    pub fee: u64,
+    #[serde(deserialize_with = "null_as_default")]
+    pub pre_balances: Vec<u64>,
+    #[serde(deserialize_with = "null_as_default")]
+    pub post_balances: Vec<u64>,
+    #[serde(deserialize_with = "null_as_default")]
+    pub pre_token_balances: Vec<TokenBalance>,
+    #[serde(deserialize_with = "null_as_default")]
+    pub post_token_balances: Vec<TokenBalance>,
+    #[serde(deserialize_with = "null_as_default")]
+    pub log_messages: Vec<String>,
+    #[serde(deserialize_with = "null_as_default")]
+    pub inner_instructions: Vec<InnerInstructions>,
+    /// Accounts loaded from address lookup tables (v0 transactions).
+    #[serde(deserialize_with = "null_as_default")]
+    pub loaded_addresses: LoadedAddresses,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct TokenBalance {
+    pub account_index: u64,
+    pub mint: String,
+    pub owner: Option<String>,
+    /// SPL Token or Token-2022; missing from older RPC responses.
+    pub program_id: Option<String>,
+    pub ui_token_amount: UiTokenAmount,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct UiTokenAmount {
+    /// Raw amount in base units, as a decimal string.
+    pub amount: String,
+    pub decimals: u8,
+    /// Deprecated and sometimes null; `amount` is authoritative.
+    pub ui_amount: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InnerInstructions {
+    pub index: u64,
+    #[serde(deserialize_with = "null_as_default")]
+    pub instructions: Vec<CompiledIx>,
+}
+
+/// A json-encoded instruction: indexes into the account keys, base58 data.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CompiledIx {
+    pub program_id_index: Option<u64>,
+    #[serde(deserialize_with = "null_as_default")]
+    pub accounts: Vec<u64>,
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoadedAddresses {
+    #[serde(deserialize_with = "null_as_default")]
+    pub writable: Vec<String>,
+    #[serde(deserialize_with = "null_as_default")]
+    pub readonly: Vec<String>,
+}
+
+/// `#[serde(default)]` covers missing fields only; this treats an explicit `null` the same.
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
 
 /// What we decided from the observed target transaction.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -8,11 +147,39 @@ pub enum MirrorIntent {
     Buy {
         output_mint: Pubkey,
         max_input_sol: f64,
+        /// SOL the target spent on it (lamports net of the tx fee, plus wSOL), if known.
+        observed_input_sol: Option<f64>,
+        /// Token program owning the mint: SPL Token or Token-2022.
+        token_program: Pubkey,
     },
     /// Target likely sold a token into SOL (optional; disabled by default).
     Sell {
         input_mint: Pubkey,
-        // fraction 0..1 of our balance (not implemented in this minimal build)
-        _fraction: f64,
+        /// Fraction (0..=1) of its holding the target sold; we sell the same fraction of ours.
+        fraction: f64,
+    },
+    /// Target received a token without paying for it (an airdrop); watched, not bought.
+    Watch {
+        mint: Pubkey,
+        token_program: Pubkey,
     },
 }
+
+impl MirrorIntent {
+    /// The token being bought or sold.
+    pub fn mint(&self) -> &Pubkey {
+        match self {
+            Self::Buy { output_mint, .. } => output_mint,
+            Self::Sell { input_mint, .. } => input_mint,
+            Self::Watch { mint, .. } => mint,
+        }
+    }
+
+    pub fn direction(&self) -> &'static str {
+        match self {
+            Self::Buy { .. } => "buy",
+            Self::Sell { .. } => "sell",
+            Self::Watch { .. } => "watch",
+        }
+    }
+}